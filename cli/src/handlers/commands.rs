@@ -1,183 +1,1852 @@
-use aws_sdk_s3 as s3;
-use s3::Client;
+use std::collections::HashSet;
 use std::fs;
 use std::fs::File;
 use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process;
 
+use crate::utils::bisect::{next_bisect_step, BisectStep};
 use crate::utils::constants::{
-    MAIN_COMMITS_METADATA_FILE_PATH, REMOTE_REPOSITORY_REFERENCE_FILE_PATH, VSM_DIR,
+    CLONE_STATE_FILE_PATH, DATA_RELATIVE_PATH, DEFAULT_REMOTE, FETCH_DIR, FILES_HISTORY_DIR,
+    HEADS_REFS_PREFIX, HEAD_FILE_PATH, MAIN_COMMITS_METADATA_FILE_PATH, MERGE_STATE_FILE_PATH,
+    REBASE_TODO_FILE_PATH, REMOTES_REFS_PREFIX, REVERT_STATE_FILE_PATH, SYMBOLIC_REF_PREFIX,
+    TAGS_REFS_PREFIX, VSM_DIR,
 };
 use crate::utils::dates::get_current_formatted_date;
 use crate::utils::fs_provider::*;
-use crate::utils::s3_provider::*;
+use crate::utils::history_import::{align_with_remote, import_history};
+use crate::utils::history_paths::{history_dir_for_path, matches_pathspec, write_history_path};
+use crate::utils::lfs::{
+    is_lfs_path, lfs_blob_key, lfs_blob_path, list_lfs_patterns, parse_lfs_pointer,
+};
+use crate::utils::merge::{apply_merge_changes, has_conflict_markers, merge_trees};
+use crate::utils::refs::{
+    append_reflog, branch_ref_name, current_branch, detach_head, head_commit_id, init_refs,
+    list_branches, list_ref_names, read_ref, remote_ref_name, rename_remote_refs,
+    set_head_to_branch, tag_ref_name, update_head, update_ref, write_ref,
+};
+use crate::utils::remote::{
+    download_history, lock_remote, open_remote, read_remote_ref, read_remote_string, unlock_remote,
+    upload_history, with_retries, RemoteBackend,
+};
+use crate::utils::revisions::{merge_base, resolve_range, resolve_revision};
+use crate::utils::shallow::{
+    download_partial_history, is_shallow, remote_head_branch, update_shallow_commits,
+};
 use crate::utils::types::Commit;
 use crate::utils::types::CommitMetadata;
+use crate::utils::types::{
+    BisectMark, BisectState, CloneState, MergeState, RemoteConfig, RemotesConfig, ResetMode,
+    RevertState, SequencerOperation, SequencerState, TodoAction, TodoItem, Tree,
+};
 use crate::utils::*;
 
 pub fn init() -> std::io::Result<()> {
     check_if_initialized()?;
 
     fs::create_dir(VSM_DIR)?;
+    fs::create_dir(FILES_HISTORY_DIR)?;
     File::create(MAIN_COMMITS_METADATA_FILE_PATH)?.write_all(b"[]")?;
+    init_refs()?;
 
     Ok(())
 }
 
 pub fn commit(description: &str) -> std::io::Result<()> {
-    check_if_initialized()?;
+    open_repository()?;
+
+    let parents: Vec<String> = head_commit_id()?.into_iter().collect();
+    let reflog_action = if parents.is_empty() {
+        "commit (initial)"
+    } else {
+        "commit"
+    };
+    create_commit(description, &get_author(), parents, reflog_action)?;
+
+    Ok(())
+}
+
+// Replaces HEAD by a commit of the current working tree with the same parents,
+// keeping its message unless a new one is given.
+pub fn commit_amend(description: Option<&str>, force: bool) -> std::io::Result<()> {
+    open_repository()?;
+
+    ensure_no_operation_in_progress()?;
+    let head_commit = head_commit()?;
+
+    if !force {
+        let commits = list_commits(MAIN_COMMITS_METADATA_FILE_PATH)?;
+        for remote_ref in list_ref_names(REMOTES_REFS_PREFIX)? {
+            let remote_commit_id = match read_ref(&(REMOTES_REFS_PREFIX.to_owned() + &remote_ref))?
+            {
+                Some(remote_commit_id) => remote_commit_id,
+                None => continue,
+            };
+            if is_ancestor(&commits, &head_commit.commit_id, &remote_commit_id) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::PermissionDenied,
+                    format!(
+                        "Commit {} is already pushed to {}, use --force to amend it anyway",
+                        head_commit.commit_id, remote_ref
+                    ),
+                ));
+            }
+        }
+    }
 
+    amend_head(
+        description.unwrap_or(&head_commit.description),
+        "commit (amend)",
+    )?;
+
+    Ok(())
+}
+
+fn create_commit(
+    description: &str,
+    author: &str,
+    parents: Vec<String>,
+    reflog_action: &str,
+) -> std::io::Result<String> {
     let commit_id: String = generate_commit_id();
     let files_to_ignore = list_files_ignore();
     let file_paths = get_file_paths_recursively(None, Some(&files_to_ignore));
+    let lfs_patterns = list_lfs_patterns();
     let formatted_date = get_current_formatted_date();
-
-    add_root_commit_metadata(Commit {
-        date: formatted_date.clone(),
+    let commit = Commit {
+        date: formatted_date,
         description: description.to_owned(),
-        commit_id: commit_id.clone(),
-    })?;
+        commit_id,
+        parents,
+        author: author.to_owned(),
+    };
+
+    add_root_commit_metadata(commit.clone())?;
 
     for file_path in file_paths {
-        let file_name_for_history = file_path.clone().to_str().unwrap().replace("/", "_");
-        let last_committed_file_path = VSM_DIR.to_owned() + "/" + file_name_for_history.as_str();
-        let file_contents = fs::read_to_string(file_path.clone())?;
+        let last_committed_file_path = history_dir_for_path(&file_path);
 
         let file_metadata_string_result: Result<Vec<CommitMetadata>, std::io::Error> =
             commits_metadata(&last_committed_file_path);
         let mut commits_metadata = match file_metadata_string_result {
             Ok(res) => res,
             Err(_) => {
-                fs::create_dir(&last_committed_file_path)?;
-                write_to_commit_metadata_file(
-                    &last_committed_file_path,
-                    vec![CommitMetadata {
-                        date: formatted_date.clone(),
-                        description: description.to_owned(),
-                        commit_id: commit_id.clone(),
-                        pointer_to_data: 0,
-                        size: file_contents.len() as i32,
-                    }],
-                )?;
-
-                write_to_data_file(&last_committed_file_path, &file_contents, false)?;
-                continue;
+                fs::create_dir_all(&last_committed_file_path)?;
+                write_history_path(&last_committed_file_path, &file_path)?;
+                Vec::new()
             }
         };
-        let last_committed_metadata = commits_metadata.last().unwrap();
-        let last_committed_file_pointer = last_committed_metadata.pointer_to_data;
-        let last_committed_file_size = last_committed_metadata.size;
-
-        let last_committed_file_contents = read_part_of_file(
-            &(last_committed_file_path.clone() + "/data.bin"),
-            last_committed_file_pointer as u64,
-            last_committed_file_size as usize,
-        )?;
-
-        if &last_committed_file_contents == &file_contents {
-            commits_metadata.push(CommitMetadata {
-                date: formatted_date.clone(),
-                description: description.to_owned(),
-                commit_id: commit_id.clone(),
-                pointer_to_data: last_committed_file_pointer + last_committed_file_size,
-                size: file_contents.len() as i32,
-            });
 
-            write_to_commit_metadata_file(&last_committed_file_path, commits_metadata)?;
-            write_to_data_file(&last_committed_file_path, &file_contents, true)?;
-        }
+        let commit_metadata = if is_lfs_path(&file_path, &lfs_patterns) {
+            store_lfs_file_contents(
+                &file_path,
+                &last_committed_file_path,
+                commits_metadata.last(),
+                &commit,
+            )?
+        } else {
+            store_file_contents(
+                &file_path,
+                &last_committed_file_path,
+                commits_metadata.last(),
+                &commit,
+            )?
+        };
+        commits_metadata.push(commit_metadata);
+        write_to_commit_metadata_file(&last_committed_file_path, commits_metadata)?;
     }
 
-    Ok(())
+    let reason = format!(
+        "{}: {}",
+        reflog_action,
+        description.lines().next().unwrap_or_default()
+    );
+    update_head(&commit.commit_id, &reason)?;
+
+    Ok(commit.commit_id)
 }
 
 pub fn view(branch_id: &str) -> std::io::Result<()> {
-    check_if_initialized()?;
+    open_repository()?;
 
+    let commits = list_commits(MAIN_COMMITS_METADATA_FILE_PATH)?;
+    let commit_id = resolve_revision(&commits, branch_id)?;
     let files_to_ignore = list_files_ignore();
     delete_contents_of_directory(".", Some(&files_to_ignore))?;
-    load_commit(branch_id)?;
+    load_commit(&commit_id)?;
+
+    Ok(())
+}
+
+fn check_remote_name(name: &str) -> std::io::Result<()> {
+    if name.is_empty() || name.contains('/') || name.starts_with('.') {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{} is not a valid remote name", name),
+        ));
+    }
 
     Ok(())
 }
 
-pub fn set_remote(bucket_name: &str) -> std::io::Result<()> {
-    if File::open(REMOTE_REPOSITORY_REFERENCE_FILE_PATH).is_ok() {
+fn remote_not_found(name: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        format!("No remote named {}", name),
+    )
+}
+
+pub fn remote_add(name: &str, remote_config: &RemoteConfig) -> std::io::Result<()> {
+    open_repository()?;
+    check_remote_name(name)?;
+
+    let mut remotes_config = read_remotes_config()?;
+    if remotes_config.remotes.contains_key(name) {
         return Err(std::io::Error::new(
             std::io::ErrorKind::AlreadyExists,
-            "Remote already set",
+            format!("Remote {} already exists", name),
         ));
     }
-    File::create(REMOTE_REPOSITORY_REFERENCE_FILE_PATH)?.write_all(bucket_name.as_bytes())
+    remotes_config
+        .remotes
+        .insert(name.to_owned(), remote_config.clone());
+    write_remotes_config(&remotes_config)
 }
 
-pub async fn clone(client: &Client, bucket_name: &str) -> std::io::Result<()> {
-    let objects = client
-        .list_objects_v2()
-        .bucket(bucket_name)
-        .send()
-        .await
-        .unwrap();
-
-    for obj in objects.contents() {
-        create_file_from_s3object(
-            client,
-            &obj.key().unwrap(),
-            bucket_name,
-            &obj.key().unwrap(),
+// Branches that pushed to the removed remote by default fall back to the
+// default remote selection.
+pub fn remote_remove(name: &str) -> std::io::Result<()> {
+    open_repository()?;
+
+    let mut remotes_config = read_remotes_config()?;
+    if remotes_config.remotes.remove(name).is_none() {
+        return Err(remote_not_found(name));
+    }
+    remotes_config
+        .branch_remotes
+        .retain(|_, branch_remote| branch_remote != name);
+    write_remotes_config(&remotes_config)?;
+    rename_remote_refs(name, None)
+}
+
+pub fn remote_rename(name: &str, new_name: &str) -> std::io::Result<()> {
+    open_repository()?;
+    check_remote_name(new_name)?;
+
+    let mut remotes_config = read_remotes_config()?;
+    if remotes_config.remotes.contains_key(new_name) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("Remote {} already exists", new_name),
+        ));
+    }
+    let remote_config = remotes_config
+        .remotes
+        .remove(name)
+        .ok_or_else(|| remote_not_found(name))?;
+    remotes_config
+        .remotes
+        .insert(new_name.to_owned(), remote_config);
+    for branch_remote in remotes_config.branch_remotes.values_mut() {
+        if branch_remote == name {
+            *branch_remote = new_name.to_owned();
+        }
+    }
+    write_remotes_config(&remotes_config)?;
+    rename_remote_refs(name, Some(new_name))
+}
+
+pub fn set_remote(remote_config: &RemoteConfig) -> std::io::Result<()> {
+    remote_add(DEFAULT_REMOTE, remote_config)
+}
+
+// Without an explicit name, the remote recorded for the current branch is
+// used, then origin, then the only remote if there is just one.
+fn select_remote(name: Option<&str>) -> std::io::Result<(String, RemoteConfig)> {
+    let remotes_config = read_remotes_config()?;
+    let name = match name {
+        Some(name) => name.to_owned(),
+        None => {
+            let branch_remote = match current_branch()? {
+                Some(branch) => remotes_config.branch_remotes.get(&branch).cloned(),
+                None => None,
+            };
+            match branch_remote {
+                Some(branch_remote) => branch_remote,
+                None if remotes_config.remotes.contains_key(DEFAULT_REMOTE) => {
+                    DEFAULT_REMOTE.to_owned()
+                }
+                None if remotes_config.remotes.len() == 1 => {
+                    remotes_config.remotes.keys().next().unwrap().clone()
+                }
+                None if remotes_config.remotes.is_empty() => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::NotFound,
+                        "No remote set",
+                    ))
+                }
+                None => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        "Several remotes are set, give the name of the remote",
+                    ))
+                }
+            }
+        }
+    };
+    let remote_config = remotes_config
+        .remotes
+        .get(&name)
+        .cloned()
+        .ok_or_else(|| remote_not_found(&name))?;
+
+    Ok((name, remote_config))
+}
+
+// The remote is recorded before anything is downloaded, so that an
+// interrupted clone can be resumed with clone --continue.
+pub async fn clone(
+    remote_config: &RemoteConfig,
+    fetch_lfs: bool,
+    clone_state: &CloneState,
+) -> std::io::Result<()> {
+    if Path::new(VSM_DIR).exists() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            "A repository already exists here, run clone --continue to resume an interrupted clone",
+        ));
+    }
+    // Settings that cannot open the remote, such as a wrong passphrase, are
+    // reported before anything is written
+    let remote = open_remote(remote_config).await?;
+
+    // Single-branch clones keep fetching only their branch afterwards, and
+    // clones without LFS blobs keep skipping them
+    let mut remote_config = remote_config.clone();
+    remote_config.no_lfs = !fetch_lfs;
+    if clone_state.single_branch {
+        let branch = match &clone_state.branch {
+            Some(branch) => branch.clone(),
+            None => remote_head_branch(&*remote).await?.ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "The remote HEAD is not on a branch, choose one with --branch",
+                )
+            })?,
+        };
+        remote_config.branches = vec![branch];
+    }
+
+    let mut remotes_config = RemotesConfig::default();
+    remotes_config
+        .remotes
+        .insert(DEFAULT_REMOTE.to_owned(), remote_config.clone());
+    write_remotes_config(&remotes_config)?;
+    write_clone_state(clone_state)?;
+
+    finish_clone(&*remote, &remote_config, clone_state).await
+}
+
+// Files already downloaded completely are kept, the others are downloaded
+// again.
+pub async fn clone_continue(fetch_lfs: bool) -> std::io::Result<()> {
+    let clone_state = read_clone_state()?;
+    let mut remotes_config = read_remotes_config()?;
+    let mut remote_config = remotes_config
+        .remotes
+        .get(DEFAULT_REMOTE)
+        .cloned()
+        .ok_or_else(|| remote_not_found(DEFAULT_REMOTE))?;
+    if !fetch_lfs && !remote_config.no_lfs {
+        remote_config.no_lfs = true;
+        remotes_config
+            .remotes
+            .insert(DEFAULT_REMOTE.to_owned(), remote_config.clone());
+        write_remotes_config(&remotes_config)?;
+    }
+    let remote = open_remote(&remote_config).await?;
+
+    finish_clone(&*remote, &remote_config, &clone_state).await
+}
+
+async fn finish_clone(
+    remote: &dyn RemoteBackend,
+    remote_config: &RemoteConfig,
+    clone_state: &CloneState,
+) -> std::io::Result<()> {
+    if clone_state.depth.is_some() || !remote_config.branches.is_empty() {
+        download_partial_history(
+            remote,
+            VSM_DIR,
+            &remote_config.branches,
+            clone_state.depth,
+            &HashSet::new(),
+            false,
+            !remote_config.no_lfs,
         )
         .await?;
+        update_shallow_commits()?;
+    } else {
+        download_history(remote, VSM_DIR, !remote_config.no_lfs).await?;
+    }
+    migrate_repository()?;
+    if let Some(branch) = &clone_state.branch {
+        if read_ref(&branch_ref_name(branch))?.is_none() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("The remote has no branch {}", branch),
+            ));
+        }
+        set_head_to_branch(branch)?;
+    }
+    verify_history()?;
+
+    // Remote-tracking refs record where the remote branches were when last
+    // synced, which tells which local commits are already published.
+    let reason = format!("clone: from {}", remote_config.url);
+    for branch in list_branches()? {
+        if let Some(commit_id) = read_ref(&branch_ref_name(&branch))? {
+            append_reflog(&branch_ref_name(&branch), None, &commit_id, &reason)?;
+            update_ref(
+                &remote_ref_name(DEFAULT_REMOTE, &branch),
+                &commit_id,
+                &reason,
+            )?;
+        }
+    }
+    if let Some(head_commit_id) = head_commit_id()? {
+        append_reflog("HEAD", None, &head_commit_id, &reason)?;
+        load_commit(&head_commit_id)?;
+    }
+
+    fs::remove_file(CLONE_STATE_FILE_PATH)
+}
+
+// Downloads the commits missing locally next to the local history, with only
+// the file histories, chunks and LFS blobs they need, imports them and then
+// moves the remote-tracking refs. Local branches and the working tree are left
+// untouched. Unshallow also downloads the commits a shallow clone left out
+// below the ones the repository already has.
+async fn fetch_remote(
+    remote_name: &str,
+    remote_config: &RemoteConfig,
+    unshallow: bool,
+) -> std::io::Result<()> {
+    let shallow = is_shallow();
+    if unshallow && !shallow {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "The repository is not shallow",
+        ));
+    }
+    let remote = open_remote(remote_config).await?;
+
+    match fs::remove_dir_all(FETCH_DIR) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let known: HashSet<String> = list_commits(MAIN_COMMITS_METADATA_FILE_PATH)?
+        .into_iter()
+        .map(|commit| commit.commit_id)
+        .collect();
+    download_partial_history(
+        &*remote,
+        FETCH_DIR,
+        &remote_config.branches,
+        None,
+        &known,
+        unshallow,
+        !remote_config.no_lfs,
+    )
+    .await?;
+    import_history(FETCH_DIR)?;
+    if shallow {
+        update_shallow_commits()?;
+    }
+
+    let reason = format!("fetch: from {}", remote_config.url);
+    let fetched_refs_dir = FETCH_DIR.to_owned() + "/" + HEADS_REFS_PREFIX;
+    for ref_path in get_file_paths_recursively(Some(Path::new(&fetched_refs_dir)), None) {
+        let remote_branch = ref_path.strip_prefix(&fetched_refs_dir).unwrap();
+        let tracking_ref = remote_ref_name(remote_name, &remote_branch.to_string_lossy());
+        let commit_id = fs::read_to_string(&ref_path)?.trim().to_owned();
+        let old_commit_id = read_ref(&tracking_ref)?;
+        if old_commit_id.as_deref() == Some(commit_id.as_str()) {
+            continue;
+        }
+
+        update_ref(&tracking_ref, &commit_id, &reason)?;
+        let short_ref = tracking_ref.strip_prefix(REMOTES_REFS_PREFIX).unwrap();
+        match old_commit_id {
+            Some(old_commit_id) => println!("{}..{} {}", old_commit_id, commit_id, short_ref),
+            None => println!("* {} {} (new branch)", commit_id, short_ref),
+        }
+    }
+
+    fs::remove_dir_all(FETCH_DIR)
+}
+
+pub async fn fetch(remote_name: Option<&str>, unshallow: bool) -> std::io::Result<()> {
+    open_repository()?;
+
+    let (remote_name, remote_config) = select_remote(remote_name)?;
+    fetch_remote(&remote_name, &remote_config, unshallow).await
+}
+
+// The remote branch is merged into the current branch, or the local commits
+// are replayed on top of it with rebase, so unpushed commits are kept either
+// way.
+pub async fn pull(remote_name: Option<&str>, rebase_local: bool) -> std::io::Result<()> {
+    open_repository()?;
+
+    let (remote_name, remote_config) = select_remote(remote_name)?;
+    let branch = current_branch()?.ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, "HEAD is not on a branch")
+    })?;
+    ensure_no_operation_in_progress()?;
+    ensure_clean_working_tree(&head_tree()?, "pulling")?;
+
+    fetch_remote(&remote_name, &remote_config, false).await?;
+
+    let remote_head = match read_ref(&remote_ref_name(&remote_name, &branch))? {
+        Some(remote_head) => remote_head,
+        None => {
+            println!("The remote has no branch {}", branch);
+            return Ok(());
+        }
+    };
+    let upstream = remote_name + "/" + &branch;
+    if rebase_local && head_commit_id()?.is_some() {
+        return rebase(&upstream, false);
     }
 
     let commits = list_commits(MAIN_COMMITS_METADATA_FILE_PATH)?;
-    let last_commit = commits.last().unwrap();
-    let last_commit_id = last_commit.commit_id.clone();
-    load_commit(&last_commit_id)?;
+    merge_commit(&commits, &remote_head, &upstream)
+}
+
+// Remotes never pushed to have no commits at all.
+async fn read_remote_commits(remote: &dyn RemoteBackend) -> std::io::Result<Vec<Commit>> {
+    let remote_commits_string =
+        match read_remote_string(remote, MAIN_COMMITS_METADATA_FILE_PATH).await {
+            Ok(remote_commits_string) => remote_commits_string,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+    Ok(serde_json::from_str(&remote_commits_string)?)
+}
+
+// Remotes pushed before refs existed only have the linear commits list. Once
+// a remote has branches, a branch missing from it is a new one.
+async fn legacy_remote_head_commit_id(
+    remote: &dyn RemoteBackend,
+    remote_commits: &[Commit],
+) -> std::io::Result<Option<String>> {
+    let heads_prefix = VSM_DIR.to_owned() + "/" + HEADS_REFS_PREFIX;
+    if !remote.list(&heads_prefix).await?.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(remote_commits.last().map(|commit| commit.commit_id.clone()))
+}
+
+// Only fast-forwards are pushed, unless force_with_lease is given: the remote
+// branch may then be rewritten as long as it still points at the expected
+// commit, by default where the remote-tracking branch says it was last seen.
+async fn push_branch(
+    remote: &dyn RemoteBackend,
+    remote_name: &str,
+    branch: &str,
+    local_head: &str,
+    force_with_lease: Option<Option<&str>>,
+) -> std::io::Result<()> {
+    let local_commits = list_commits(MAIN_COMMITS_METADATA_FILE_PATH)?;
+    let ref_key = VSM_DIR.to_owned() + "/" + &branch_ref_name(branch);
+    let remote_commits = read_remote_commits(remote).await?;
+    let remote_ref = read_remote_ref(remote, &ref_key).await?;
+    let remote_head = match &remote_ref {
+        Some(remote_ref) => Some(remote_ref.clone()),
+        None => legacy_remote_head_commit_id(remote, &remote_commits).await?,
+    };
+    if remote_head.as_deref() == Some(local_head) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            "Nothing to push",
+        ));
+    }
+
+    match force_with_lease {
+        Some(expected_revision) => {
+            let expected_head = match expected_revision {
+                // The expected commit may not have been fetched, in which case
+                // its full id is compared as is
+                Some(expected_revision) => Some(
+                    resolve_revision(&local_commits, expected_revision)
+                        .unwrap_or_else(|_| expected_revision.to_owned()),
+                ),
+                None => read_ref(&remote_ref_name(remote_name, branch))?,
+            };
+            if remote_head != expected_head {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::PermissionDenied,
+                    "The remote branch is not where it was expected, fetch and check the changes first",
+                ));
+            }
+        }
+        None => {
+            if let Some(remote_head) = &remote_head {
+                if !is_ancestor(&local_commits, remote_head, local_head) {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::PermissionDenied,
+                        "Remote history is ahead of local history, pull the changes first",
+                    ));
+                }
+            }
+        }
+    }
+
+    // The commits list is shared by every branch on the remote, so the commits
+    // of branches this clone does not fetch are kept in the pushed one
+    let local_commit_ids: HashSet<&str> = local_commits
+        .iter()
+        .map(|commit| commit.commit_id.as_str())
+        .collect();
+    let remote_only_commits: Vec<&Commit> = remote_commits
+        .iter()
+        .filter(|commit| !local_commit_ids.contains(commit.commit_id.as_str()))
+        .collect();
+
+    // Every remote entry must stay where it is in the data files replacing the
+    // remote ones
+    align_with_remote(remote).await?;
+
+    // The pushed branch is only moved once everything it references is
+    // uploaded, and the backend only moves it if it was not changed since it
+    // was read. Other local branches are left out, as they may be behind
+    // their remote counterparts, and the HEAD and tags are written below.
+    let tags = list_ref_names(TAGS_REFS_PREFIX)?;
+    let mut skipped_keys: Vec<String> = list_branches()?
+        .iter()
+        .map(|branch| VSM_DIR.to_owned() + "/" + &branch_ref_name(branch))
+        .collect();
+    skipped_keys.extend(
+        tags.iter()
+            .map(|tag| VSM_DIR.to_owned() + "/" + &tag_ref_name(tag)),
+    );
+    skipped_keys.push(HEAD_FILE_PATH.to_owned());
+    if !remote_only_commits.is_empty() {
+        skipped_keys.push(MAIN_COMMITS_METADATA_FILE_PATH.to_owned());
+    }
+    upload_history(remote, VSM_DIR, &skipped_keys).await?;
+    if !remote_only_commits.is_empty() {
+        let pushed_commits: Vec<&Commit> =
+            local_commits.iter().chain(remote_only_commits).collect();
+        let pushed_commits_path = FETCH_DIR.to_owned()
+            + MAIN_COMMITS_METADATA_FILE_PATH
+                .strip_prefix(VSM_DIR)
+                .unwrap();
+        fs::create_dir_all(FETCH_DIR)?;
+        fs::write(
+            &pushed_commits_path,
+            serde_json::to_string(&pushed_commits)?,
+        )?;
+        with_retries(|| {
+            remote.put(
+                MAIN_COMMITS_METADATA_FILE_PATH,
+                Path::new(&pushed_commits_path),
+            )
+        })
+        .await?;
+        fs::remove_dir_all(FETCH_DIR)?;
+    }
+    remote
+        .update_ref(
+            &ref_key,
+            remote_ref.as_ref().map(|remote_ref| remote_ref.as_bytes()),
+            local_head.as_bytes(),
+        )
+        .await?;
+
+    // The remote HEAD tells clones which branch to check out, it is set by the
+    // first push and left alone afterwards
+    let head = SYMBOLIC_REF_PREFIX.to_owned() + &branch_ref_name(branch);
+    match remote
+        .update_ref(HEAD_FILE_PATH, None, head.as_bytes())
+        .await
+    {
+        Err(e)
+            if e.kind() == std::io::ErrorKind::PermissionDenied
+                || e.kind() == std::io::ErrorKind::AlreadyExists => {}
+        result => result?,
+    }
+
+    // Tags are only created on the remote, moving one takes a forced push
+    for tag in tags {
+        let tag_ref = tag_ref_name(&tag);
+        let commit_id = match read_ref(&tag_ref)? {
+            Some(commit_id) => commit_id,
+            None => continue,
+        };
+        let tag_key = VSM_DIR.to_owned() + "/" + &tag_ref;
+        match read_remote_ref(remote, &tag_key).await? {
+            Some(remote_commit_id) if remote_commit_id == commit_id => {}
+            Some(remote_commit_id) if force_with_lease.is_none() => println!(
+                "Tag {} is at {} on the remote, push with --force-with-lease to move it",
+                tag, remote_commit_id
+            ),
+            remote_commit_id => {
+                remote
+                    .update_ref(
+                        &tag_key,
+                        remote_commit_id
+                            .as_ref()
+                            .map(|commit_id| commit_id.as_bytes()),
+                        commit_id.as_bytes(),
+                    )
+                    .await?
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// With set_upstream, the remote becomes the default one of the branch for
+// later pushes and pulls.
+pub async fn push(
+    remote_name: Option<&str>,
+    set_upstream: bool,
+    force_with_lease: Option<Option<&str>>,
+) -> std::io::Result<()> {
+    open_repository()?;
+
+    // The remote keeps a single commits list, which a shallow history would
+    // replace with its truncated one
+    if is_shallow() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Cannot push from a shallow repository, run fetch --unshallow first",
+        ));
+    }
+
+    let (remote_name, remote_config) = select_remote(remote_name)?;
+    let remote = open_remote(&remote_config).await?;
+    let branch = current_branch()?.ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, "HEAD is not on a branch")
+    })?;
+    let local_head = head_commit_id()?.ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::PermissionDenied, "Nothing to push")
+    })?;
+
+    let lock = lock_remote(&*remote, &get_author()).await?;
+    let result = push_branch(
+        &*remote,
+        &remote_name,
+        &branch,
+        &local_head,
+        force_with_lease,
+    )
+    .await;
+    // The lock expires anyway, so failing to release it does not fail the push
+    if let Err(e) = unlock_remote(&*remote, &lock).await {
+        eprintln!("Could not release the push lock: {}", e);
+    }
+    result?;
+
+    update_ref(
+        &remote_ref_name(&remote_name, &branch),
+        &local_head,
+        "update by push",
+    )?;
+
+    if set_upstream {
+        let mut remotes_config = read_remotes_config()?;
+        remotes_config.branch_remotes.insert(branch, remote_name);
+        write_remotes_config(&remotes_config)?;
+    }
+
+    Ok(())
+}
+
+pub fn reset(revision: &str, mode: ResetMode) -> std::io::Result<()> {
+    open_repository()?;
+
+    let commits = list_commits(MAIN_COMMITS_METADATA_FILE_PATH)?;
+    let commit_id = resolve_revision(&commits, revision)?;
+    update_head(&commit_id, &format!("reset: moving to {}", revision))?;
+
+    // There is no staging area, so a mixed reset leaves everything but HEAD as is
+    if mode == ResetMode::Hard {
+        let files_to_ignore = list_files_ignore();
+        delete_contents_of_directory(".", Some(&files_to_ignore))?;
+        load_commit(&commit_id)?;
+    }
+
+    Ok(())
+}
+
+// Only the given paths are brought back from the source commit, leaving HEAD and
+// the rest of the working tree alone. Committed files the source does not have
+// are removed, as checking the source out would.
+pub fn restore(source: &str, pathspecs: &[String], staged: bool) -> std::io::Result<()> {
+    open_repository()?;
+
+    if staged {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "There is no staging index to restore, commits are made from the working tree",
+        ));
+    }
+
+    let commits = list_commits(MAIN_COMMITS_METADATA_FILE_PATH)?;
+    let commit_id = resolve_revision(&commits, source)?;
+    let source_tree = load_commit_tree(&commit_id)?;
+    let head_tree = head_tree()?;
+
+    let mut restored_paths = Vec::new();
+    let mut removed_paths = Vec::new();
+    for pathspec in pathspecs {
+        let restored_count = restored_paths.len();
+        let removed_count = removed_paths.len();
+        restored_paths.extend(
+            source_tree
+                .keys()
+                .filter(|path| matches_pathspec(path, pathspec)),
+        );
+        removed_paths.extend(
+            head_tree.keys().filter(|path| {
+                !source_tree.contains_key(*path) && matches_pathspec(path, pathspec)
+            }),
+        );
+
+        if restored_paths.len() == restored_count && removed_paths.len() == removed_count {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("Pathspec {} did not match any file in {}", pathspec, source),
+            ));
+        }
+    }
+
+    for path in restored_paths {
+        let entry = &source_tree[path];
+        restore_file(&entry.history_dir, &entry.metadata, path)?;
+    }
+    for path in removed_paths {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
 
     Ok(())
 }
 
-pub async fn pull(client: &Client) -> std::io::Result<()> {
-    let bucket_name = fs::read_to_string(REMOTE_REPOSITORY_REFERENCE_FILE_PATH)?;
+pub fn revert(revision: &str) -> std::io::Result<()> {
+    open_repository()?;
+
+    ensure_no_operation_in_progress()?;
+
+    let commits = list_commits(MAIN_COMMITS_METADATA_FILE_PATH)?;
+    let commit_id = &resolve_revision(&commits, revision)?;
+    let reverted_commit = find_commit(&commits, commit_id)?;
+    let head_commit_id = head_commit_id()?
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "Nothing to revert"))?;
+
+    let head_tree = load_commit_tree(&head_commit_id)?;
+    ensure_clean_working_tree(&head_tree, "reverting")?;
+
+    let reverted_tree = load_commit_tree(commit_id)?;
+    let parent_tree = match reverted_commit.parents.first() {
+        Some(parent_commit_id) => load_commit_tree(parent_commit_id)?,
+        None => Tree::new(),
+    };
+
+    let merge_result = merge_trees(
+        &reverted_tree,
+        &head_tree,
+        &parent_tree,
+        "HEAD",
+        &format!("parent of {}", commit_id),
+    )?;
+    apply_merge_changes(&merge_result.changes)?;
+
+    let message = format!(
+        "Revert \"{}\"\n\nThis reverts commit {}.",
+        reverted_commit.description, commit_id
+    );
+    if !merge_result.conflicts.is_empty() {
+        write_revert_state(&RevertState {
+            commit_id: commit_id.to_owned(),
+            message,
+            conflicts: merge_result.conflicts.clone(),
+        })?;
+        for conflict in &merge_result.conflicts {
+            println!("CONFLICT: {}", conflict.display());
+        }
+        println!("Resolve the conflicts and run revert --continue, or revert --abort");
+        return Ok(());
+    }
+
+    create_commit(&message, &get_author(), vec![head_commit_id], "revert")?;
+
+    Ok(())
+}
+
+pub fn revert_continue() -> std::io::Result<()> {
+    open_repository()?;
+
+    let revert_state = read_revert_state()?;
+    for conflict in &revert_state.conflicts {
+        if has_conflict_markers(conflict)? {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{} still has conflict markers", conflict.display()),
+            ));
+        }
+    }
+
+    create_commit(
+        &revert_state.message,
+        &get_author(),
+        head_commit_id()?.into_iter().collect(),
+        "revert",
+    )?;
+    fs::remove_file(REVERT_STATE_FILE_PATH)
+}
+
+pub fn revert_abort() -> std::io::Result<()> {
+    open_repository()?;
+
+    read_revert_state()?;
+    reset_working_tree(head_commit_id()?.as_deref())?;
+
+    fs::remove_file(REVERT_STATE_FILE_PATH)
+}
+
+// Fast-forwards when the current branch has no commits of its own, otherwise
+// records a commit with both heads as parents. Conflicts stop the merge until
+// merge --continue or --abort.
+fn merge_commit(commits: &[Commit], commit_id: &str, label: &str) -> std::io::Result<()> {
+    let head_commit_id = match head_commit_id()? {
+        Some(head_commit_id) => head_commit_id,
+        None => {
+            update_head(commit_id, &format!("merge {}: fast-forward", label))?;
+            return reset_working_tree(Some(commit_id));
+        }
+    };
+    if is_ancestor(commits, commit_id, &head_commit_id) {
+        println!("Already up to date");
+        return Ok(());
+    }
+
+    let head_tree = load_commit_tree(&head_commit_id)?;
+    ensure_clean_working_tree(&head_tree, "merging")?;
+
+    if is_ancestor(commits, &head_commit_id, commit_id) {
+        update_head(commit_id, &format!("merge {}: fast-forward", label))?;
+        println!("Fast-forward to {}", commit_id);
+        return reset_working_tree(Some(commit_id));
+    }
+
+    let base_tree = match merge_base(commits, &head_commit_id, commit_id) {
+        Some(base_commit_id) => load_commit_tree(&base_commit_id)?,
+        None => Tree::new(),
+    };
+    let merge_result = merge_trees(
+        &base_tree,
+        &head_tree,
+        &load_commit_tree(commit_id)?,
+        "HEAD",
+        label,
+    )?;
+    apply_merge_changes(&merge_result.changes)?;
+
+    let message = match current_branch()? {
+        Some(branch) => format!("Merge {} into {}", label, branch),
+        None => format!("Merge {}", label),
+    };
+    if !merge_result.conflicts.is_empty() {
+        write_merge_state(&MergeState {
+            commit_id: commit_id.to_owned(),
+            message,
+            conflicts: merge_result.conflicts.clone(),
+        })?;
+        for conflict in &merge_result.conflicts {
+            println!("CONFLICT: {}", conflict.display());
+        }
+        println!("Resolve the conflicts and run merge --continue, or merge --abort");
+        return Ok(());
+    }
+
+    create_commit(
+        &message,
+        &get_author(),
+        vec![head_commit_id, commit_id.to_owned()],
+        "merge",
+    )?;
+
+    Ok(())
+}
+
+pub fn merge(revision: &str) -> std::io::Result<()> {
+    open_repository()?;
+
+    ensure_no_operation_in_progress()?;
+
+    let commits = list_commits(MAIN_COMMITS_METADATA_FILE_PATH)?;
+    let commit_id = resolve_revision(&commits, revision)?;
+    merge_commit(&commits, &commit_id, revision)
+}
+
+pub fn merge_continue() -> std::io::Result<()> {
+    open_repository()?;
+
+    let merge_state = read_merge_state()?;
+    for conflict in &merge_state.conflicts {
+        if has_conflict_markers(conflict)? {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{} still has conflict markers", conflict.display()),
+            ));
+        }
+    }
+
+    let mut parents: Vec<String> = head_commit_id()?.into_iter().collect();
+    parents.push(merge_state.commit_id);
+    create_commit(&merge_state.message, &get_author(), parents, "merge")?;
+    fs::remove_file(MERGE_STATE_FILE_PATH)
+}
+
+pub fn merge_abort() -> std::io::Result<()> {
+    open_repository()?;
+
+    read_merge_state()?;
+    reset_working_tree(head_commit_id()?.as_deref())?;
+
+    fs::remove_file(MERGE_STATE_FILE_PATH)
+}
+
+fn ensure_clean_working_tree(head_tree: &Tree, action: &str) -> std::io::Result<()> {
+    if !working_tree_changes(head_tree)?.is_empty() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            format!("Commit or reset the local changes before {}", action),
+        ));
+    }
+
+    Ok(())
+}
+
+// Like ensure_clean_working_tree, but untracked files such as build outputs
+// do not count.
+fn ensure_clean_tracked_files(head_tree: &Tree, action: &str) -> std::io::Result<()> {
+    let changes = working_tree_changes(head_tree)?;
+    if changes.iter().any(|path| head_tree.contains_key(path)) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            format!("Commit or reset the local changes before {}", action),
+        ));
+    }
+
+    Ok(())
+}
+
+// Replaces the files of the tree with the ones of the commit, leaving
+// untracked files in place.
+fn switch_tracked_files(tree: &Tree, commit_id: &str) -> std::io::Result<()> {
+    for path in tree.keys() {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        // Directories left empty go as well
+        for dir in path.ancestors().skip(1) {
+            if dir.as_os_str().is_empty() || fs::remove_dir(dir).is_err() {
+                break;
+            }
+        }
+    }
+
+    load_commit(commit_id)
+}
+
+fn reset_working_tree(commit_id: Option<&str>) -> std::io::Result<()> {
     let files_to_ignore = list_files_ignore();
     delete_contents_of_directory(".", Some(&files_to_ignore))?;
-    clone(client, &bucket_name).await?;
+    if let Some(commit_id) = commit_id {
+        load_commit(commit_id)?;
+    }
 
     Ok(())
 }
 
-pub async fn push(client: &Client) -> std::io::Result<()> {
-    let bucket_name = fs::read_to_string(REMOTE_REPOSITORY_REFERENCE_FILE_PATH)?;
-    create_file_from_s3object(
-        client,
-        &(MAIN_COMMITS_METADATA_FILE_PATH.to_owned() + "-temp"),
-        &bucket_name,
-        MAIN_COMMITS_METADATA_FILE_PATH,
+fn head_tree() -> std::io::Result<Tree> {
+    match head_commit_id()? {
+        Some(head_commit_id) => load_commit_tree(&head_commit_id),
+        None => Ok(Tree::new()),
+    }
+}
+
+pub fn create_branch(branch: &str, start_point: Option<&str>) -> std::io::Result<()> {
+    open_repository()?;
+
+    if read_ref(&branch_ref_name(branch))?.is_some() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("Branch {} already exists", branch),
+        ));
+    }
+
+    let commit_id = match start_point {
+        Some(start_point) => {
+            let commits = list_commits(MAIN_COMMITS_METADATA_FILE_PATH)?;
+            resolve_revision(&commits, start_point)?
+        }
+        None => head_commit_id()?.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "Nothing committed yet to branch from",
+            )
+        })?,
+    };
+
+    update_ref(
+        &branch_ref_name(branch),
+        &commit_id,
+        &format!("branch: Created from {}", start_point.unwrap_or("HEAD")),
     )
-    .await?;
+}
 
-    let remote_commits = list_commits(&(MAIN_COMMITS_METADATA_FILE_PATH.to_owned() + "-temp"))?;
-    let local_commits = list_commits(MAIN_COMMITS_METADATA_FILE_PATH)?;
-    fs::remove_file(MAIN_COMMITS_METADATA_FILE_PATH.to_owned() + "-temp")?;
+pub fn create_tag(tag: &str, revision: &str) -> std::io::Result<()> {
+    open_repository()?;
+
+    if read_ref(&tag_ref_name(tag))?.is_some() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("Tag {} already exists", tag),
+        ));
+    }
+
+    let commits = list_commits(MAIN_COMMITS_METADATA_FILE_PATH)?;
+    let commit_id = resolve_revision(&commits, revision)?;
+    write_ref(&tag_ref_name(tag), &commit_id)
+}
+
+// Switches to a branch, or detaches HEAD when given a commit id.
+pub fn checkout(target: &str) -> std::io::Result<()> {
+    open_repository()?;
 
-    let last_remote_commit = remote_commits.last().unwrap();
-    let last_remote_commit_id = last_remote_commit.commit_id.clone();
+    ensure_clean_working_tree(&head_tree()?, "checking out")?;
 
-    let found_commit = find_commit_by_commit_id(&local_commits, &last_remote_commit_id);
-    if found_commit.is_none() {
+    let old_commit_id = head_commit_id()?;
+    let old_position = match current_branch()? {
+        Some(branch) => branch,
+        None => old_commit_id.clone().unwrap_or_default(),
+    };
+    let commit_id = match read_ref(&branch_ref_name(target))? {
+        Some(commit_id) => {
+            set_head_to_branch(target)?;
+            commit_id
+        }
+        None => {
+            let commits = list_commits(MAIN_COMMITS_METADATA_FILE_PATH)?;
+            let commit_id = resolve_revision(&commits, target)?;
+            detach_head(&commit_id)?;
+            commit_id
+        }
+    };
+    append_reflog(
+        "HEAD",
+        old_commit_id.as_deref(),
+        &commit_id,
+        &format!("checkout: moving from {} to {}", old_position, target),
+    )?;
+
+    reset_working_tree(Some(&commit_id))
+}
+
+fn ensure_no_operation_in_progress() -> std::io::Result<()> {
+    if read_sequencer_state()?.is_some()
+        || Path::new(REVERT_STATE_FILE_PATH).exists()
+        || Path::new(MERGE_STATE_FILE_PATH).exists()
+    {
         return Err(std::io::Error::new(
-            std::io::ErrorKind::PermissionDenied,
-            "Remote history is ahead of local history, pull the changes first",
+            std::io::ErrorKind::AlreadyExists,
+            "Another operation is in progress, continue or abort it first",
+        ));
+    }
+
+    Ok(())
+}
+
+fn head_commit() -> std::io::Result<Commit> {
+    let commits = list_commits(MAIN_COMMITS_METADATA_FILE_PATH)?;
+    let head_commit_id = head_commit_id()?.ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::NotFound, "Nothing committed yet")
+    })?;
+    find_commit(&commits, &head_commit_id)
+}
+
+fn commit_author(commit: &Commit) -> String {
+    if commit.author.is_empty() {
+        get_author()
+    } else {
+        commit.author.clone()
+    }
+}
+
+// Replaces HEAD by a commit of the working tree with the same parents and author.
+fn amend_head(message: &str, reflog_action: &str) -> std::io::Result<String> {
+    let head_commit = head_commit()?;
+    create_commit(
+        message,
+        &commit_author(&head_commit),
+        head_commit.parents,
+        reflog_action,
+    )
+}
+
+fn operation_command(operation: SequencerOperation) -> &'static str {
+    match operation {
+        SequencerOperation::CherryPick => "cherry-pick",
+        SequencerOperation::Rebase => "rebase",
+    }
+}
+
+fn picked_commit_message(commit: &Commit, record_origin: bool) -> String {
+    if record_origin {
+        format!(
+            "{}\n\n(cherry picked from commit {})",
+            commit.description, commit.commit_id
+        )
+    } else {
+        commit.description.clone()
+    }
+}
+
+// Merges the changes a commit made to its first parent into the working tree,
+// returning the conflicting paths.
+fn apply_commit_changes(commit: &Commit) -> std::io::Result<Vec<PathBuf>> {
+    let head_tree = head_tree()?;
+    let parent_tree = match commit.parents.first() {
+        Some(parent_commit_id) => load_commit_tree(parent_commit_id)?,
+        None => Tree::new(),
+    };
+    let picked_tree = load_commit_tree(&commit.commit_id)?;
+
+    let merge_result = merge_trees(
+        &parent_tree,
+        &head_tree,
+        &picked_tree,
+        "HEAD",
+        &commit.commit_id,
+    )?;
+    apply_merge_changes(&merge_result.changes)?;
+
+    Ok(merge_result.conflicts)
+}
+
+// Records the changes of a todo item once they are in the working tree.
+fn commit_todo_item(
+    sequencer_state: &SequencerState,
+    todo_item: &TodoItem,
+    commit: &Commit,
+) -> std::io::Result<()> {
+    let has_changes = !working_tree_changes(&head_tree()?)?.is_empty();
+
+    match todo_item.action {
+        TodoAction::Pick | TodoAction::Reword | TodoAction::Edit => {
+            if !has_changes {
+                println!(
+                    "Commit {} is already applied, skipping it",
+                    commit.commit_id
+                );
+                return Ok(());
+            }
+
+            let mut message = picked_commit_message(commit, sequencer_state.record_origin);
+            if todo_item.action == TodoAction::Reword {
+                message = edit_message(&message, "# Enter the new message of the commit.")?;
+            }
+            create_commit(
+                &message,
+                &commit_author(commit),
+                head_commit_id()?.into_iter().collect(),
+                operation_command(sequencer_state.operation),
+            )?;
+        }
+        TodoAction::Squash => {
+            let head_commit = head_commit()?;
+            let message = edit_message(
+                &format!("{}\n\n{}", head_commit.description, commit.description),
+                "# This is a combination of two commits, enter the message of the result.",
+            )?;
+            amend_head(
+                &message,
+                &format!("{} (squash)", operation_command(sequencer_state.operation)),
+            )?;
+        }
+        TodoAction::Fixup => {
+            if has_changes {
+                amend_head(
+                    &head_commit()?.description,
+                    &format!("{} (fixup)", operation_command(sequencer_state.operation)),
+                )?;
+            }
+        }
+        TodoAction::Drop => {}
+    }
+
+    Ok(())
+}
+
+// Runs the todo list one item at a time. The item being applied is saved as
+// "current" beforehand, so that a conflict or an edit stop can be resumed
+// with --continue or --skip.
+fn run_sequencer(mut sequencer_state: SequencerState) -> std::io::Result<()> {
+    let commits = list_commits(MAIN_COMMITS_METADATA_FILE_PATH)?;
+    let command = operation_command(sequencer_state.operation);
+
+    while !sequencer_state.todo.is_empty() {
+        let todo_item = sequencer_state.todo.remove(0);
+        if todo_item.action == TodoAction::Drop {
+            continue;
+        }
+        sequencer_state.current = Some(todo_item.clone());
+        write_sequencer_state(&sequencer_state)?;
+
+        let commit = find_commit(&commits, &todo_item.commit_id)?;
+        let conflicts = apply_commit_changes(&commit)?;
+        if !conflicts.is_empty() {
+            sequencer_state.conflicts = conflicts;
+            write_sequencer_state(&sequencer_state)?;
+            for conflict in &sequencer_state.conflicts {
+                println!("CONFLICT: {}", conflict.display());
+            }
+            println!(
+                "Could not apply {}, resolve the conflicts and run {} --continue, --skip or --abort",
+                commit.commit_id, command
+            );
+            return Ok(());
+        }
+
+        commit_todo_item(&sequencer_state, &todo_item, &commit)?;
+        if todo_item.action == TodoAction::Edit {
+            println!(
+                "Stopped at {}, change the working tree and run {} --continue",
+                commit.commit_id, command
+            );
+            return Ok(());
+        }
+        sequencer_state.current = None;
+    }
+
+    remove_sequencer_state()?;
+    if sequencer_state.operation == SequencerOperation::Rebase {
+        println!("Successfully rebased");
+    }
+
+    Ok(())
+}
+
+fn read_operation_state(operation: SequencerOperation) -> std::io::Result<SequencerState> {
+    match read_sequencer_state()? {
+        Some(sequencer_state) if sequencer_state.operation == operation => Ok(sequencer_state),
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("No {} in progress", operation_command(operation)),
+        )),
+    }
+}
+
+pub fn sequencer_continue(operation: SequencerOperation) -> std::io::Result<()> {
+    open_repository()?;
+
+    let mut sequencer_state = read_operation_state(operation)?;
+    for conflict in &sequencer_state.conflicts {
+        if has_conflict_markers(conflict)? {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{} still has conflict markers", conflict.display()),
+            ));
+        }
+    }
+
+    if let Some(todo_item) = sequencer_state.current.take() {
+        if todo_item.action == TodoAction::Edit && sequencer_state.conflicts.is_empty() {
+            // The commit was already recorded before stopping, so the changes
+            // made since then are folded into it.
+            if !working_tree_changes(&head_tree()?)?.is_empty() {
+                amend_head(
+                    &head_commit()?.description,
+                    &format!("{} (edit)", operation_command(sequencer_state.operation)),
+                )?;
+            }
+        } else {
+            let commits = list_commits(MAIN_COMMITS_METADATA_FILE_PATH)?;
+            let commit = find_commit(&commits, &todo_item.commit_id)?;
+            commit_todo_item(&sequencer_state, &todo_item, &commit)?;
+        }
+    }
+    sequencer_state.conflicts.clear();
+
+    run_sequencer(sequencer_state)
+}
+
+pub fn sequencer_skip(operation: SequencerOperation) -> std::io::Result<()> {
+    open_repository()?;
+
+    let mut sequencer_state = read_operation_state(operation)?;
+    reset_working_tree(head_commit_id()?.as_deref())?;
+    sequencer_state.current = None;
+    sequencer_state.conflicts.clear();
+
+    run_sequencer(sequencer_state)
+}
+
+pub fn sequencer_abort(operation: SequencerOperation) -> std::io::Result<()> {
+    open_repository()?;
+
+    let sequencer_state = read_operation_state(operation)?;
+    update_head(
+        &sequencer_state.original_head,
+        &format!("{}: aborting", operation_command(operation)),
+    )?;
+    reset_working_tree(Some(&sequencer_state.original_head))?;
+
+    remove_sequencer_state()
+}
+
+pub fn cherry_pick(revisions: &[String], record_origin: bool) -> std::io::Result<()> {
+    open_repository()?;
+
+    ensure_no_operation_in_progress()?;
+
+    let commits = list_commits(MAIN_COMMITS_METADATA_FILE_PATH)?;
+    let mut todo = Vec::new();
+    for revision in revisions {
+        let picked_commit_ids = if revision.contains("..") {
+            resolve_range(&commits, revision)?
+                .into_iter()
+                .map(|commit| commit.commit_id)
+                .collect()
+        } else {
+            vec![resolve_revision(&commits, revision)?]
+        };
+        for commit_id in picked_commit_ids {
+            todo.push(TodoItem {
+                action: TodoAction::Pick,
+                commit_id,
+            });
+        }
+    }
+
+    let original_head = head_commit_id()?.ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "Nothing committed yet to cherry-pick onto",
+        )
+    })?;
+    ensure_clean_working_tree(&load_commit_tree(&original_head)?, "cherry-picking")?;
+
+    run_sequencer(SequencerState {
+        operation: SequencerOperation::CherryPick,
+        original_head,
+        current: None,
+        todo,
+        record_origin,
+        conflicts: Vec::new(),
+    })
+}
+
+fn parse_todo_action(word: &str) -> Option<TodoAction> {
+    match word {
+        "p" | "pick" => Some(TodoAction::Pick),
+        "r" | "reword" => Some(TodoAction::Reword),
+        "e" | "edit" => Some(TodoAction::Edit),
+        "s" | "squash" => Some(TodoAction::Squash),
+        "f" | "fixup" => Some(TodoAction::Fixup),
+        "d" | "drop" => Some(TodoAction::Drop),
+        _ => None,
+    }
+}
+
+fn todo_action_name(action: TodoAction) -> &'static str {
+    match action {
+        TodoAction::Pick => "pick",
+        TodoAction::Reword => "reword",
+        TodoAction::Edit => "edit",
+        TodoAction::Squash => "squash",
+        TodoAction::Fixup => "fixup",
+        TodoAction::Drop => "drop",
+    }
+}
+
+// Writes the todo list to a file, lets the user edit it and reads it back.
+fn edit_todo_list(commits: &[Commit], todo: &[TodoItem]) -> std::io::Result<Vec<TodoItem>> {
+    let mut todo_string = String::new();
+    for todo_item in todo {
+        let commit = find_commit(commits, &todo_item.commit_id)?;
+        todo_string += &format!(
+            "{} {} {}\n",
+            todo_action_name(todo_item.action),
+            commit.commit_id,
+            commit.description.lines().next().unwrap_or_default()
+        );
+    }
+    todo_string += "\n\
+        # Commands:\n\
+        # p, pick <commit> = use commit\n\
+        # r, reword <commit> = use commit, but edit the commit message\n\
+        # e, edit <commit> = use commit, but stop for amending\n\
+        # s, squash <commit> = use commit, but meld into previous commit\n\
+        # f, fixup <commit> = like squash, but discard this commit's message\n\
+        # d, drop <commit> = remove commit\n\
+        #\n\
+        # The lines are run from top to bottom, removing every line cancels the rebase.\n";
+    File::create(REBASE_TODO_FILE_PATH)?.write_all(todo_string.as_bytes())?;
+    launch_editor(REBASE_TODO_FILE_PATH)?;
+
+    let mut edited_todo = Vec::new();
+    for line in fs::read_to_string(REBASE_TODO_FILE_PATH)?.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut words = line.split_whitespace();
+        let action = words.next().and_then(parse_todo_action);
+        let commit_id = words.next();
+        let (action, commit_id) = match (action, commit_id) {
+            (Some(action), Some(commit_id)) => (action, commit_id),
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("Invalid todo line: {}", line),
+                ))
+            }
+        };
+        edited_todo.push(TodoItem {
+            action,
+            commit_id: find_commit(commits, commit_id)?.commit_id,
+        });
+    }
+    fs::remove_file(REBASE_TODO_FILE_PATH)?;
+
+    let first_action = edited_todo
+        .iter()
+        .map(|todo_item| todo_item.action)
+        .find(|action| *action != TodoAction::Drop);
+    if matches!(first_action, Some(TodoAction::Squash | TodoAction::Fixup)) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "Cannot squash without a previous commit",
         ));
     }
 
-    if remote_commits.len() >= local_commits.len() {
+    Ok(edited_todo)
+}
+
+// Replays the commits of the current branch that are not in upstream on top of
+// it. The branch itself is moved, so --abort only has to move it back.
+pub fn rebase(upstream: &str, interactive: bool) -> std::io::Result<()> {
+    open_repository()?;
+
+    ensure_no_operation_in_progress()?;
+
+    let commits = list_commits(MAIN_COMMITS_METADATA_FILE_PATH)?;
+    let upstream_commit_id = resolve_revision(&commits, upstream)?;
+    let original_head = head_commit_id()?.ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "Nothing committed yet to rebase",
+        )
+    })?;
+    ensure_clean_working_tree(&load_commit_tree(&original_head)?, "rebasing")?;
+
+    if !interactive && is_ancestor(&commits, &upstream_commit_id, &original_head) {
+        println!("Current branch is up to date");
+        return Ok(());
+    }
+
+    // Every commit of the merged branches is replayed, parents first, the merges
+    // themselves being dropped as the history becomes linear
+    let range = format!("{}..{}", upstream_commit_id, original_head);
+    let mut todo: Vec<TodoItem> = resolve_range(&commits, &range)?
+        .into_iter()
+        .filter(|commit| commit.parents.len() <= 1)
+        .map(|commit| TodoItem {
+            action: TodoAction::Pick,
+            commit_id: commit.commit_id,
+        })
+        .collect();
+    if interactive {
+        todo = edit_todo_list(&commits, &todo)?;
+        if todo.is_empty() {
+            println!("Nothing to do");
+            return Ok(());
+        }
+    }
+
+    update_head(
+        &upstream_commit_id,
+        &format!("rebase: checkout {}", upstream),
+    )?;
+    reset_working_tree(Some(&upstream_commit_id))?;
+
+    run_sequencer(SequencerState {
+        operation: SequencerOperation::Rebase,
+        original_head,
+        current: None,
+        todo,
+        record_origin: false,
+        conflicts: Vec::new(),
+    })
+}
+
+pub async fn lfs_fetch(paths: &[String], remote_name: Option<&str>) -> std::io::Result<()> {
+    open_repository()?;
+
+    let (_, remote_config) = select_remote(remote_name)?;
+    let remote = open_remote(&remote_config).await?;
+    let last_commit_id = match head_commit_id()? {
+        Some(head_commit_id) => head_commit_id,
+        None => return Ok(()),
+    };
+
+    for path in paths {
+        let history_dir = history_dir_for_path(Path::new(path));
+        let commits_metadata = commits_metadata(&history_dir).map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("{} is not tracked", path),
+            )
+        })?;
+        let commit_metadata = find_metadata_by_commit_id(&commits_metadata, &last_commit_id)
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("{} is not in the last commit", path),
+                )
+            })?;
+        if !commit_metadata.lfs {
+            println!("{} is not an LFS file, skipping", path);
+            continue;
+        }
+
+        let pointer_contents = read_part_of_file(
+            &(history_dir.clone() + DATA_RELATIVE_PATH),
+            commit_metadata.pointer_to_data,
+            commit_metadata.size as usize,
+        )?;
+        let pointer = parse_lfs_pointer(&pointer_contents).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{} has an invalid LFS pointer", path),
+            )
+        })?;
+
+        let blob_path = lfs_blob_path(&pointer.oid);
+        if !Path::new(&blob_path).exists() {
+            println!("Fetching {}", path);
+            remote
+                .download(&lfs_blob_key(&pointer.oid), Path::new(&blob_path))
+                .await?;
+        }
+        restore_file(&history_dir, &commit_metadata, Path::new(path))?;
+    }
+
+    Ok(())
+}
+
+fn read_bisect_state_or_fail() -> std::io::Result<BisectState> {
+    read_bisect_state()?
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "Not bisecting"))
+}
+
+fn bisect_checkout(commit_id: &str) -> std::io::Result<()> {
+    let head_tree = head_tree()?;
+    ensure_clean_tracked_files(&head_tree, "bisecting")?;
+
+    let old_commit_id = head_commit_id()?;
+    let old_position = match current_branch()? {
+        Some(branch) => branch,
+        None => old_commit_id.clone().unwrap_or_default(),
+    };
+    detach_head(commit_id)?;
+    append_reflog(
+        "HEAD",
+        old_commit_id.as_deref(),
+        commit_id,
+        &format!("checkout: moving from {} to {}", old_position, commit_id),
+    )?;
+
+    switch_tracked_files(&head_tree, commit_id)
+}
+
+// Checks out the next commit to test, or tells which commit is the first bad one.
+fn bisect_next(bisect_state: &BisectState) -> std::io::Result<BisectStep> {
+    let commits = list_commits(MAIN_COMMITS_METADATA_FILE_PATH)?;
+    let bisect_step = next_bisect_step(&commits, bisect_state)?;
+
+    match &bisect_step {
+        BisectStep::Waiting => println!("Waiting for both good and bad commits"),
+        BisectStep::Test {
+            commit_id,
+            remaining,
+        } => {
+            let steps = usize::BITS - remaining.leading_zeros();
+            println!(
+                "Bisecting: {} revisions left to test after this (roughly {} steps)",
+                remaining, steps
+            );
+            bisect_checkout(commit_id)?;
+            let commit = find_commit(&commits, commit_id)?;
+            println!("[{}] {}", commit.commit_id, commit.description);
+        }
+        BisectStep::Found(commit_id) => {
+            let commit = find_commit(&commits, commit_id)?;
+            println!("{} is the first bad commit", commit.commit_id);
+            println!("{} {} {}", commit.date, commit.author, commit.description);
+        }
+        BisectStep::OnlySkipped(commit_ids) => {
+            println!("There are only skipped commits left to test.");
+            println!("The first bad commit could be any of:");
+            for commit_id in commit_ids {
+                println!("{}", commit_id);
+            }
+        }
+    }
+
+    Ok(bisect_step)
+}
+
+fn record_bisect_mark(mark: BisectMark, commit_id: String) -> std::io::Result<BisectStep> {
+    let mut bisect_state = read_bisect_state_or_fail()?;
+    match mark {
+        BisectMark::Good => bisect_state.good.push(commit_id),
+        BisectMark::Bad => bisect_state.bad = Some(commit_id),
+        BisectMark::Skip => bisect_state.skipped.push(commit_id),
+    }
+    write_bisect_state(&bisect_state)?;
+
+    bisect_next(&bisect_state)
+}
+
+// Starts a binary search for the commit that introduced a bug, optionally
+// with a known bad commit followed by known good ones.
+pub fn bisect_start(bad: Option<&str>, good: &[String]) -> std::io::Result<()> {
+    open_repository()?;
+
+    ensure_no_operation_in_progress()?;
+    if read_bisect_state()?.is_some() {
         return Err(std::io::Error::new(
-            std::io::ErrorKind::PermissionDenied,
-            "Nothing to push",
+            std::io::ErrorKind::AlreadyExists,
+            "Already bisecting, use bisect reset first",
         ));
     }
+    ensure_clean_tracked_files(&head_tree()?, "bisecting")?;
+
+    let commits = list_commits(MAIN_COMMITS_METADATA_FILE_PATH)?;
+    let original_head = head_commit_id()?
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "Nothing to bisect"))?;
+    let bisect_state = BisectState {
+        original_head,
+        original_branch: current_branch()?,
+        bad: bad.map(|bad| resolve_revision(&commits, bad)).transpose()?,
+        good: good
+            .iter()
+            .map(|good| resolve_revision(&commits, good))
+            .collect::<std::io::Result<_>>()?,
+        skipped: Vec::new(),
+    };
+    write_bisect_state(&bisect_state)?;
 
-    sync_local_history_with_s3(client, &bucket_name, VSM_DIR).await?;
+    bisect_next(&bisect_state)?;
 
     Ok(())
 }
+
+pub fn bisect_mark(mark: BisectMark, revision: &str) -> std::io::Result<()> {
+    open_repository()?;
+
+    let commits = list_commits(MAIN_COMMITS_METADATA_FILE_PATH)?;
+    record_bisect_mark(mark, resolve_revision(&commits, revision)?)?;
+
+    Ok(())
+}
+
+// Goes back to the branch or commit checked out when the bisection started.
+pub fn bisect_reset() -> std::io::Result<()> {
+    open_repository()?;
+
+    let bisect_state = read_bisect_state_or_fail()?;
+    let head_tree = head_tree()?;
+    ensure_clean_tracked_files(&head_tree, "resetting the bisection")?;
+
+    let old_commit_id = head_commit_id()?;
+    let target = match &bisect_state.original_branch {
+        Some(branch) => {
+            set_head_to_branch(branch)?;
+            branch.clone()
+        }
+        None => {
+            detach_head(&bisect_state.original_head)?;
+            bisect_state.original_head.clone()
+        }
+    };
+    let commit_id = head_commit_id()?.unwrap_or(bisect_state.original_head);
+    append_reflog(
+        "HEAD",
+        old_commit_id.as_deref(),
+        &commit_id,
+        &format!(
+            "checkout: moving from {} to {}",
+            old_commit_id.as_deref().unwrap_or_default(),
+            target
+        ),
+    )?;
+    switch_tracked_files(&head_tree, &commit_id)?;
+
+    remove_bisect_state()
+}
+
+// Runs the command on every commit to test: exiting with 0 marks the commit
+// good, 125 skips it and any other status up to 127 marks it bad.
+pub fn bisect_run(command: &[String]) -> std::io::Result<()> {
+    open_repository()?;
+
+    let bisect_state = read_bisect_state_or_fail()?;
+    if bisect_state.bad.is_none() || bisect_state.good.is_empty() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Mark a good and a bad commit before running bisect run",
+        ));
+    }
+
+    loop {
+        println!("running {}", command.join(" "));
+        let status = process::Command::new(&command[0])
+            .args(&command[1..])
+            .status()?;
+        let mark = match status.code() {
+            Some(0) => BisectMark::Good,
+            Some(125) => BisectMark::Skip,
+            Some(code) if code < 128 => BisectMark::Bad,
+            _ => {
+                return Err(std::io::Error::other(format!(
+                    "bisect run failed: {} stopped with {}",
+                    command[0], status
+                )))
+            }
+        };
+
+        let commit_id = head_commit_id()?.unwrap_or_default();
+        if !matches!(
+            record_bisect_mark(mark, commit_id)?,
+            BisectStep::Test { .. }
+        ) {
+            return Ok(());
+        }
+    }
+}
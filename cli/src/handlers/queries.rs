@@ -1,11 +1,32 @@
-use crate::utils::constants::MAIN_COMMITS_METADATA_FILE_PATH;
+use crate::utils::blame::blame_file;
+use crate::utils::constants::{MAIN_COMMITS_METADATA_FILE_PATH, TAGS_REFS_PREFIX};
+use crate::utils::fs_provider::get_file_paths_recursively;
+use crate::utils::grep::{grep_files, GrepOptions};
+use crate::utils::history_paths::{matches_pathspec, normalize_path};
+use crate::utils::patch::{write_contents_diff, write_tree_diff};
+use crate::utils::refs::{
+    current_branch, full_ref_name, head_commit_id, list_branches, list_ref_names, read_reflog,
+};
+use crate::utils::revisions::{
+    merge_base, path_separator_index, resolve_file_revision, resolve_range, resolve_range_side,
+    resolve_revision,
+};
+use crate::utils::types::{Commit, Tree, TreeEntry};
 use crate::utils::*;
+use regex::RegexBuilder;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
 
-pub fn log_commits() -> std::io::Result<()> {
-    check_if_initialized()?;
+pub fn log_commits(range: &str) -> std::io::Result<()> {
+    open_repository()?;
     let commits = list_commits(MAIN_COMMITS_METADATA_FILE_PATH)?;
+    if range == "HEAD" && head_commit_id()?.is_none() {
+        return Ok(());
+    }
 
-    for commit in commits {
+    for commit in resolve_range(&commits, range)? {
         println!(
             "{} {} {}",
             commit.date, commit.commit_id, commit.description
@@ -14,3 +35,358 @@ pub fn log_commits() -> std::io::Result<()> {
 
     Ok(())
 }
+
+pub fn list_branch_names() -> std::io::Result<()> {
+    open_repository()?;
+    let current_branch = current_branch()?;
+
+    for branch in list_branches()? {
+        if current_branch.as_deref() == Some(branch.as_str()) {
+            println!("* {}", branch);
+        } else {
+            println!("  {}", branch);
+        }
+    }
+
+    Ok(())
+}
+
+// Newest entries first, numbered the way "ref@{n}" resolves them.
+pub fn show_reflog(ref_name: &str) -> std::io::Result<()> {
+    open_repository()?;
+
+    for (index, entry) in read_reflog(&full_ref_name(ref_name))?
+        .iter()
+        .rev()
+        .enumerate()
+    {
+        println!(
+            "{} {}@{{{}}} {} {}",
+            entry.new_commit_id, ref_name, index, entry.date, entry.reason
+        );
+    }
+
+    Ok(())
+}
+
+pub fn list_tags() -> std::io::Result<()> {
+    open_repository()?;
+
+    for tag in list_ref_names(TAGS_REFS_PREFIX)? {
+        println!("{}", tag);
+    }
+
+    Ok(())
+}
+
+// Verbose listing shows the URL of each remote and the branches using it by
+// default.
+pub fn list_remotes(verbose: bool) -> std::io::Result<()> {
+    open_repository()?;
+
+    let remotes_config = read_remotes_config()?;
+    for (name, remote_config) in &remotes_config.remotes {
+        if !verbose {
+            println!("{}", name);
+            continue;
+        }
+        let branches: Vec<&str> = remotes_config
+            .branch_remotes
+            .iter()
+            .filter(|(_, branch_remote)| *branch_remote == name)
+            .map(|(branch, _)| branch.as_str())
+            .collect();
+        if branches.is_empty() {
+            println!("{}\t{}", name, remote_config.url);
+        } else {
+            println!("{}\t{} ({})", name, remote_config.url, branches.join(", "));
+        }
+    }
+
+    Ok(())
+}
+
+// Resolves "rev:path" to the stored entry of the file, reading only its own
+// history.
+fn file_at_revision(commits: &[Commit], revision: &str) -> std::io::Result<TreeEntry> {
+    let (commit_id, path) = resolve_file_revision(commits, revision)?;
+    load_tree_entry(&commit_id, &path)?.ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("{} does not exist in {}", path.display(), commit_id),
+        )
+    })
+}
+
+fn write_commit_header(commit: &Commit, out: &mut impl Write) -> std::io::Result<()> {
+    writeln!(out, "commit {}", commit.commit_id)?;
+    if commit.parents.len() > 1 {
+        writeln!(out, "Merge: {}", commit.parents.join(" "))?;
+    }
+    if !commit.author.is_empty() {
+        writeln!(out, "Author: {}", commit.author)?;
+    }
+    writeln!(out, "Date:   {}", commit.date)?;
+    writeln!(out)?;
+    for line in commit.description.lines() {
+        writeln!(out, "    {}", line)?;
+    }
+    writeln!(out)
+}
+
+// Prints a commit and its changes against its first parent, or the contents
+// of a file for "rev:path", without touching the working tree.
+pub fn show(revision: &str) -> std::io::Result<()> {
+    open_repository()?;
+    let commits = list_commits(MAIN_COMMITS_METADATA_FILE_PATH)?;
+    let mut out = BufWriter::new(io::stdout().lock());
+
+    if path_separator_index(revision).is_some() {
+        let entry = file_at_revision(&commits, revision)?;
+        write_file_contents(&entry.history_dir, &entry.metadata, &mut out)?;
+        return out.flush();
+    }
+
+    let commit = find_commit(&commits, &resolve_revision(&commits, revision)?)?;
+    let parent_tree = match commit.parents.first() {
+        Some(parent_commit_id) => load_commit_tree(parent_commit_id)?,
+        None => Tree::new(),
+    };
+    let tree = load_commit_tree(&commit.commit_id)?;
+
+    write_commit_header(&commit, &mut out)?;
+    write_tree_diff(&parent_tree, &tree, &mut out)?;
+    out.flush()
+}
+
+// "diff rev" compares a commit with the working tree, "diff A B" and "diff A..B"
+// two commits and "diff A...B" B with its merge base with A.
+pub fn diff(revision: &str, other_revision: Option<&str>) -> std::io::Result<()> {
+    open_repository()?;
+    let commits = list_commits(MAIN_COMMITS_METADATA_FILE_PATH)?;
+    let mut out = BufWriter::new(io::stdout().lock());
+
+    let (old_commit_id, new_commit_id) = if let Some(other_revision) = other_revision {
+        (
+            resolve_revision(&commits, revision)?,
+            Some(resolve_revision(&commits, other_revision)?),
+        )
+    } else if let Some((a, b)) = revision.split_once("...") {
+        let a = resolve_range_side(&commits, a)?;
+        let b = resolve_range_side(&commits, b)?;
+        let base = merge_base(&commits, &a, &b).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} and {} have no common ancestor", a, b),
+            )
+        })?;
+        (base, Some(b))
+    } else if let Some((a, b)) = revision.split_once("..") {
+        (
+            resolve_range_side(&commits, a)?,
+            Some(resolve_range_side(&commits, b)?),
+        )
+    } else {
+        (resolve_revision(&commits, revision)?, None)
+    };
+
+    let old_tree = load_commit_tree(&old_commit_id)?;
+    match new_commit_id {
+        Some(new_commit_id) => {
+            write_tree_diff(&old_tree, &load_commit_tree(&new_commit_id)?, &mut out)?
+        }
+        None => {
+            let mut changes = working_tree_changes(&old_tree)?;
+            changes.sort();
+            for path in changes {
+                let old_contents = old_tree.get(&path).map(read_stored_contents).transpose()?;
+                let new_contents = match fs::read(&path) {
+                    Ok(contents) => Some(contents),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                    Err(e) => return Err(e),
+                };
+                write_contents_diff(
+                    &path,
+                    old_contents.as_deref(),
+                    new_contents.as_deref(),
+                    &mut out,
+                )?;
+            }
+        }
+    }
+
+    out.flush()
+}
+
+// Prints the raw record of a commit or the contents of a file ("rev:path"),
+// or only their type or size.
+pub fn cat_file(object: &str, print_type: bool, print_size: bool) -> std::io::Result<()> {
+    open_repository()?;
+    let commits = list_commits(MAIN_COMMITS_METADATA_FILE_PATH)?;
+    let mut out = BufWriter::new(io::stdout().lock());
+
+    if path_separator_index(object).is_some() {
+        let entry = file_at_revision(&commits, object)?;
+        if print_type {
+            writeln!(out, "file")?;
+        } else if print_size {
+            let size = write_file_contents(&entry.history_dir, &entry.metadata, &mut io::sink())?;
+            writeln!(out, "{}", size)?;
+        } else {
+            write_file_contents(&entry.history_dir, &entry.metadata, &mut out)?;
+        }
+        return out.flush();
+    }
+
+    let commit = find_commit(&commits, &resolve_revision(&commits, object)?)?;
+    let commit_string = serde_json::to_string_pretty(&commit)?;
+    if print_type {
+        writeln!(out, "commit")?;
+    } else if print_size {
+        writeln!(out, "{}", commit_string.len())?;
+    } else {
+        writeln!(out, "{}", commit_string)?;
+    }
+    out.flush()
+}
+
+fn invalid_line_range(line_range: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        format!("Invalid line range {}", line_range),
+    )
+}
+
+// "start,end" with 1-based inclusive bounds, either of which may be omitted.
+fn parse_line_range(line_range: &str, line_count: usize) -> std::io::Result<(usize, usize)> {
+    let (start, end) = line_range.split_once(',').unwrap_or((line_range, ""));
+    let start = match start {
+        "" => 1,
+        start => start.parse().map_err(|_| invalid_line_range(line_range))?,
+    };
+    let end = match end {
+        "" => line_count,
+        end => end.parse().map_err(|_| invalid_line_range(line_range))?,
+    };
+    if start == 0 || start > end || end > line_count {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("File has only {} lines", line_count),
+        ));
+    }
+
+    Ok((start, end))
+}
+
+pub fn blame(
+    path: &str,
+    revision: &str,
+    line_range: Option<&str>,
+    ignore_whitespace: bool,
+    follow_renames: bool,
+) -> std::io::Result<()> {
+    open_repository()?;
+    let commits = list_commits(MAIN_COMMITS_METADATA_FILE_PATH)?;
+    let commit_id = resolve_revision(&commits, revision)?;
+    let lines = blame_file(
+        &commits,
+        &commit_id,
+        Path::new(path),
+        ignore_whitespace,
+        follow_renames,
+    )?;
+    let (start, end) = match line_range {
+        Some(line_range) => parse_line_range(line_range, lines.len())?,
+        None => (1, lines.len()),
+    };
+
+    let commits_by_id: HashMap<&str, &Commit> = commits
+        .iter()
+        .map(|commit| (commit.commit_id.as_str(), commit))
+        .collect();
+    let author_width = lines
+        .iter()
+        .filter_map(|line| commits_by_id.get(line.commit_id.as_str()))
+        .map(|commit| commit.author.len())
+        .max()
+        .unwrap_or_default();
+    let line_number_width = end.to_string().len();
+
+    let mut out = BufWriter::new(io::stdout().lock());
+    for (line_number, line) in lines.iter().enumerate().take(end).skip(start - 1) {
+        let (author, date) = match commits_by_id.get(line.commit_id.as_str()) {
+            Some(commit) => (commit.author.as_str(), commit.date.as_str()),
+            None => ("", ""),
+        };
+        write!(
+            out,
+            "{} ({:<author_width$} {} {:>line_number_width$}) {}",
+            line.commit_id,
+            author,
+            date,
+            line_number + 1,
+            line.contents
+        )?;
+        if !line.contents.ends_with('\n') {
+            writeln!(out)?;
+        }
+    }
+    out.flush()
+}
+
+// Searches the working tree, or the files of a commit straight from the
+// history when a revision is given.
+pub fn grep(
+    pattern: &str,
+    revision: Option<&str>,
+    pathspecs: &[String],
+    ignore_case: bool,
+    options: &GrepOptions,
+) -> std::io::Result<()> {
+    open_repository()?;
+    let regex = RegexBuilder::new(pattern)
+        .case_insensitive(ignore_case)
+        .build()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e.to_string()))?;
+    let matches_pathspecs = |path: &Path| {
+        pathspecs.is_empty()
+            || pathspecs
+                .iter()
+                .any(|pathspec| matches_pathspec(path, pathspec))
+    };
+
+    let outputs = match revision {
+        Some(revision) => {
+            let commits = list_commits(MAIN_COMMITS_METADATA_FILE_PATH)?;
+            let tree = load_commit_tree(&resolve_revision(&commits, revision)?)?;
+            let entries: Vec<(&PathBuf, &TreeEntry)> = tree
+                .iter()
+                .filter(|(path, _)| matches_pathspecs(path))
+                .collect();
+            grep_files(&entries, &regex, options, |(path, entry)| {
+                let mut contents = Vec::new();
+                write_file_contents(&entry.history_dir, &entry.metadata, &mut contents)?;
+                Ok((format!("{}:{}", revision, path.display()), contents))
+            })?
+        }
+        None => {
+            let files_to_ignore = list_files_ignore();
+            let mut file_paths: Vec<PathBuf> =
+                get_file_paths_recursively(None, Some(&files_to_ignore))
+                    .iter()
+                    .map(|file_path| normalize_path(file_path))
+                    .filter(|file_path| matches_pathspecs(file_path))
+                    .collect();
+            file_paths.sort();
+            grep_files(&file_paths, &regex, options, |file_path| {
+                Ok((file_path.display().to_string(), fs::read(file_path)?))
+            })?
+        }
+    };
+
+    let mut out = BufWriter::new(io::stdout().lock());
+    for output in outputs {
+        out.write_all(output.as_bytes())?;
+    }
+    out.flush()
+}
@@ -1,6 +1,8 @@
 pub mod handlers;
 pub mod utils;
-use clap::{Arg, Command};
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use utils::grep::GrepOptions;
+use utils::types::{BisectMark, CloneState, RemoteConfig, ResetMode, SequencerOperation};
 
 fn cli() -> Command {
     Command::new("rgit")
@@ -8,48 +10,653 @@ fn cli() -> Command {
         .subcommand_required(true)
         .subcommand(Command::new("init").about("Initializes a new repository"))
         .subcommand(
-            Command::new("commit").about("Commits the changes").arg(
-                Arg::new("description")
+            Command::new("commit")
+                .about("Commits the changes")
+                .arg(
+                    Arg::new("description")
+                        .required_unless_present("amend")
+                        .short('d')
+                        .help("commit description"),
+                )
+                .arg(
+                    Arg::new("amend")
+                        .long("amend")
+                        .action(ArgAction::SetTrue)
+                        .help("Replaces the last commit, keeping its description unless -d is given"),
+                )
+                .arg(
+                    Arg::new("force")
+                        .long("force")
+                        .action(ArgAction::SetTrue)
+                        .requires("amend")
+                        .help("Amends the last commit even if it was already pushed"),
+                ),
+        )
+        .subcommand(
+            Command::new("view").about("Views the commit").arg(
+                Arg::new("id")
+                    .help("revision, e.g. a commit id, a branch or HEAD~2")
                     .required(true)
-                    .short('d')
-                    .help("commit description"),
+                    .short('i'),
             ),
         )
         .subcommand(
-            Command::new("view")
-                .about("Views the commit")
-                .arg(Arg::new("id").help("commit id").required(true).short('i')),
+            Command::new("commits")
+                .about("Views all the commits")
+                .arg(
+                    Arg::new("range")
+                        .default_value("HEAD")
+                        .help("revision or range (A..B, A...B) to list"),
+                ),
+        )
+        .subcommand(
+            Command::new("show")
+                .about("Shows a commit and its changes, or a file with rev:path")
+                .arg(
+                    Arg::new("revision")
+                        .default_value("HEAD")
+                        .help("revision, or rev:path for a file"),
+                ),
+        )
+        .subcommand(
+            Command::new("diff")
+                .about("Shows the changes between a commit and the working tree, or two commits")
+                .arg(
+                    Arg::new("revision")
+                        .default_value("HEAD")
+                        .help("revision, or A..B and A...B for two commits"),
+                )
+                .arg(Arg::new("other").help("revision to compare the first one with")),
+        )
+        .subcommand(
+            Command::new("cat-file")
+                .about("Prints the record of a commit, or a file with rev:path")
+                .arg(
+                    Arg::new("object")
+                        .required(true)
+                        .help("revision, or rev:path for a file"),
+                )
+                .arg(
+                    Arg::new("type")
+                        .short('t')
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("size")
+                        .help("Prints the type of the object instead"),
+                )
+                .arg(
+                    Arg::new("size")
+                        .short('s')
+                        .action(ArgAction::SetTrue)
+                        .help("Prints the size of the object instead"),
+                ),
+        )
+        .subcommand(
+            Command::new("blame")
+                .about("Shows the commit that last changed each line of a file")
+                .arg(Arg::new("path").required(true))
+                .arg(
+                    Arg::new("revision")
+                        .default_value("HEAD")
+                        .help("Commit to blame the file at"),
+                )
+                .arg(
+                    Arg::new("lines")
+                        .short('L')
+                        .help("Only shows the lines start,end"),
+                )
+                .arg(
+                    Arg::new("ignore-whitespace")
+                        .short('w')
+                        .long("ignore-whitespace")
+                        .action(ArgAction::SetTrue)
+                        .help("Ignores whitespace changes when comparing lines"),
+                )
+                .arg(
+                    Arg::new("follow")
+                        .long("follow")
+                        .action(ArgAction::SetTrue)
+                        .help("Follows the file across renames"),
+                ),
+        )
+        .subcommand(
+            Command::new("grep")
+                .about("Searches the working tree or a commit for lines matching a regex")
+                .arg(Arg::new("pattern").required(true))
+                .arg(
+                    Arg::new("revision")
+                        .help("Commit to search instead of the working tree"),
+                )
+                .arg(
+                    Arg::new("paths")
+                        .num_args(1..)
+                        .last(true)
+                        .help("files, directories or glob patterns to search"),
+                )
+                .arg(
+                    Arg::new("ignore-case")
+                        .short('i')
+                        .long("ignore-case")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("line-number")
+                        .short('n')
+                        .long("line-number")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("files-with-matches")
+                        .short('l')
+                        .long("files-with-matches")
+                        .action(ArgAction::SetTrue)
+                        .help("Only prints the names of the matching files"),
+                )
+                .arg(
+                    Arg::new("count")
+                        .short('c')
+                        .long("count")
+                        .action(ArgAction::SetTrue)
+                        .help("Prints the number of matching lines of each file"),
+                )
+                .arg(
+                    Arg::new("after-context")
+                        .short('A')
+                        .value_parser(clap::value_parser!(usize))
+                        .help("Prints that many lines after each match"),
+                )
+                .arg(
+                    Arg::new("before-context")
+                        .short('B')
+                        .value_parser(clap::value_parser!(usize))
+                        .help("Prints that many lines before each match"),
+                )
+                .arg(
+                    Arg::new("context")
+                        .short('C')
+                        .value_parser(clap::value_parser!(usize))
+                        .help("Prints that many lines around each match"),
+                ),
+        )
+        .subcommand(
+            Command::new("reflog")
+                .about("Shows the previous positions of a ref")
+                .arg(
+                    Arg::new("ref")
+                        .default_value("HEAD")
+                        .help("HEAD, a branch or a remote branch"),
+                ),
         )
-        .subcommand(Command::new("commits").about("Views all the commits"))
         .subcommand(
             Command::new("clone")
                 .about("Clones the remote repository")
                 .arg(
                     Arg::new("bucket")
-                        .required(true)
+                        .required_unless_present("continue")
                         .short('n')
-                        .help("Repository S3 bucket bucket"),
+                        .help("S3 bucket, file:// URL or directory of the repository"),
+                )
+                .args(remote_settings_args())
+                .arg(
+                    Arg::new("no-lfs")
+                        .long("no-lfs")
+                        .action(ArgAction::SetTrue)
+                        .help(
+                            "Skip downloading LFS files, now and on later fetches, they can be fetched with lfs fetch",
+                        ),
+                )
+                .arg(
+                    Arg::new("depth")
+                        .long("depth")
+                        .value_parser(clap::builder::RangedU64ValueParser::<usize>::new().range(1..))
+                        .help("Only downloads the last commits of the branch, implies --single-branch"),
+                )
+                .arg(
+                    Arg::new("branch")
+                        .short('b')
+                        .long("branch")
+                        .help("Checks out this branch instead of the one of the remote HEAD"),
+                )
+                .arg(
+                    Arg::new("single-branch")
+                        .long("single-branch")
+                        .action(ArgAction::SetTrue)
+                        .help("Only downloads the history of one branch, also on later fetches"),
+                )
+                .arg(
+                    Arg::new("no-single-branch")
+                        .long("no-single-branch")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("single-branch")
+                        .help("Downloads the history of every branch, even with --depth"),
+                )
+                .arg(
+                    Arg::new("continue")
+                        .long("continue")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with_all([
+                            "bucket",
+                            "endpoint-url",
+                            "region",
+                            "path-style",
+                            "profile",
+                            "encrypt",
+                            "key-file",
+                            "depth",
+                            "branch",
+                            "single-branch",
+                            "no-single-branch",
+                        ])
+                        .help("Resumes an interrupted clone"),
                 ),
         )
         .subcommand(
             Command::new("set-remote")
-                .about("Sets the remote repository bucket")
+                .about("Sets the remote repository")
                 .arg(
                     Arg::new("bucket")
                         .required(true)
                         .short('n')
-                        .help("Repository S3 bucket bucket"),
+                        .help("S3 bucket, file:// URL or directory of the repository"),
+                )
+                .args(remote_settings_args()),
+        )
+        .subcommand(
+            Command::new("reset")
+                .about("Moves the current branch to the given commit")
+                .arg(
+                    Arg::new("commit")
+                        .required(true)
+                        .help("revision, e.g. a commit id, a branch or HEAD@{1}"),
+                )
+                .arg(
+                    Arg::new("soft")
+                        .long("soft")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with_all(["mixed", "hard"])
+                        .help("Only moves HEAD"),
+                )
+                .arg(
+                    Arg::new("mixed")
+                        .long("mixed")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("hard")
+                        .help("Same as --soft, as there is no index to reset (default)"),
+                )
+                .arg(
+                    Arg::new("hard")
+                        .long("hard")
+                        .action(ArgAction::SetTrue)
+                        .help("Moves HEAD and overwrites the working tree"),
+                ),
+        )
+        .subcommand(
+            Command::new("restore")
+                .about("Restores files of the working tree from a commit")
+                .arg(
+                    Arg::new("source")
+                        .short('s')
+                        .long("source")
+                        .default_value("HEAD")
+                        .help("Commit to restore the files from"),
+                )
+                .arg(
+                    Arg::new("staged")
+                        .long("staged")
+                        .action(ArgAction::SetTrue)
+                        .help("Restores the index instead, which rgit does not have"),
+                )
+                .arg(
+                    Arg::new("paths")
+                        .required(true)
+                        .num_args(1..)
+                        .help("files, directories or glob patterns"),
+                ),
+        )
+        .subcommand(
+            Command::new("revert")
+                .about("Creates a commit undoing the changes of the given commit")
+                .arg(
+                    Arg::new("commit")
+                        .required_unless_present_any(["continue", "abort"])
+                        .help("revision to revert"),
+                )
+                .arg(
+                    Arg::new("continue")
+                        .long("continue")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with_all(["commit", "abort"])
+                        .help("Commits the revert once the conflicts are resolved"),
+                )
+                .arg(
+                    Arg::new("abort")
+                        .long("abort")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("commit")
+                        .help("Cancels the revert and restores the working tree"),
+                ),
+        )
+        .subcommand(
+            Command::new("branch")
+                .about("Lists the branches, or creates one")
+                .arg(Arg::new("name").help("name of the branch to create"))
+                .arg(
+                    Arg::new("start")
+                        .requires("name")
+                        .help("branch or commit id to start from, HEAD by default"),
+                ),
+        )
+        .subcommand(
+            Command::new("tag")
+                .about("Lists the tags, or creates one")
+                .arg(Arg::new("name").help("name of the tag to create"))
+                .arg(
+                    Arg::new("revision")
+                        .requires("name")
+                        .default_value("HEAD")
+                        .help("revision to tag"),
+                ),
+        )
+        .subcommand(
+            Command::new("checkout")
+                .about("Switches to a branch or a commit")
+                .arg(
+                    Arg::new("target")
+                        .required(true)
+                        .help("branch name or commit id"),
+                ),
+        )
+        .subcommand(
+            Command::new("cherry-pick")
+                .about("Applies the changes of the given commits on top of HEAD")
+                .arg(
+                    Arg::new("commits")
+                        .num_args(1..)
+                        .required_unless_present_any(["continue", "skip", "abort"])
+                        .help("revisions or ranges (A..B) to apply"),
+                )
+                .arg(
+                    Arg::new("record-origin")
+                        .short('x')
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with_all(["continue", "skip", "abort"])
+                        .help("Appends a \"(cherry picked from commit ...)\" line to the messages"),
+                )
+                .arg(
+                    Arg::new("continue")
+                        .long("continue")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with_all(["commits", "skip", "abort"])
+                        .help("Commits the resolved conflicts and picks the remaining commits"),
+                )
+                .arg(
+                    Arg::new("skip")
+                        .long("skip")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with_all(["commits", "abort"])
+                        .help("Drops the conflicting commit and picks the remaining commits"),
+                )
+                .arg(
+                    Arg::new("abort")
+                        .long("abort")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("commits")
+                        .help("Cancels the cherry-pick and restores the original HEAD"),
+                ),
+        )
+        .subcommand(
+            Command::new("rebase")
+                .about("Replays the commits of the current branch on top of another one")
+                .arg(
+                    Arg::new("upstream")
+                        .required_unless_present_any(["continue", "skip", "abort"])
+                        .help("branch or commit id to rebase onto"),
+                )
+                .arg(
+                    Arg::new("interactive")
+                        .short('i')
+                        .long("interactive")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with_all(["continue", "skip", "abort"])
+                        .help("Edits the list of commits to replay before starting"),
+                )
+                .arg(
+                    Arg::new("continue")
+                        .long("continue")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with_all(["upstream", "skip", "abort"])
+                        .help("Commits the resolved conflicts or edits and replays the remaining commits"),
+                )
+                .arg(
+                    Arg::new("skip")
+                        .long("skip")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with_all(["upstream", "abort"])
+                        .help("Drops the current commit and replays the remaining commits"),
+                )
+                .arg(
+                    Arg::new("abort")
+                        .long("abort")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("upstream")
+                        .help("Cancels the rebase and restores the original branch"),
+                ),
+        )
+        .subcommand(
+            Command::new("merge")
+                .about("Merges another branch into the current one")
+                .arg(
+                    Arg::new("revision")
+                        .required_unless_present_any(["continue", "abort"])
+                        .help("branch, remote branch or commit to merge"),
+                )
+                .arg(
+                    Arg::new("continue")
+                        .long("continue")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with_all(["revision", "abort"])
+                        .help("Commits the merge once the conflicts are resolved"),
+                )
+                .arg(
+                    Arg::new("abort")
+                        .long("abort")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("revision")
+                        .help("Cancels the merge and restores HEAD"),
+                ),
+        )
+        .subcommand(
+            Command::new("remote")
+                .about("Manages the remote repositories")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("add")
+                        .about("Adds a remote repository")
+                        .arg(Arg::new("name").required(true))
+                        .arg(
+                            Arg::new("url")
+                                .required(true)
+                                .help("S3 bucket, file:// URL or directory of the repository"),
+                        )
+                        .args(remote_settings_args()),
+                )
+                .subcommand(
+                    Command::new("remove")
+                        .about("Removes a remote and its remote branches")
+                        .arg(Arg::new("name").required(true)),
+                )
+                .subcommand(
+                    Command::new("rename")
+                        .about("Renames a remote and its remote branches")
+                        .arg(Arg::new("name").required(true))
+                        .arg(Arg::new("new-name").required(true)),
+                )
+                .subcommand(
+                    Command::new("list")
+                        .about("Lists the remotes")
+                        .arg(
+                            Arg::new("verbose")
+                                .short('v')
+                                .long("verbose")
+                                .action(ArgAction::SetTrue)
+                                .help("Shows the URLs and the branches using each remote"),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("fetch")
+                .about("Downloads the changes and updates the remote branches")
+                .arg(remote_name_arg())
+                .arg(
+                    Arg::new("unshallow")
+                        .long("unshallow")
+                        .action(ArgAction::SetTrue)
+                        .help("Downloads the history left out by a shallow clone"),
+                ),
+        )
+        .subcommand(
+            Command::new("pull")
+                .about("Fetches the changes and merges the remote branch")
+                .arg(remote_name_arg())
+                .arg(
+                    Arg::new("rebase")
+                        .long("rebase")
+                        .action(ArgAction::SetTrue)
+                        .help("Replays the local commits on top of the remote branch instead"),
+                ),
+        )
+        .subcommand(
+            Command::new("push")
+                .about("Syncs the changes to the remote repository")
+                .arg(remote_name_arg())
+                .arg(
+                    Arg::new("set-upstream")
+                        .short('u')
+                        .long("set-upstream")
+                        .action(ArgAction::SetTrue)
+                        .help("Makes the remote the default one of the current branch"),
+                )
+                .arg(
+                    Arg::new("force-with-lease")
+                        .long("force-with-lease")
+                        .value_name("commit")
+                        .num_args(0..=1)
+                        .require_equals(true)
+                        .default_missing_value("")
+                        .help("Rewrites the remote branch if it still points at the given commit, by default the one of the remote branch"),
+                ),
+        )
+        .subcommand(
+            Command::new("bisect")
+                .about("Finds the commit that introduced a bug with a binary search")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("start")
+                        .about("Starts bisecting, optionally with a bad and good commits")
+                        .arg(Arg::new("bad").help("revision known to be bad"))
+                        .arg(
+                            Arg::new("good")
+                                .num_args(1..)
+                                .help("revisions known to be good"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("good")
+                        .about("Marks a commit as good")
+                        .arg(Arg::new("revision").default_value("HEAD")),
+                )
+                .subcommand(
+                    Command::new("bad")
+                        .about("Marks a commit as bad")
+                        .arg(Arg::new("revision").default_value("HEAD")),
+                )
+                .subcommand(
+                    Command::new("skip")
+                        .about("Marks a commit as impossible to test")
+                        .arg(Arg::new("revision").default_value("HEAD")),
+                )
+                .subcommand(
+                    Command::new("reset")
+                        .about("Stops bisecting and checks the original HEAD out"),
+                )
+                .subcommand(
+                    Command::new("run")
+                        .about("Bisects automatically with the exit status of a command")
+                        .arg(
+                            Arg::new("command")
+                                .required(true)
+                                .num_args(1..)
+                                .trailing_var_arg(true)
+                                .allow_hyphen_values(true)
+                                .help("command exiting with 0 on good commits, 125 to skip"),
+                        ),
                 ),
         )
-        .subcommand(Command::new("pull").about("Pulls the changes"))
-        .subcommand(Command::new("push").about("Syncs the changes to the remote repository"))
+        .subcommand(
+            Command::new("lfs")
+                .about("Manages files tracked with the patterns in .lfs")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("fetch")
+                        .about("Downloads the LFS files of the given paths")
+                        .arg(
+                            Arg::new("paths")
+                                .required(true)
+                                .num_args(1..)
+                                .help("paths of the LFS files"),
+                        )
+                        .arg(
+                            Arg::new("remote")
+                                .short('r')
+                                .long("remote")
+                                .help("remote to download from, by default the one of the branch"),
+                        ),
+                ),
+        )
+}
+
+fn remote_name_arg() -> Arg {
+    Arg::new("remote").help("name of the remote, by default the one of the current branch")
+}
+
+fn remote_settings_args() -> Vec<Arg> {
+    vec![
+        Arg::new("endpoint-url")
+            .long("endpoint-url")
+            .help("S3 endpoint, for instance http://localhost:9000 for MinIO"),
+        Arg::new("region").long("region").help("S3 region"),
+        Arg::new("path-style")
+            .long("path-style")
+            .action(ArgAction::SetTrue)
+            .help("Addresses buckets in the path instead of the host name"),
+        Arg::new("profile")
+            .long("profile")
+            .help("Named profile of the AWS credentials and config files"),
+        Arg::new("encrypt")
+            .long("encrypt")
+            .action(ArgAction::SetTrue)
+            .help("Encrypts the contents with a key derived from the RGIT_PASSPHRASE passphrase"),
+        Arg::new("key-file")
+            .long("key-file")
+            .help("Encrypts the contents with a key derived from this file instead"),
+    ]
+}
+
+fn remote_config(url: &str, sub_matches: &ArgMatches) -> RemoteConfig {
+    RemoteConfig {
+        url: url.to_owned(),
+        endpoint_url: sub_matches.get_one::<String>("endpoint-url").cloned(),
+        region: sub_matches.get_one::<String>("region").cloned(),
+        path_style: sub_matches.get_flag("path-style"),
+        profile: sub_matches.get_one::<String>("profile").cloned(),
+        encrypted: sub_matches.get_flag("encrypt") || sub_matches.contains_id("key-file"),
+        key_file: sub_matches.get_one::<String>("key-file").cloned(),
+        branches: Vec::new(),
+        no_lfs: false,
+    }
 }
 
 #[::tokio::main]
 async fn main() {
-    let config = aws_config::load_from_env().await;
-    let client = aws_sdk_s3::Client::new(&config);
-
     let matches = cli().get_matches();
 
     match matches.subcommand() {
@@ -59,34 +666,312 @@ async fn main() {
         }
         Some(("commit", sub_matches)) => {
             let description = sub_matches.get_one::<String>("description");
-            println!("Committing the changes");
-            handlers::commands::commit(description.unwrap_or(&"".to_owned())).unwrap();
+            if sub_matches.get_flag("amend") {
+                println!("Amending the last commit");
+                let force = sub_matches.get_flag("force");
+                handlers::commands::commit_amend(description.map(|d| d.as_str()), force).unwrap();
+            } else {
+                println!("Committing the changes");
+                handlers::commands::commit(description.unwrap_or(&"".to_owned())).unwrap();
+            }
         }
         Some(("view", sub_matches)) => {
             let id = sub_matches.get_one::<String>("id");
-            handlers::commands::view(&id.unwrap_or(&"".to_owned())).unwrap();
+            handlers::commands::view(id.unwrap_or(&"".to_owned())).unwrap();
+        }
+        Some(("commits", sub_matches)) => {
+            let range = sub_matches.get_one::<String>("range");
+            handlers::queries::log_commits(range.unwrap_or(&"HEAD".to_owned())).unwrap();
+        }
+        Some(("show", sub_matches)) => {
+            let revision = sub_matches.get_one::<String>("revision");
+            handlers::queries::show(revision.unwrap_or(&"HEAD".to_owned())).unwrap();
+        }
+        Some(("diff", sub_matches)) => {
+            let revision = sub_matches.get_one::<String>("revision");
+            handlers::queries::diff(
+                revision.unwrap_or(&"HEAD".to_owned()),
+                sub_matches
+                    .get_one::<String>("other")
+                    .map(|other| other.as_str()),
+            )
+            .unwrap();
+        }
+        Some(("cat-file", sub_matches)) => {
+            let object = sub_matches.get_one::<String>("object");
+            handlers::queries::cat_file(
+                object.unwrap_or(&"".to_owned()),
+                sub_matches.get_flag("type"),
+                sub_matches.get_flag("size"),
+            )
+            .unwrap();
+        }
+        Some(("blame", sub_matches)) => {
+            let path = sub_matches.get_one::<String>("path");
+            let revision = sub_matches.get_one::<String>("revision");
+            handlers::queries::blame(
+                path.unwrap_or(&"".to_owned()),
+                revision.unwrap_or(&"HEAD".to_owned()),
+                sub_matches
+                    .get_one::<String>("lines")
+                    .map(|lines| lines.as_str()),
+                sub_matches.get_flag("ignore-whitespace"),
+                sub_matches.get_flag("follow"),
+            )
+            .unwrap();
+        }
+        Some(("grep", sub_matches)) => {
+            let pattern = sub_matches.get_one::<String>("pattern");
+            let revision = sub_matches.get_one::<String>("revision");
+            let paths: Vec<String> = sub_matches
+                .get_many::<String>("paths")
+                .unwrap_or_default()
+                .cloned()
+                .collect();
+            let context = sub_matches.get_one::<usize>("context").copied();
+            let options = GrepOptions {
+                line_numbers: sub_matches.get_flag("line-number"),
+                files_with_matches: sub_matches.get_flag("files-with-matches"),
+                count: sub_matches.get_flag("count"),
+                before_context: sub_matches
+                    .get_one::<usize>("before-context")
+                    .copied()
+                    .or(context)
+                    .unwrap_or_default(),
+                after_context: sub_matches
+                    .get_one::<usize>("after-context")
+                    .copied()
+                    .or(context)
+                    .unwrap_or_default(),
+            };
+            handlers::queries::grep(
+                pattern.unwrap_or(&"".to_owned()),
+                revision.map(|revision| revision.as_str()),
+                &paths,
+                sub_matches.get_flag("ignore-case"),
+                &options,
+            )
+            .unwrap();
+        }
+        Some(("reflog", sub_matches)) => {
+            let ref_name = sub_matches.get_one::<String>("ref");
+            handlers::queries::show_reflog(ref_name.unwrap_or(&"HEAD".to_owned())).unwrap();
+        }
+        Some(("reset", sub_matches)) => {
+            let commit_id = sub_matches.get_one::<String>("commit");
+            let mode = if sub_matches.get_flag("hard") {
+                ResetMode::Hard
+            } else if sub_matches.get_flag("soft") {
+                ResetMode::Soft
+            } else {
+                ResetMode::Mixed
+            };
+            handlers::commands::reset(commit_id.unwrap_or(&"".to_owned()), mode).unwrap();
+        }
+        Some(("restore", sub_matches)) => {
+            let source = sub_matches.get_one::<String>("source");
+            let paths: Vec<String> = sub_matches
+                .get_many::<String>("paths")
+                .unwrap()
+                .cloned()
+                .collect();
+            handlers::commands::restore(
+                source.unwrap_or(&"HEAD".to_owned()),
+                &paths,
+                sub_matches.get_flag("staged"),
+            )
+            .unwrap();
         }
-        Some(("log-commits", _)) => {
-            handlers::queries::log_commits().unwrap();
+        Some(("revert", sub_matches)) => {
+            if sub_matches.get_flag("continue") {
+                handlers::commands::revert_continue().unwrap();
+            } else if sub_matches.get_flag("abort") {
+                handlers::commands::revert_abort().unwrap();
+            } else {
+                let commit_id = sub_matches.get_one::<String>("commit");
+                handlers::commands::revert(commit_id.unwrap_or(&"".to_owned())).unwrap();
+            }
+        }
+        Some(("branch", sub_matches)) => match sub_matches.get_one::<String>("name") {
+            Some(name) => {
+                let start = sub_matches.get_one::<String>("start");
+                handlers::commands::create_branch(name, start.map(|start| start.as_str())).unwrap();
+            }
+            None => handlers::queries::list_branch_names().unwrap(),
+        },
+        Some(("tag", sub_matches)) => match sub_matches.get_one::<String>("name") {
+            Some(name) => {
+                let revision = sub_matches.get_one::<String>("revision");
+                handlers::commands::create_tag(name, revision.unwrap_or(&"HEAD".to_owned()))
+                    .unwrap();
+            }
+            None => handlers::queries::list_tags().unwrap(),
+        },
+        Some(("checkout", sub_matches)) => {
+            let target = sub_matches.get_one::<String>("target");
+            handlers::commands::checkout(target.unwrap_or(&"".to_owned())).unwrap();
+        }
+        Some(("cherry-pick", sub_matches)) => {
+            if sub_matches.get_flag("continue") {
+                handlers::commands::sequencer_continue(SequencerOperation::CherryPick).unwrap();
+            } else if sub_matches.get_flag("skip") {
+                handlers::commands::sequencer_skip(SequencerOperation::CherryPick).unwrap();
+            } else if sub_matches.get_flag("abort") {
+                handlers::commands::sequencer_abort(SequencerOperation::CherryPick).unwrap();
+            } else {
+                let commit_ids: Vec<String> = sub_matches
+                    .get_many::<String>("commits")
+                    .unwrap_or_default()
+                    .cloned()
+                    .collect();
+                let record_origin = sub_matches.get_flag("record-origin");
+                handlers::commands::cherry_pick(&commit_ids, record_origin).unwrap();
+            }
+        }
+        Some(("rebase", sub_matches)) => {
+            if sub_matches.get_flag("continue") {
+                handlers::commands::sequencer_continue(SequencerOperation::Rebase).unwrap();
+            } else if sub_matches.get_flag("skip") {
+                handlers::commands::sequencer_skip(SequencerOperation::Rebase).unwrap();
+            } else if sub_matches.get_flag("abort") {
+                handlers::commands::sequencer_abort(SequencerOperation::Rebase).unwrap();
+            } else {
+                let upstream = sub_matches.get_one::<String>("upstream");
+                let interactive = sub_matches.get_flag("interactive");
+                handlers::commands::rebase(upstream.unwrap_or(&"".to_owned()), interactive)
+                    .unwrap();
+            }
         }
         Some(("clone", sub_matches)) => {
-            let bucket_name = sub_matches.get_one::<String>("bucket");
-            handlers::commands::clone(&client, bucket_name.unwrap_or(&"".to_owned()))
+            let fetch_lfs = !sub_matches.get_flag("no-lfs");
+            if sub_matches.get_flag("continue") {
+                handlers::commands::clone_continue(fetch_lfs).await.unwrap();
+            } else {
+                let bucket = sub_matches.get_one::<String>("bucket").unwrap();
+                let depth = sub_matches.get_one::<usize>("depth").copied();
+                let clone_state = CloneState {
+                    depth,
+                    branch: sub_matches.get_one::<String>("branch").cloned(),
+                    single_branch: sub_matches.get_flag("single-branch")
+                        || (depth.is_some() && !sub_matches.get_flag("no-single-branch")),
+                };
+                handlers::commands::clone(
+                    &remote_config(bucket, sub_matches),
+                    fetch_lfs,
+                    &clone_state,
+                )
+                .await
+                .unwrap();
+            }
+        }
+        Some(("fetch", sub_matches)) => {
+            let remote = sub_matches.get_one::<String>("remote");
+            let unshallow = sub_matches.get_flag("unshallow");
+            handlers::commands::fetch(remote.map(|remote| remote.as_str()), unshallow)
+                .await
+                .unwrap();
+        }
+        Some(("pull", sub_matches)) => {
+            let remote = sub_matches.get_one::<String>("remote");
+            let rebase = sub_matches.get_flag("rebase");
+            handlers::commands::pull(remote.map(|remote| remote.as_str()), rebase)
                 .await
                 .unwrap();
         }
-        Some(("pull", _)) => {
-            // TODO: Currently it will overwrite the local unpushed commits
-            handlers::commands::pull(&client).await.unwrap();
+        Some(("merge", sub_matches)) => {
+            if sub_matches.get_flag("continue") {
+                handlers::commands::merge_continue().unwrap();
+            } else if sub_matches.get_flag("abort") {
+                handlers::commands::merge_abort().unwrap();
+            } else {
+                let revision = sub_matches.get_one::<String>("revision");
+                handlers::commands::merge(revision.unwrap_or(&"".to_owned())).unwrap();
+            }
         }
-        Some(("push", _)) => {
-            // TODO: Currently it will only push if there are no pushed commits in the remote repository that is not in the local repository
-            handlers::commands::push(&client).await.unwrap();
+        Some(("push", sub_matches)) => {
+            let remote = sub_matches.get_one::<String>("remote");
+            let set_upstream = sub_matches.get_flag("set-upstream");
+            // An empty value stands for the flag given without a commit
+            let force_with_lease = sub_matches
+                .get_one::<String>("force-with-lease")
+                .map(|expected| (!expected.is_empty()).then_some(expected.as_str()));
+            handlers::commands::push(
+                remote.map(|remote| remote.as_str()),
+                set_upstream,
+                force_with_lease,
+            )
+            .await
+            .unwrap();
         }
         Some(("set-remote", sub_matches)) => {
-            let bucket_name = sub_matches.get_one::<String>("bucket");
-            handlers::commands::set_remote(bucket_name.unwrap_or(&"".to_owned())).unwrap();
+            let bucket = sub_matches.get_one::<String>("bucket").unwrap();
+            handlers::commands::set_remote(&remote_config(bucket, sub_matches)).unwrap();
         }
+        Some(("remote", sub_matches)) => match sub_matches.subcommand() {
+            Some(("add", sub_matches)) => {
+                let name = sub_matches.get_one::<String>("name").unwrap();
+                let url = sub_matches.get_one::<String>("url").unwrap();
+                handlers::commands::remote_add(name, &remote_config(url, sub_matches)).unwrap();
+            }
+            Some(("remove", sub_matches)) => {
+                let name = sub_matches.get_one::<String>("name").unwrap();
+                handlers::commands::remote_remove(name).unwrap();
+            }
+            Some(("rename", sub_matches)) => {
+                let name = sub_matches.get_one::<String>("name").unwrap();
+                let new_name = sub_matches.get_one::<String>("new-name").unwrap();
+                handlers::commands::remote_rename(name, new_name).unwrap();
+            }
+            Some(("list", sub_matches)) => {
+                handlers::queries::list_remotes(sub_matches.get_flag("verbose")).unwrap();
+            }
+            _ => unreachable!(),
+        },
+        Some(("bisect", sub_matches)) => match sub_matches.subcommand() {
+            Some(("start", sub_matches)) => {
+                let bad = sub_matches.get_one::<String>("bad");
+                let good: Vec<String> = sub_matches
+                    .get_many::<String>("good")
+                    .unwrap_or_default()
+                    .cloned()
+                    .collect();
+                handlers::commands::bisect_start(bad.map(|bad| bad.as_str()), &good).unwrap();
+            }
+            Some(("reset", _)) => handlers::commands::bisect_reset().unwrap(),
+            Some(("run", sub_matches)) => {
+                let command: Vec<String> = sub_matches
+                    .get_many::<String>("command")
+                    .unwrap_or_default()
+                    .cloned()
+                    .collect();
+                handlers::commands::bisect_run(&command).unwrap();
+            }
+            Some((mark, sub_matches)) => {
+                let mark = match mark {
+                    "good" => BisectMark::Good,
+                    "bad" => BisectMark::Bad,
+                    _ => BisectMark::Skip,
+                };
+                let revision = sub_matches.get_one::<String>("revision");
+                handlers::commands::bisect_mark(mark, revision.unwrap_or(&"HEAD".to_owned()))
+                    .unwrap();
+            }
+            _ => unreachable!(),
+        },
+        Some(("lfs", sub_matches)) => match sub_matches.subcommand() {
+            Some(("fetch", sub_matches)) => {
+                let paths: Vec<String> = sub_matches
+                    .get_many::<String>("paths")
+                    .unwrap_or_default()
+                    .cloned()
+                    .collect();
+                let remote = sub_matches.get_one::<String>("remote");
+                handlers::commands::lfs_fetch(&paths, remote.map(|remote| remote.as_str()))
+                    .await
+                    .unwrap();
+            }
+            _ => unreachable!(),
+        },
         _ => unreachable!(),
     }
 }
@@ -0,0 +1,100 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+
+use super::revisions::reachable_commits;
+use super::types::{BisectState, Commit};
+
+pub enum BisectStep {
+    // Both a good and a bad commit are needed before searching
+    Waiting,
+    Test { commit_id: String, remaining: usize },
+    Found(String),
+    // The first bad commit is one of these, all but the bad one being skipped
+    OnlySkipped(Vec<String>),
+}
+
+// Number of candidates reachable from the commit, walking only through
+// candidates since everything else is known to be good.
+fn count_reachable_candidates(
+    commits_by_id: &HashMap<&str, &Commit>,
+    candidates: &HashSet<&str>,
+    commit_id: &str,
+) -> usize {
+    let mut visited = HashSet::new();
+    let mut pending = vec![commit_id];
+    while let Some(commit_id) = pending.pop() {
+        if !candidates.contains(commit_id) || !visited.insert(commit_id) {
+            continue;
+        }
+        if let Some(commit) = commits_by_id.get(commit_id) {
+            pending.extend(commit.parents.iter().map(|parent| parent.as_str()));
+        }
+    }
+
+    visited.len()
+}
+
+// The first bad commit is reachable from the bad commit and from none of the
+// good ones. The next commit to test is the one splitting these candidates
+// closest to halves, which works on merges as well as on linear histories.
+pub fn next_bisect_step(commits: &[Commit], bisect_state: &BisectState) -> io::Result<BisectStep> {
+    let bad = match &bisect_state.bad {
+        Some(bad) if !bisect_state.good.is_empty() => bad,
+        _ => return Ok(BisectStep::Waiting),
+    };
+
+    let good_reachable = reachable_commits(commits, &bisect_state.good);
+    if good_reachable.contains(bad) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("The bad commit {} is an ancestor of a good commit", bad),
+        ));
+    }
+    let bad_reachable = reachable_commits(commits, std::slice::from_ref(bad));
+    let candidates: Vec<&str> = commits
+        .iter()
+        .map(|commit| commit.commit_id.as_str())
+        .filter(|commit_id| {
+            bad_reachable.contains(*commit_id) && !good_reachable.contains(*commit_id)
+        })
+        .collect();
+
+    let testable: Vec<&str> = candidates
+        .iter()
+        .copied()
+        .filter(|commit_id| {
+            commit_id != bad && !bisect_state.skipped.iter().any(|s| s == commit_id)
+        })
+        .collect();
+    if testable.is_empty() {
+        if candidates.len() == 1 {
+            return Ok(BisectStep::Found(bad.clone()));
+        }
+        return Ok(BisectStep::OnlySkipped(
+            candidates
+                .iter()
+                .map(|commit_id| commit_id.to_string())
+                .collect(),
+        ));
+    }
+
+    let commits_by_id: HashMap<&str, &Commit> = commits
+        .iter()
+        .map(|commit| (commit.commit_id.as_str(), commit))
+        .collect();
+    let candidate_set: HashSet<&str> = candidates.iter().copied().collect();
+    let mut best: Option<(usize, &str)> = None;
+    for commit_id in testable {
+        let reachable_count = count_reachable_candidates(&commits_by_id, &candidate_set, commit_id);
+        let score = reachable_count.min(candidates.len() - reachable_count);
+        if best.is_none_or(|(best_score, _)| score > best_score) {
+            best = Some((score, commit_id));
+        }
+    }
+
+    let (score, commit_id) = best.unwrap();
+    Ok(BisectStep::Test {
+        commit_id: commit_id.to_owned(),
+        remaining: candidates.len() - score - 1,
+    })
+}
@@ -0,0 +1,164 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::history_paths::normalize_path;
+use super::merge::{diff, split_lines, DiffLine};
+use super::patch::is_binary;
+use super::types::{BlameLine, Commit, TreeEntry};
+use super::{commit_history, load_commit_tree, load_tree_entry, read_stored_contents};
+
+fn read_lines(entry: &TreeEntry) -> io::Result<Option<Vec<String>>> {
+    let contents = read_stored_contents(entry)?;
+    if is_binary(&contents) {
+        return Ok(None);
+    }
+
+    Ok(Some(
+        split_lines(&String::from_utf8_lossy(&contents))
+            .into_iter()
+            .map(|line| line.to_owned())
+            .collect(),
+    ))
+}
+
+fn comparison_keys(lines: &[String], ignore_whitespace: bool) -> Vec<String> {
+    lines
+        .iter()
+        .map(|line| {
+            if ignore_whitespace {
+                line.chars().filter(|c| !c.is_whitespace()).collect()
+            } else {
+                line.clone()
+            }
+        })
+        .collect()
+}
+
+// A file the parent has and the commit does not is taken as the previous name
+// of the blamed file when at least half of its lines come from it.
+fn find_renamed_file(
+    parent_commit_id: &str,
+    commit_id: &str,
+    keys: &[String],
+    ignore_whitespace: bool,
+) -> io::Result<Option<(PathBuf, TreeEntry)>> {
+    let tree = load_commit_tree(commit_id)?;
+    let mut best_match: Option<(usize, PathBuf, TreeEntry)> = None;
+
+    for (path, entry) in load_commit_tree(parent_commit_id)? {
+        if tree.contains_key(&path) {
+            continue;
+        }
+        let parent_keys = match read_lines(&entry)? {
+            Some(lines) => comparison_keys(&lines, ignore_whitespace),
+            None => continue,
+        };
+        let common_lines = diff(&parent_keys, keys)
+            .iter()
+            .filter(|edit| matches!(edit, DiffLine::Equal(_, _)))
+            .count();
+        if common_lines * 2 >= keys.len()
+            && common_lines > 0
+            && best_match
+                .as_ref()
+                .is_none_or(|(best_common_lines, _, _)| common_lines > *best_common_lines)
+        {
+            best_match = Some((common_lines, path, entry));
+        }
+    }
+
+    Ok(best_match.map(|(_, path, entry)| (path, entry)))
+}
+
+// Walks the first-parent history of the file from the given commit, diffing
+// each version against the previous one. Lines are attributed to the commit
+// where they stop matching a line of the parent version.
+pub fn blame_file(
+    commits: &[Commit],
+    commit_id: &str,
+    path: &Path,
+    ignore_whitespace: bool,
+    follow_renames: bool,
+) -> io::Result<Vec<BlameLine>> {
+    let mut path = normalize_path(path);
+    let entry = load_tree_entry(commit_id, &path)?.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} does not exist in {}", path.display(), commit_id),
+        )
+    })?;
+    let lines = read_lines(&entry)?.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Cannot blame binary file {}", path.display()),
+        )
+    })?;
+
+    let mut blamed_commit_ids: Vec<Option<String>> = vec![None; lines.len()];
+    // Pairs of a line of the blamed version and its index in the version
+    // being looked at
+    let mut tracked_lines: Vec<(usize, usize)> = (0..lines.len()).map(|i| (i, i)).collect();
+    let mut keys = comparison_keys(&lines, ignore_whitespace);
+
+    let history = commit_history(commits, commit_id);
+    for (index, commit) in history.iter().enumerate().rev() {
+        if tracked_lines.is_empty() {
+            break;
+        }
+
+        let parent_file = match index.checked_sub(1).map(|i| &history[i]) {
+            Some(parent) => match load_tree_entry(&parent.commit_id, &path)? {
+                Some(parent_entry) => Some((path.clone(), parent_entry)),
+                None if follow_renames => find_renamed_file(
+                    &parent.commit_id,
+                    &commit.commit_id,
+                    &keys,
+                    ignore_whitespace,
+                )?,
+                None => None,
+            },
+            None => None,
+        };
+        let parent_lines = match &parent_file {
+            Some((_, parent_entry)) => read_lines(parent_entry)?,
+            None => None,
+        };
+        let (parent_path, parent_lines) = match (parent_file, parent_lines) {
+            (Some((parent_path, _)), Some(parent_lines)) => (parent_path, parent_lines),
+            _ => {
+                for (line_index, _) in tracked_lines.drain(..) {
+                    blamed_commit_ids[line_index] = Some(commit.commit_id.clone());
+                }
+                break;
+            }
+        };
+
+        let parent_keys = comparison_keys(&parent_lines, ignore_whitespace);
+        let mut parent_indexes = vec![None; keys.len()];
+        for edit in diff(&parent_keys, &keys) {
+            if let DiffLine::Equal(parent_index, current_index) = edit {
+                parent_indexes[current_index] = Some(parent_index);
+            }
+        }
+
+        let mut parent_tracked_lines = Vec::new();
+        for (line_index, current_index) in tracked_lines {
+            match parent_indexes[current_index] {
+                Some(parent_index) => parent_tracked_lines.push((line_index, parent_index)),
+                None => blamed_commit_ids[line_index] = Some(commit.commit_id.clone()),
+            }
+        }
+        tracked_lines = parent_tracked_lines;
+        keys = parent_keys;
+        path = parent_path;
+    }
+
+    Ok(lines
+        .into_iter()
+        .zip(blamed_commit_ids)
+        .map(|(contents, blamed_commit_id)| BlameLine {
+            commit_id: blamed_commit_id.unwrap_or_else(|| commit_id.to_owned()),
+            contents,
+        })
+        .collect())
+}
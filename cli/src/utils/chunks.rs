@@ -0,0 +1,176 @@
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Write};
+use std::path::Path;
+
+use super::constants::{CHUNK_BOUNDARY_MASK, CHUNK_MAX_SIZE, CHUNK_MIN_SIZE, OBJECTS_DIR};
+
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+const GEAR: [u64; 256] = gear_table();
+
+// Finds content-defined chunk boundaries with a gear rolling hash, one byte at
+// a time.
+#[derive(Default)]
+struct ChunkBoundaries {
+    hash: u64,
+    chunk_len: usize,
+}
+
+impl ChunkBoundaries {
+    // Whether the chunk ends after this byte.
+    fn push(&mut self, byte: u8) -> bool {
+        self.chunk_len += 1;
+        self.hash = (self.hash << 1).wrapping_add(GEAR[byte as usize]);
+
+        let at_boundary = self.chunk_len >= CHUNK_MIN_SIZE && self.hash & CHUNK_BOUNDARY_MASK == 0;
+        if at_boundary || self.chunk_len >= CHUNK_MAX_SIZE {
+            *self = ChunkBoundaries::default();
+            return true;
+        }
+
+        false
+    }
+}
+
+pub fn object_path(object_id: &str) -> String {
+    OBJECTS_DIR.to_owned() + "/" + &object_id[..2] + "/" + &object_id[2..]
+}
+
+pub fn store_object(data: &[u8]) -> io::Result<String> {
+    let object_id = hex::encode(Sha256::digest(data));
+    let path = object_path(&object_id);
+    if Path::new(&path).exists() {
+        return Ok(object_id);
+    }
+
+    fs::create_dir_all(Path::new(&path).parent().unwrap())?;
+    let temp_path = path.clone() + ".tmp";
+    File::create(&temp_path)?.write_all(data)?;
+    fs::rename(temp_path, path)?;
+
+    Ok(object_id)
+}
+
+// Splits the file on content-defined boundaries, so an edit only changes the
+// chunks around it. At most one chunk is held in memory at a time.
+pub fn store_file_chunks(file_path: &Path) -> io::Result<Vec<String>> {
+    let mut reader = BufReader::new(File::open(file_path)?);
+    let mut buffer = vec![0; 64 * 1024];
+    let mut chunk = Vec::with_capacity(CHUNK_MAX_SIZE);
+    let mut chunks = Vec::new();
+    let mut boundaries = ChunkBoundaries::default();
+
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+
+        for &byte in &buffer[..read] {
+            chunk.push(byte);
+            if boundaries.push(byte) {
+                chunks.push(store_object(&chunk)?);
+                chunk.clear();
+            }
+        }
+    }
+
+    if !chunk.is_empty() {
+        chunks.push(store_object(&chunk)?);
+    }
+
+    Ok(chunks)
+}
+
+pub fn copy_chunks(chunks: &[String], destination: &mut impl Write) -> io::Result<u64> {
+    let mut copied = 0;
+    for chunk in chunks {
+        let mut chunk_file = File::open(object_path(chunk))?;
+        copied += io::copy(&mut chunk_file, destination)?;
+    }
+
+    Ok(copied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{RngCore, SeedableRng};
+
+    fn random_bytes(len: usize, seed: u64) -> Vec<u8> {
+        let mut bytes = vec![0; len];
+        StdRng::seed_from_u64(seed).fill_bytes(&mut bytes);
+        bytes
+    }
+
+    fn chunk_lengths(data: &[u8]) -> Vec<usize> {
+        let mut boundaries = ChunkBoundaries::default();
+        let mut lengths = Vec::new();
+        let mut chunk_len = 0;
+        for &byte in data {
+            chunk_len += 1;
+            if boundaries.push(byte) {
+                lengths.push(chunk_len);
+                chunk_len = 0;
+            }
+        }
+        if chunk_len > 0 {
+            lengths.push(chunk_len);
+        }
+        lengths
+    }
+
+    #[test]
+    fn chunks_stay_within_size_limits() {
+        let data = random_bytes(4 * CHUNK_MAX_SIZE, 1);
+        let lengths = chunk_lengths(&data);
+
+        assert!(lengths.len() > 1);
+        assert_eq!(lengths.iter().sum::<usize>(), data.len());
+        for &len in &lengths[..lengths.len() - 1] {
+            assert!((CHUNK_MIN_SIZE..=CHUNK_MAX_SIZE).contains(&len));
+        }
+    }
+
+    #[test]
+    fn small_files_are_one_chunk() {
+        assert_eq!(
+            chunk_lengths(&random_bytes(CHUNK_MIN_SIZE, 2)),
+            [CHUNK_MIN_SIZE]
+        );
+        assert!(chunk_lengths(&[]).is_empty());
+    }
+
+    #[test]
+    fn chunks_without_boundaries_are_cut_at_max_size() {
+        let data = vec![0; 2 * CHUNK_MAX_SIZE + 1];
+        assert_eq!(chunk_lengths(&data), [CHUNK_MAX_SIZE, CHUNK_MAX_SIZE, 1]);
+    }
+
+    #[test]
+    fn insertion_only_changes_nearby_boundaries() {
+        let data = random_bytes(4 * CHUNK_MAX_SIZE, 3);
+        let mut edited = data.clone();
+        edited.splice(1000..1000, *b"inserted");
+
+        let lengths = chunk_lengths(&data);
+        let edited_lengths = chunk_lengths(&edited);
+        assert_eq!(edited_lengths[0], lengths[0] + 8);
+        assert_eq!(edited_lengths[1..], lengths[1..]);
+    }
+}
@@ -1,5 +1,49 @@
 pub const VSM_DIR: &str = ".history";
 pub const MAIN_COMMITS_METADATA_FILE_PATH: &str = ".history/commits.json";
 pub const IGNORE_FILES_PATH: &str = ".ignore";
-pub const COMMIT_METADATA_RELATIVE_PATH: &str = ".ignore";
+pub const LFS_PATTERNS_PATH: &str = ".lfs";
+pub const COMMIT_METADATA_RELATIVE_PATH: &str = "/metadata.json";
+pub const LEGACY_COMMIT_METADATA_RELATIVE_PATH: &str = ".ignore";
+pub const DATA_RELATIVE_PATH: &str = "/data.bin";
+pub const HISTORY_PATH_RELATIVE_PATH: &str = "/path";
+pub const FILES_HISTORY_DIR: &str = ".history/files";
+pub const OBJECTS_DIR: &str = ".history/objects";
+pub const LFS_DIR: &str = ".history/lfs";
+pub const LFS_REMOTE_PREFIX: &str = "lfs/";
+pub const PUSH_LOCK_KEY: &str = "locks/push";
+pub const PUSH_LOCK_EXPIRY_SECS: i64 = 60 * 60;
+pub const TRANSFER_CONCURRENCY: usize = 8;
+pub const TRANSFER_MAX_ATTEMPTS: u32 = 5;
+pub const TRANSFER_RETRY_DELAY_MS: u64 = 200;
+pub const MULTIPART_THRESHOLD: u64 = 16 * 1024 * 1024;
+pub const MULTIPART_PART_SIZE: u64 = 8 * 1024 * 1024;
+pub const LFS_POINTER_VERSION: &str = "version rgit-lfs/1";
+pub const LARGE_FILE_THRESHOLD: u64 = 4 * 1024 * 1024;
+pub const CHUNK_MIN_SIZE: usize = 256 * 1024;
+pub const CHUNK_MAX_SIZE: usize = 4 * 1024 * 1024;
+pub const CHUNK_BOUNDARY_MASK: u64 = ((1 << 20) - 1) << 44;
 pub const REMOTE_REPOSITORY_REFERENCE_FILE_PATH: &str = ".history/remote";
+pub const REMOTES_CONFIG_FILE_PATH: &str = ".history/remotes.json";
+pub const HEAD_FILE_PATH: &str = ".history/HEAD";
+pub const REFS_DIR: &str = ".history/refs";
+pub const LOGS_DIR: &str = ".history/logs";
+pub const HEADS_REFS_PREFIX: &str = "refs/heads/";
+pub const REMOTES_REFS_PREFIX: &str = "refs/remotes/";
+pub const TAGS_REFS_PREFIX: &str = "refs/tags/";
+pub const DEFAULT_REMOTE: &str = "origin";
+pub const SYMBOLIC_REF_PREFIX: &str = "ref: ";
+pub const DEFAULT_BRANCH: &str = "main";
+pub const AUTHOR_ENV_VAR: &str = "RGIT_AUTHOR";
+pub const EDITOR_ENV_VAR: &str = "RGIT_EDITOR";
+pub const PASSPHRASE_ENV_VAR: &str = "RGIT_PASSPHRASE";
+pub const REVERT_STATE_FILE_PATH: &str = ".history/REVERT_STATE";
+pub const MERGE_STATE_FILE_PATH: &str = ".history/MERGE_STATE";
+pub const SEQUENCER_STATE_FILE_PATH: &str = ".history/SEQUENCER_STATE";
+pub const BISECT_STATE_FILE_PATH: &str = ".history/BISECT_STATE";
+pub const REBASE_TODO_FILE_PATH: &str = ".history/REBASE_TODO";
+pub const COMMIT_EDITMSG_FILE_PATH: &str = ".history/COMMIT_EDITMSG";
+pub const CLONE_STATE_FILE_PATH: &str = ".history/CLONE_IN_PROGRESS";
+pub const SHALLOW_FILE_PATH: &str = ".history/shallow";
+pub const FETCH_DIR: &str = ".history/fetch";
+pub const ENCRYPTION_PARAMS_KEY: &str = "encryption.json";
+pub const KEY_DERIVATION_ITERATIONS: u32 = 100_000;
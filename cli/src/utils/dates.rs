@@ -1,6 +1,54 @@
-use chrono::{DateTime, Utc};
+use std::time::Duration as StdDuration;
+
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, Utc};
+
+const DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
 
 pub fn get_current_formatted_date() -> String {
     let now: DateTime<Utc> = Utc::now();
-    now.format("%Y-%m-%d %H:%M:%S").to_string()
+    now.format(DATE_FORMAT).to_string()
+}
+
+pub fn parse_formatted_date(date: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(date, DATE_FORMAT).ok()
+}
+
+// Accepts absolute dates ("2024-01-31", "2024-01-31 12:00:00") and the relative
+// ones used in revisions ("now", "yesterday", "3 days ago", "1.week.ago").
+pub fn parse_approximate_date(date: &str) -> Option<NaiveDateTime> {
+    let date = date.trim().replace('.', " ");
+    let now = Utc::now().naive_utc();
+
+    match date.as_str() {
+        "now" => return Some(now),
+        "yesterday" => return Some(now - Duration::days(1)),
+        _ => {}
+    }
+    if let Some(date_time) = parse_formatted_date(&date) {
+        return Some(date_time);
+    }
+    if let Ok(day) = NaiveDate::parse_from_str(&date, "%Y-%m-%d") {
+        return day.and_hms_opt(0, 0, 0);
+    }
+
+    let words: Vec<&str> = date.split_whitespace().collect();
+    let (count, unit) = match words.as_slice() {
+        [count, unit, "ago"] => (count.parse::<i64>().ok()?, *unit),
+        _ => return None,
+    };
+    let unit_seconds: i64 = match unit.strip_suffix('s').unwrap_or(unit) {
+        "second" => 1,
+        "minute" => 60,
+        "hour" => 60 * 60,
+        "day" => 24 * 60 * 60,
+        "week" => 7 * 24 * 60 * 60,
+        "month" => 30 * 24 * 60 * 60,
+        "year" => 365 * 24 * 60 * 60,
+        _ => return None,
+    };
+
+    // Counts too large to be a date are rejected rather than wrapped
+    let seconds = u64::try_from(count.checked_mul(unit_seconds)?).ok()?;
+    let duration = Duration::from_std(StdDuration::from_secs(seconds)).ok()?;
+    now.checked_sub_signed(duration)
 }
@@ -0,0 +1,770 @@
+use async_trait::async_trait;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, MAX_TAG_LEN, NONCE_LEN};
+use ring::hmac;
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::num::NonZeroU32;
+use std::path::Path;
+
+use super::constants::{
+    ENCRYPTION_PARAMS_KEY, KEY_DERIVATION_ITERATIONS, PASSPHRASE_ENV_VAR, VSM_DIR,
+};
+use super::generate_commit_id;
+use super::remote::{checksum, with_retries, RemoteBackend, RemoteObject};
+use super::types::{EncryptionParams, RemoteConfig};
+
+// Prefix of every encrypted object, followed by its seed and its segments.
+const MAGIC: &[u8] = b"RGITENC2";
+const ALGORITHM: &str = "chacha20-poly1305-segments";
+const KDF: &str = "pbkdf2-hmac-sha256";
+const SALT_LEN: usize = 16;
+const SEED_LEN: usize = 32;
+const HEADER_LEN: usize = MAGIC.len() + SEED_LEN;
+// Contents are encrypted in segments of this size, each with its own tag, so
+// that objects are streamed rather than held in memory.
+const SEGMENT_LEN: usize = 64 * 1024;
+const ENCRYPTED_SEGMENT_LEN: usize = SEGMENT_LEN + MAX_TAG_LEN;
+// Suffix of downloaded ciphertext, removed once decrypted.
+const ENCRYPTED_SUFFIX: &str = ".enc";
+
+struct Keys {
+    cipher: hmac::Key,
+    seed: hmac::Key,
+    name_cipher: LessSafeKey,
+    name_nonce: hmac::Key,
+}
+
+fn read_secret(remote_config: &RemoteConfig) -> io::Result<Vec<u8>> {
+    if let Some(key_file) = &remote_config.key_file {
+        return fs::read(key_file).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Failed to read the key file {}: {}", key_file, e),
+            )
+        });
+    }
+
+    match std::env::var(PASSPHRASE_ENV_VAR) {
+        Ok(passphrase) if !passphrase.is_empty() => Ok(passphrase.into_bytes()),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "The remote is encrypted, set {} to its passphrase or use a key file",
+                PASSPHRASE_ENV_VAR
+            ),
+        )),
+    }
+}
+
+// The master key is stretched from the secret, and separate keys for the
+// contents and the names are derived from it.
+fn derive_keys(secret: &[u8], params: &EncryptionParams) -> io::Result<Keys> {
+    if params.algorithm != ALGORITHM || params.kdf != KDF {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "Unsupported encryption {} with {}",
+                params.algorithm, params.kdf
+            ),
+        ));
+    }
+    let salt = hex::decode(&params.salt)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    let iterations = NonZeroU32::new(params.iterations).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Invalid key derivation iterations",
+        )
+    })?;
+
+    let mut master_key = [0; 32];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        iterations,
+        &salt,
+        secret,
+        &mut master_key,
+    );
+    Ok(keys_from_master_key(&master_key))
+}
+
+fn keys_from_master_key(master_key: &[u8]) -> Keys {
+    let master_key = hmac::Key::new(hmac::HMAC_SHA256, master_key);
+    let derive = |purpose: &[u8]| hmac::sign(&master_key, purpose);
+
+    Keys {
+        cipher: hmac::Key::new(hmac::HMAC_SHA256, derive(b"encryption").as_ref()),
+        seed: hmac::Key::new(hmac::HMAC_SHA256, derive(b"seed").as_ref()),
+        name_cipher: LessSafeKey::new(
+            UnboundKey::new(&CHACHA20_POLY1305, derive(b"name encryption").as_ref()).unwrap(),
+        ),
+        name_nonce: hmac::Key::new(hmac::HMAC_SHA256, derive(b"name nonce").as_ref()),
+    }
+}
+
+fn decryption_failed(key: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "Failed to decrypt {}, the key is wrong or the object was tampered with",
+            key
+        ),
+    )
+}
+
+// Names are encrypted one path component at a time, with a nonce derived from
+// the path up to the component, so that a key always gets the same name, which
+// refs and listings rely on, and a prefix of directories can still be listed.
+fn encrypt_name(keys: &Keys, key: &str) -> String {
+    let mut parent = String::new();
+    let mut names = Vec::new();
+    for component in key.split('/') {
+        let mut context = hmac::Context::with_key(&keys.name_nonce);
+        context.update(parent.as_bytes());
+        context.update(&[0]);
+        context.update(component.as_bytes());
+        let tag = context.sign();
+        let nonce = &tag.as_ref()[..NONCE_LEN];
+
+        let mut sealed = component.as_bytes().to_vec();
+        keys.name_cipher
+            .seal_in_place_append_tag(
+                Nonce::try_assume_unique_for_key(nonce).unwrap(),
+                Aad::from(parent.as_bytes()),
+                &mut sealed,
+            )
+            .unwrap();
+        names.push(hex::encode([nonce, &sealed].concat()));
+
+        parent.push_str(component);
+        parent.push('/');
+    }
+
+    names.join("/")
+}
+
+fn decrypt_name(keys: &Keys, name: &str) -> io::Result<String> {
+    let mut key = String::new();
+    for component_name in name.split('/') {
+        let sealed = hex::decode(component_name).map_err(|_| decryption_failed(name))?;
+        if sealed.len() < NONCE_LEN {
+            return Err(decryption_failed(name));
+        }
+        let (nonce, sealed) = sealed.split_at(NONCE_LEN);
+        let mut component = sealed.to_vec();
+        let component_len = keys
+            .name_cipher
+            .open_in_place(
+                Nonce::try_assume_unique_for_key(nonce).unwrap(),
+                Aad::from(key.as_bytes()),
+                &mut component,
+            )
+            .map_err(|_| decryption_failed(name))?
+            .len();
+        component.truncate(component_len);
+
+        key.push_str(&String::from_utf8(component).map_err(|_| decryption_failed(name))?);
+        key.push('/');
+    }
+    key.pop();
+
+    Ok(key)
+}
+
+// The seed of an object is derived from its key and contents, so the same
+// contents always encrypt to the same bytes: checksums of unchanged files still
+// match the remote ones and refs can be compared before they are replaced.
+fn object_seed(keys: &Keys, key: &str, mut contents: impl Read) -> io::Result<[u8; SEED_LEN]> {
+    let mut context = hmac::Context::with_key(&keys.seed);
+    context.update(key.as_bytes());
+    context.update(&[0]);
+    let mut buffer = vec![0; SEGMENT_LEN];
+    loop {
+        let read = contents.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        context.update(&buffer[..read]);
+    }
+
+    let mut seed = [0; SEED_LEN];
+    seed.copy_from_slice(context.sign().as_ref());
+    Ok(seed)
+}
+
+// Every object is encrypted with its own key derived from its seed, and every
+// segment with its index as nonce, the last one being flagged so that a
+// truncated object does not decrypt. The key of the object is authenticated
+// too, so objects cannot be swapped with one another.
+fn object_cipher(keys: &Keys, seed: &[u8]) -> LessSafeKey {
+    let object_key = hmac::sign(&keys.cipher, seed);
+    LessSafeKey::new(UnboundKey::new(&CHACHA20_POLY1305, &object_key.as_ref()[..32]).unwrap())
+}
+
+fn segment_nonce(index: u64, is_last: bool) -> Nonce {
+    let mut nonce = [0; NONCE_LEN];
+    nonce[..8].copy_from_slice(&index.to_be_bytes());
+    nonce[NONCE_LEN - 1] = is_last as u8;
+    Nonce::assume_unique_for_key(nonce)
+}
+
+fn segments_count(contents_len: u64) -> u64 {
+    contents_len.div_ceil(SEGMENT_LEN as u64).max(1)
+}
+
+fn encrypted_len(contents_len: u64) -> u64 {
+    HEADER_LEN as u64 + contents_len + segments_count(contents_len) * MAX_TAG_LEN as u64
+}
+
+// Ciphertext of an object, encrypted a segment at a time as it is read.
+struct EncryptingReader<R> {
+    cipher: LessSafeKey,
+    key: String,
+    contents: R,
+    remaining_len: u64,
+    index: u64,
+    segments_count: u64,
+    segment: Vec<u8>,
+    position: usize,
+}
+
+impl<R: Read> EncryptingReader<R> {
+    fn new(keys: &Keys, key: &str, seed: &[u8], contents: R, contents_len: u64) -> Self {
+        EncryptingReader {
+            cipher: object_cipher(keys, seed),
+            key: key.to_owned(),
+            contents,
+            remaining_len: contents_len,
+            index: 0,
+            segments_count: segments_count(contents_len),
+            segment: [MAGIC, seed].concat(),
+            position: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for EncryptingReader<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.segment.len() {
+            if self.index == self.segments_count {
+                return Ok(0);
+            }
+            let segment_len = self.remaining_len.min(SEGMENT_LEN as u64) as usize;
+            self.segment.resize(segment_len, 0);
+            self.contents.read_exact(&mut self.segment)?;
+            self.remaining_len -= segment_len as u64;
+            let is_last = self.index + 1 == self.segments_count;
+            self.cipher
+                .seal_in_place_append_tag(
+                    segment_nonce(self.index, is_last),
+                    Aad::from(self.key.as_bytes()),
+                    &mut self.segment,
+                )
+                .unwrap();
+            self.index += 1;
+            self.position = 0;
+        }
+
+        let len = buffer.len().min(self.segment.len() - self.position);
+        buffer[..len].copy_from_slice(&self.segment[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+fn decrypt_to(
+    keys: &Keys,
+    key: &str,
+    mut encrypted: impl Read,
+    encrypted_len: u64,
+    contents: &mut impl Write,
+) -> io::Result<()> {
+    if encrypted_len < (HEADER_LEN + MAX_TAG_LEN) as u64 {
+        return Err(decryption_failed(key));
+    }
+    let mut header = [0; HEADER_LEN];
+    encrypted.read_exact(&mut header)?;
+    let seed = header
+        .strip_prefix(MAGIC)
+        .ok_or_else(|| decryption_failed(key))?;
+    let cipher = object_cipher(keys, seed);
+
+    let mut remaining_len = encrypted_len - HEADER_LEN as u64;
+    let segments_count = remaining_len.div_ceil(ENCRYPTED_SEGMENT_LEN as u64);
+    let mut segment = Vec::with_capacity(ENCRYPTED_SEGMENT_LEN);
+    for index in 0..segments_count {
+        let segment_len = remaining_len.min(ENCRYPTED_SEGMENT_LEN as u64) as usize;
+        if segment_len < MAX_TAG_LEN {
+            return Err(decryption_failed(key));
+        }
+        segment.resize(segment_len, 0);
+        encrypted.read_exact(&mut segment)?;
+        remaining_len -= segment_len as u64;
+
+        let plaintext = cipher
+            .open_in_place(
+                segment_nonce(index, index + 1 == segments_count),
+                Aad::from(key.as_bytes()),
+                &mut segment,
+            )
+            .map_err(|_| decryption_failed(key))?;
+        contents.write_all(plaintext)?;
+    }
+
+    Ok(())
+}
+
+// Segments read alone are not known to be the last one unless they are shorter
+// than a full one, so a full one is tried both ways.
+fn decrypt_segment(
+    cipher: &LessSafeKey,
+    key: &str,
+    index: u64,
+    segment: &[u8],
+) -> io::Result<Vec<u8>> {
+    let last_flags: &[bool] = match segment.len() {
+        ENCRYPTED_SEGMENT_LEN => &[false, true],
+        _ => &[true],
+    };
+    for &is_last in last_flags {
+        let mut contents = segment.to_vec();
+        if let Ok(plaintext) = cipher.open_in_place(
+            segment_nonce(index, is_last),
+            Aad::from(key.as_bytes()),
+            &mut contents,
+        ) {
+            let contents_len = plaintext.len();
+            contents.truncate(contents_len);
+            return Ok(contents);
+        }
+    }
+
+    Err(decryption_failed(key))
+}
+
+fn encrypt(keys: &Keys, key: &str, contents: &[u8]) -> Vec<u8> {
+    let seed = object_seed(keys, key, contents).unwrap();
+    let mut encrypted = Vec::new();
+    EncryptingReader::new(keys, key, &seed, contents, contents.len() as u64)
+        .read_to_end(&mut encrypted)
+        .unwrap();
+    encrypted
+}
+
+fn decrypt(keys: &Keys, key: &str, encrypted: &[u8]) -> io::Result<Vec<u8>> {
+    let mut contents = Vec::new();
+    decrypt_to(keys, key, encrypted, encrypted.len() as u64, &mut contents)?;
+    Ok(contents)
+}
+
+fn encrypt_file(keys: &Keys, key: &str, source: &Path) -> io::Result<impl Read> {
+    let contents_len = fs::metadata(source)?.len();
+    let seed = object_seed(keys, key, BufReader::new(File::open(source)?))?;
+    Ok(EncryptingReader::new(
+        keys,
+        key,
+        &seed,
+        BufReader::new(File::open(source)?),
+        contents_len,
+    ))
+}
+
+async fn read_params(storage: &dyn RemoteBackend) -> io::Result<Option<EncryptionParams>> {
+    match with_retries(|| storage.get(ENCRYPTION_PARAMS_KEY)).await {
+        Ok(bytes) => serde_json::from_slice(&bytes)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string())),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+// Encryption is set up by the first push, with the same conditional write as
+// refs so that concurrent pushes agree on a single salt. A remote already
+// holding unencrypted history is left alone.
+async fn create_params(storage: &dyn RemoteBackend, secret: &[u8]) -> io::Result<EncryptionParams> {
+    if !with_retries(|| storage.list(VSM_DIR)).await?.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The remote holds unencrypted history, encryption can only be set up on an empty remote",
+        ));
+    }
+
+    let mut salt = [0; SALT_LEN];
+    SystemRandom::new()
+        .fill(&mut salt)
+        .map_err(|_| io::Error::other("Failed to generate a salt"))?;
+    let mut params = EncryptionParams {
+        algorithm: ALGORITHM.to_owned(),
+        kdf: KDF.to_owned(),
+        salt: hex::encode(salt),
+        iterations: KEY_DERIVATION_ITERATIONS,
+        check: String::new(),
+    };
+    let keys = derive_keys(secret, &params)?;
+    params.check = hex::encode(encrypt(&keys, ENCRYPTION_PARAMS_KEY, &[]));
+
+    let contents = serde_json::to_vec_pretty(&params)?;
+    match storage
+        .update_ref(ENCRYPTION_PARAMS_KEY, None, &contents)
+        .await
+    {
+        Ok(()) => Ok(params),
+        Err(e)
+            if e.kind() == io::ErrorKind::PermissionDenied
+                || e.kind() == io::ErrorKind::AlreadyExists =>
+        {
+            read_params(storage).await?.ok_or(e)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+// Opening an encrypted remote without encryption would upload plaintext next
+// to the ciphertext, or fail to read it.
+pub async fn check_not_encrypted(storage: &dyn RemoteBackend) -> io::Result<()> {
+    match read_params(storage).await {
+        Ok(Some(_)) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The remote is encrypted, add it again with --encrypt or --key-file",
+        )),
+        Ok(None) => Ok(()),
+        // Remotes that cannot be read yet, such as missing directories, are
+        // reported by the operation itself
+        Err(_) => Ok(()),
+    }
+}
+
+// Encrypts everything stored on the remote, refs and the push lock included,
+// along with the keys naming them. The remote only sees how many objects there
+// are, how they are nested and roughly how big they are.
+pub struct EncryptedBackend {
+    storage: Box<dyn RemoteBackend>,
+    keys: Keys,
+}
+
+impl EncryptedBackend {
+    pub async fn open(
+        storage: Box<dyn RemoteBackend>,
+        remote_config: &RemoteConfig,
+    ) -> io::Result<Self> {
+        let secret = read_secret(remote_config)?;
+        let params = match read_params(&*storage).await? {
+            Some(params) => params,
+            None => create_params(&*storage, &secret).await?,
+        };
+        let keys = derive_keys(&secret, &params)?;
+
+        let check = hex::decode(&params.check)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        if decrypt(&keys, ENCRYPTION_PARAMS_KEY, &check).is_err() {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Wrong passphrase or key file for the encrypted remote",
+            ));
+        }
+
+        Ok(EncryptedBackend { storage, keys })
+    }
+}
+
+#[async_trait]
+impl RemoteBackend for EncryptedBackend {
+    // Only the directories of the prefix can be listed on the storage, the
+    // rest of it is matched once the names are decrypted.
+    async fn list(&self, prefix: &str) -> io::Result<Vec<RemoteObject>> {
+        let storage_prefix = match prefix.rsplit_once('/') {
+            Some((dirs, _)) => encrypt_name(&self.keys, dirs) + "/",
+            None => String::new(),
+        };
+
+        let mut objects = Vec::new();
+        for object in self.storage.list(&storage_prefix).await? {
+            if object.key == ENCRYPTION_PARAMS_KEY {
+                continue;
+            }
+            let key = decrypt_name(&self.keys, &object.key)?;
+            if key.starts_with(prefix) {
+                objects.push(RemoteObject { key, ..object });
+            }
+        }
+        objects.sort_by(|a, b| a.key.cmp(&b.key));
+
+        Ok(objects)
+    }
+
+    async fn get(&self, key: &str) -> io::Result<Vec<u8>> {
+        let name = encrypt_name(&self.keys, key);
+        decrypt(&self.keys, key, &self.storage.get(&name).await?)
+    }
+
+    // Only the header and the segments covering the range are read.
+    async fn get_range(&self, key: &str, start: u64, length: u64) -> io::Result<Vec<u8>> {
+        if length == 0 {
+            return Ok(Vec::new());
+        }
+        let name = encrypt_name(&self.keys, key);
+        let header = self.storage.get_range(&name, 0, HEADER_LEN as u64).await?;
+        let seed = header
+            .strip_prefix(MAGIC)
+            .filter(|seed| seed.len() == SEED_LEN)
+            .ok_or_else(|| decryption_failed(key))?;
+        let cipher = object_cipher(&self.keys, seed);
+
+        let first_index = start / SEGMENT_LEN as u64;
+        let last_index = (start + length - 1) / SEGMENT_LEN as u64;
+        let encrypted = self
+            .storage
+            .get_range(
+                &name,
+                HEADER_LEN as u64 + first_index * ENCRYPTED_SEGMENT_LEN as u64,
+                (last_index - first_index + 1) * ENCRYPTED_SEGMENT_LEN as u64,
+            )
+            .await?;
+        let mut contents = Vec::new();
+        for (index, segment) in (first_index..).zip(encrypted.chunks(ENCRYPTED_SEGMENT_LEN)) {
+            contents.extend(decrypt_segment(&cipher, key, index, segment)?);
+        }
+
+        Ok(contents
+            .into_iter()
+            .skip((start - first_index * SEGMENT_LEN as u64) as usize)
+            .take(length as usize)
+            .collect())
+    }
+
+    async fn download(&self, key: &str, destination: &Path) -> io::Result<()> {
+        let mut encrypted_path = destination.as_os_str().to_owned();
+        encrypted_path.push(ENCRYPTED_SUFFIX);
+        let encrypted_path = Path::new(&encrypted_path);
+        self.storage
+            .download(&encrypt_name(&self.keys, key), encrypted_path)
+            .await?;
+
+        let result = (|| {
+            let mut contents = BufWriter::new(File::create(destination)?);
+            decrypt_to(
+                &self.keys,
+                key,
+                BufReader::new(File::open(encrypted_path)?),
+                fs::metadata(encrypted_path)?.len(),
+                &mut contents,
+            )?;
+            contents.flush()
+        })();
+        let _ = fs::remove_file(encrypted_path);
+
+        result
+    }
+
+    async fn put(&self, key: &str, source: &Path) -> io::Result<()> {
+        let encrypted_path = std::env::temp_dir().join(format!("rgit-{}", generate_commit_id()));
+        let result = async {
+            let mut encrypted = BufWriter::new(File::create(&encrypted_path)?);
+            io::copy(&mut encrypt_file(&self.keys, key, source)?, &mut encrypted)?;
+            encrypted.flush()?;
+            drop(encrypted);
+            self.storage
+                .put(&encrypt_name(&self.keys, key), &encrypted_path)
+                .await
+        }
+        .await;
+        let _ = fs::remove_file(&encrypted_path);
+
+        result
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        self.storage.delete(&encrypt_name(&self.keys, key)).await
+    }
+
+    async fn update_ref(
+        &self,
+        key: &str,
+        expected_contents: Option<&[u8]>,
+        contents: &[u8],
+    ) -> io::Result<()> {
+        let expected_contents =
+            expected_contents.map(|expected| encrypt(&self.keys, key, expected));
+        self.storage
+            .update_ref(
+                &encrypt_name(&self.keys, key),
+                expected_contents.as_deref(),
+                &encrypt(&self.keys, key, contents),
+            )
+            .await
+    }
+
+    fn local_checksum(&self, key: &str, file: &Path) -> io::Result<String> {
+        checksum(
+            encrypt_file(&self.keys, key, file)?,
+            encrypted_len(fs::metadata(file)?.len()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::fs_remote::DirectoryBackend;
+
+    fn keys() -> Keys {
+        keys_from_master_key(&[7; 32])
+    }
+
+    fn params(secret: &[u8]) -> EncryptionParams {
+        let mut params = EncryptionParams {
+            algorithm: ALGORITHM.to_owned(),
+            kdf: KDF.to_owned(),
+            salt: hex::encode([1; SALT_LEN]),
+            iterations: 1,
+            check: String::new(),
+        };
+        let keys = derive_keys(secret, &params).unwrap();
+        params.check = hex::encode(encrypt(&keys, ENCRYPTION_PARAMS_KEY, &[]));
+        params
+    }
+
+    #[test]
+    fn contents_round_trip_across_segment_sizes() {
+        let keys = keys();
+        for len in [
+            0,
+            1,
+            SEGMENT_LEN - 1,
+            SEGMENT_LEN,
+            SEGMENT_LEN + 1,
+            3 * SEGMENT_LEN,
+        ] {
+            let contents: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+            let encrypted = encrypt(&keys, "a/key", &contents);
+
+            assert_eq!(encrypted.len() as u64, encrypted_len(len as u64));
+            assert_eq!(decrypt(&keys, "a/key", &encrypted).unwrap(), contents);
+        }
+    }
+
+    #[test]
+    fn encryption_is_deterministic_per_key() {
+        let keys = keys();
+
+        assert_eq!(
+            encrypt(&keys, "a/key", b"contents"),
+            encrypt(&keys, "a/key", b"contents")
+        );
+        assert_ne!(
+            encrypt(&keys, "a/key", b"contents"),
+            encrypt(&keys, "other/key", b"contents")
+        );
+    }
+
+    #[test]
+    fn wrong_key_is_rejected() {
+        let encrypted = encrypt(&keys(), "a/key", b"contents");
+
+        let error = decrypt(&keys_from_master_key(&[8; 32]), "a/key", &encrypted).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn wrong_passphrase_fails_the_check() {
+        let params = params(b"passphrase");
+        let check = hex::decode(&params.check).unwrap();
+
+        let keys = derive_keys(b"passphrase", &params).unwrap();
+        assert!(decrypt(&keys, ENCRYPTION_PARAMS_KEY, &check).is_ok());
+        let keys = derive_keys(b"wrong", &params).unwrap();
+        assert!(decrypt(&keys, ENCRYPTION_PARAMS_KEY, &check).is_err());
+    }
+
+    #[test]
+    fn tampered_objects_are_rejected() {
+        let keys = keys();
+        let contents = vec![3; 2 * SEGMENT_LEN];
+        let encrypted = encrypt(&keys, "a/key", &contents);
+
+        let mut tampered = encrypted.clone();
+        tampered[HEADER_LEN + 10] ^= 1;
+        assert!(decrypt(&keys, "a/key", &tampered).is_err());
+        // An object moved under another key
+        assert!(decrypt(&keys, "b/key", &encrypted).is_err());
+        // Cut after its first segment, which is not flagged as the last one
+        let truncated = &encrypted[..HEADER_LEN + ENCRYPTED_SEGMENT_LEN];
+        assert!(decrypt(&keys, "a/key", truncated).is_err());
+    }
+
+    #[test]
+    fn names_round_trip_one_component_at_a_time() {
+        let keys = keys();
+        let name = encrypt_name(&keys, ".history/refs/heads/main");
+
+        assert_eq!(name, encrypt_name(&keys, ".history/refs/heads/main"));
+        assert_eq!(name.split('/').count(), 4);
+        assert!(name.starts_with(&(encrypt_name(&keys, ".history/refs") + "/")));
+        assert!(!name.contains("main"));
+        assert_eq!(
+            decrypt_name(&keys, &name).unwrap(),
+            ".history/refs/heads/main"
+        );
+        assert!(decrypt_name(&keys_from_master_key(&[8; 32]), &name).is_err());
+    }
+
+    #[tokio::test]
+    async fn backend_encrypts_names_and_reads_ranges() {
+        let root = std::env::temp_dir().join(format!("rgit-test-{}", generate_commit_id()));
+        fs::create_dir_all(&root).unwrap();
+        let backend = EncryptedBackend {
+            storage: Box::new(DirectoryBackend::new(&root)),
+            keys: keys(),
+        };
+
+        let contents: Vec<u8> = (0..3 * SEGMENT_LEN + 10).map(|i| (i % 253) as u8).collect();
+        let source = root.join("source");
+        fs::write(&source, &contents).unwrap();
+        backend
+            .put(".history/files/a/data.bin", &source)
+            .await
+            .unwrap();
+        backend.put(".history/commits.json", &source).await.unwrap();
+        fs::remove_file(&source).unwrap();
+
+        let keys: Vec<String> = backend
+            .list(".history/files/")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|object| object.key)
+            .collect();
+        assert_eq!(keys, [".history/files/a/data.bin"]);
+        for object in backend.storage.list("").await.unwrap() {
+            assert!(!object.key.contains("history"));
+        }
+
+        let key = ".history/files/a/data.bin";
+        assert_eq!(backend.get(key).await.unwrap(), contents);
+        for (start, length) in [(0, 10), (SEGMENT_LEN - 5, 10), (3 * SEGMENT_LEN, 10)] {
+            assert_eq!(
+                backend
+                    .get_range(key, start as u64, length as u64)
+                    .await
+                    .unwrap(),
+                contents[start..start + length]
+            );
+        }
+        let destination = root.join("destination");
+        backend.download(key, &destination).await.unwrap();
+        assert_eq!(fs::read(&destination).unwrap(), contents);
+        assert_eq!(
+            backend.local_checksum(key, &destination).unwrap(),
+            checksum(
+                File::open(root.join(encrypt_name(&backend.keys, key))).unwrap(),
+                encrypted_len(contents.len() as u64)
+            )
+            .unwrap()
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -7,31 +7,49 @@ use std::io::{Result, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
 use std::thread;
 
+use super::constants::DATA_RELATIVE_PATH;
 use super::list_files_ignore;
 
-pub fn write_to_data_file(path: &str, data: &str, exists: bool) -> Result<()> {
+pub fn write_to_data_file(path: &str, data: &[u8], exists: bool) -> Result<()> {
     if exists {
         let mut file = OpenOptions::new()
             .append(true)
-            .open(path.to_owned() + "/data.bin")?;
-        file.write_all(data.as_bytes())?;
+            .open(path.to_owned() + DATA_RELATIVE_PATH)?;
+        file.write_all(data)?;
         Ok(())
     } else {
-        let mut file = File::create(path.to_owned() + "/data.bin")?;
-        file.write_all(data.as_bytes())?;
+        let mut file = File::create(path.to_owned() + DATA_RELATIVE_PATH)?;
+        file.write_all(data)?;
         Ok(())
     }
 }
 
-pub fn read_part_of_file(file_path: &str, start: u64, length: usize) -> Result<String> {
+pub fn read_part_of_file(file_path: &str, start: u64, length: usize) -> Result<Vec<u8>> {
     let mut file = File::open(file_path)?;
     file.seek(SeekFrom::Start(start))?;
     let mut buffer = vec![0; length];
     file.read_exact(&mut buffer)?;
 
-    let contents = String::from_utf8(buffer).unwrap();
+    Ok(buffer)
+}
+
+pub fn copy_part_of_file(
+    file_path: &str,
+    start: u64,
+    length: u64,
+    destination: &mut impl Write,
+) -> Result<u64> {
+    let mut file = File::open(file_path)?;
+    file.seek(SeekFrom::Start(start))?;
+    let copied = io::copy(&mut file.take(length), destination)?;
+    if copied != length {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("{} is shorter than expected", file_path),
+        ));
+    }
 
-    Ok(contents)
+    Ok(copied)
 }
 
 pub fn get_file_paths_recursively(
@@ -50,23 +68,21 @@ pub fn get_file_paths_recursively(
     };
 
     if let Ok(entries) = fs::read_dir(path) {
-        for entry in entries {
-            if let Ok(entry) = entry {
-                let entry_path = entry.path();
-                let entry_name = entry_path.file_name().unwrap().to_str().unwrap();
-                if ignores.contains(&entry_name.to_owned()) {
+        for entry in entries.flatten() {
+            let entry_path = entry.path();
+            let entry_name = entry_path.file_name().unwrap().to_string_lossy();
+            if ignores.contains(&entry_name.to_string()) {
+                continue;
+            }
+            if entry_path.is_file() {
+                result.push(entry_path.clone());
+            } else if entry_path.is_dir() {
+                if entry_name == ".history" {
                     continue;
                 }
-                if entry_path.is_file() {
-                    result.push(entry_path.clone());
-                } else if entry_path.is_dir() {
-                    if entry_path.file_name().unwrap().to_str().unwrap() == ".history" {
-                        continue;
-                    }
-                    let subdirectory_files =
-                        get_file_paths_recursively(Some(&entry_path), Some(ignores));
-                    result.extend(subdirectory_files);
-                }
+                let subdirectory_files =
+                    get_file_paths_recursively(Some(&entry_path), Some(ignores));
+                result.extend(subdirectory_files);
             }
         }
     }
@@ -87,8 +103,8 @@ pub fn delete_contents_of_directory(path: &str, ignores: Option<&Vec<String>>) -
     for entry_path in files {
         let files_to_ignore = files_to_ignore.clone();
         let handle = thread::spawn(move || {
-            let entry_name = entry_path.file_name().unwrap().to_str().unwrap();
-            if files_to_ignore.contains(&entry_name.to_owned()) {
+            let entry_name = entry_path.file_name().unwrap().to_string_lossy();
+            if files_to_ignore.contains(&entry_name.to_string()) {
                 return;
             }
             if entry_path.is_file() {
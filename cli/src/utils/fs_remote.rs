@@ -0,0 +1,173 @@
+use async_trait::async_trait;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use super::generate_commit_id;
+use super::remote::{ref_mismatch, RemoteBackend, RemoteObject};
+
+// Files being written are kept out of the repository layout until they are
+// complete, so readers never see a partial file.
+const TEMP_DIR: &str = "tmp";
+const LOCK_SUFFIX: &str = ".lock";
+
+// A remote stored as a plain directory, such as a network mount shared by a
+// team.
+pub struct DirectoryBackend {
+    root: PathBuf,
+}
+
+impl DirectoryBackend {
+    pub fn new(root: &Path) -> Self {
+        DirectoryBackend {
+            root: root.to_owned(),
+        }
+    }
+
+    fn write_atomically(
+        &self,
+        key: &str,
+        write: impl FnOnce(&Path) -> io::Result<()>,
+    ) -> io::Result<()> {
+        let destination = self.root.join(key);
+        let temp_dir = self.root.join(TEMP_DIR);
+        fs::create_dir_all(destination.parent().unwrap())?;
+        fs::create_dir_all(&temp_dir)?;
+
+        let temp_path = temp_dir.join(generate_commit_id());
+        if let Err(e) = write(&temp_path).and_then(|_| fs::rename(&temp_path, &destination)) {
+            let _ = fs::remove_file(&temp_path);
+            return Err(e);
+        }
+
+        Ok(())
+    }
+}
+
+// Unlike get_file_paths_recursively, this does not skip ".history" directories.
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry_path = entry?.path();
+        if entry_path.is_dir() {
+            collect_files(&entry_path, files)?;
+        } else {
+            files.push(entry_path);
+        }
+    }
+
+    Ok(())
+}
+
+#[async_trait]
+impl RemoteBackend for DirectoryBackend {
+    async fn list(&self, prefix: &str) -> io::Result<Vec<RemoteObject>> {
+        if !self.root.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Remote directory {} does not exist", self.root.display()),
+            ));
+        }
+
+        let mut files = Vec::new();
+        collect_files(&self.root, &mut files)?;
+        let mut objects = Vec::new();
+        for file in files {
+            let key = file
+                .strip_prefix(&self.root)
+                .unwrap()
+                .to_string_lossy()
+                .to_string();
+            if Path::new(&key).starts_with(TEMP_DIR)
+                || key.ends_with(LOCK_SUFFIX)
+                || !key.starts_with(prefix)
+            {
+                continue;
+            }
+            objects.push(RemoteObject {
+                key,
+                size: fs::metadata(&file)?.len(),
+                checksum: None,
+            });
+        }
+        objects.sort_by(|a, b| a.key.cmp(&b.key));
+
+        Ok(objects)
+    }
+
+    async fn get(&self, key: &str) -> io::Result<Vec<u8>> {
+        fs::read(self.root.join(key))
+    }
+
+    async fn get_range(&self, key: &str, start: u64, length: u64) -> io::Result<Vec<u8>> {
+        let mut file = File::open(self.root.join(key))?;
+        file.seek(SeekFrom::Start(start))?;
+        let mut contents = Vec::new();
+        file.take(length).read_to_end(&mut contents)?;
+        Ok(contents)
+    }
+
+    async fn download(&self, key: &str, destination: &Path) -> io::Result<()> {
+        fs::create_dir_all(destination.parent().unwrap())?;
+        fs::copy(self.root.join(key), destination)?;
+        Ok(())
+    }
+
+    async fn put(&self, key: &str, source: &Path) -> io::Result<()> {
+        self.write_atomically(key, |temp_path| fs::copy(source, temp_path).map(|_| ()))
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        fs::remove_file(self.root.join(key))
+    }
+
+    // Like the local refs, the new value is written to "<ref>.lock", which
+    // only one writer can create, and renamed over the ref once checked.
+    async fn update_ref(
+        &self,
+        key: &str,
+        expected_contents: Option<&[u8]>,
+        contents: &[u8],
+    ) -> io::Result<()> {
+        let ref_path = self.root.join(key);
+        let lock_path = self.root.join(key.to_owned() + LOCK_SUFFIX);
+        fs::create_dir_all(ref_path.parent().unwrap())?;
+        let mut lock_file = match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+        {
+            Ok(lock_file) => lock_file,
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!(
+                        "{} is locked by another push, remove {} if no push is running",
+                        key,
+                        lock_path.display()
+                    ),
+                ))
+            }
+            Err(e) => return Err(e),
+        };
+
+        let current_contents = match fs::read(&ref_path) {
+            Ok(current_contents) => Ok(Some(current_contents)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        };
+        let result = match current_contents {
+            Ok(current_contents) if current_contents.as_deref() != expected_contents => {
+                Err(ref_mismatch(key))
+            }
+            Ok(_) => lock_file
+                .write_all(contents)
+                .and_then(|_| fs::rename(&lock_path, &ref_path)),
+            Err(e) => Err(e),
+        };
+        if result.is_err() {
+            let _ = fs::remove_file(&lock_path);
+        }
+
+        result
+    }
+}
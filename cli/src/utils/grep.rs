@@ -0,0 +1,108 @@
+use regex::Regex;
+use std::fmt::Write;
+use std::io;
+use std::thread;
+
+use super::merge::split_lines;
+use super::patch::is_binary;
+
+pub struct GrepOptions {
+    pub line_numbers: bool,
+    pub files_with_matches: bool,
+    pub count: bool,
+    pub before_context: usize,
+    pub after_context: usize,
+}
+
+// Formats the matches of a file the way "grep -H" does, matching lines being
+// separated from their label by ":" and context lines by "-".
+pub fn grep_contents(label: &str, contents: &[u8], regex: &Regex, options: &GrepOptions) -> String {
+    let text = String::from_utf8_lossy(contents);
+    let lines = split_lines(&text);
+    let matching_lines: Vec<bool> = lines.iter().map(|line| regex.is_match(line)).collect();
+    let match_count = matching_lines.iter().filter(|matching| **matching).count();
+
+    let mut output = String::new();
+    if match_count == 0 {
+        return output;
+    }
+    if options.files_with_matches {
+        writeln!(output, "{}", label).unwrap();
+        return output;
+    }
+    if options.count {
+        writeln!(output, "{}:{}", label, match_count).unwrap();
+        return output;
+    }
+    if is_binary(contents) {
+        writeln!(output, "Binary file {} matches", label).unwrap();
+        return output;
+    }
+
+    let has_context = options.before_context > 0 || options.after_context > 0;
+    let mut next_line = 0;
+    for (index, _) in matching_lines
+        .iter()
+        .enumerate()
+        .filter(|(_, matching)| **matching)
+    {
+        let start = index.saturating_sub(options.before_context).max(next_line);
+        let end = (index + options.after_context + 1).min(lines.len());
+        if has_context && next_line > 0 && start > next_line {
+            writeln!(output, "--").unwrap();
+        }
+        for (line_index, line) in lines.iter().enumerate().take(end).skip(start) {
+            let separator = if matching_lines[line_index] { ':' } else { '-' };
+            write!(output, "{}{}", label, separator).unwrap();
+            if options.line_numbers {
+                write!(output, "{}{}", line_index + 1, separator).unwrap();
+            }
+            output.push_str(line.strip_suffix('\n').unwrap_or(line));
+            output.push('\n');
+        }
+        next_line = next_line.max(end);
+    }
+
+    output
+}
+
+// Searches the files on as many threads as there are cores, the output being
+// returned in the order of the files.
+pub fn grep_files<T, F>(
+    files: &[T],
+    regex: &Regex,
+    options: &GrepOptions,
+    read_file: F,
+) -> io::Result<Vec<String>>
+where
+    T: Sync,
+    F: Fn(&T) -> io::Result<(String, Vec<u8>)> + Sync,
+{
+    let threads_count = thread::available_parallelism()
+        .map(|count| count.get())
+        .unwrap_or(1);
+    let chunk_size = files.len().div_ceil(threads_count).max(1);
+
+    thread::scope(|scope| {
+        let handles: Vec<_> = files
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(|| {
+                    chunk
+                        .iter()
+                        .map(|file| {
+                            let (label, contents) = read_file(file)?;
+                            Ok(grep_contents(&label, &contents, regex, options))
+                        })
+                        .collect::<io::Result<Vec<String>>>()
+                })
+            })
+            .collect();
+
+        let mut outputs = Vec::with_capacity(files.len());
+        for handle in handles {
+            outputs.extend(handle.join().unwrap()?);
+        }
+        Ok(outputs)
+    })
+}
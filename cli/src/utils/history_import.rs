@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Seek, SeekFrom};
+use std::path::Path;
+
+use super::constants::{
+    COMMIT_METADATA_RELATIVE_PATH, DATA_RELATIVE_PATH, FETCH_DIR, FILES_HISTORY_DIR,
+    HISTORY_PATH_RELATIVE_PATH, LFS_DIR, MAIN_COMMITS_METADATA_FILE_PATH, OBJECTS_DIR, VSM_DIR,
+};
+use super::fs_provider::{copy_part_of_file, get_file_paths_recursively};
+use super::remote::{with_retries, RemoteBackend};
+use super::types::CommitMetadata;
+use super::{commits_metadata, list_commits, write_commits, write_to_commit_metadata_file};
+
+// Suffix of a data file being rebuilt, renamed over the data file once done.
+const IMPORT_SUFFIX: &str = ".import";
+
+// Path inside a downloaded copy of the history of a path inside ".history".
+fn source_path(source_dir: &str, local_path: &str) -> String {
+    source_dir.to_owned() + local_path.strip_prefix(VSM_DIR).unwrap()
+}
+
+fn import_commits(source_dir: &str) -> io::Result<()> {
+    let source_commits_path = source_path(source_dir, MAIN_COMMITS_METADATA_FILE_PATH);
+    if !Path::new(&source_commits_path).exists() {
+        return Ok(());
+    }
+
+    let mut commits = list_commits(MAIN_COMMITS_METADATA_FILE_PATH)?;
+    let commits_count = commits.len();
+    for commit in list_commits(&source_commits_path)? {
+        if !commits.iter().any(|c| c.commit_id == commit.commit_id) {
+            commits.push(commit);
+        }
+    }
+
+    if commits.len() == commits_count {
+        return Ok(());
+    }
+    write_commits(&commits)
+}
+
+fn same_entry(a: &CommitMetadata, b: &CommitMetadata) -> bool {
+    a.commit_id == b.commit_id
+        && a.pointer_to_data == b.pointer_to_data
+        && a.size == b.size
+        && a.chunks == b.chunks
+}
+
+// The local data file keeps the layout of the remote one, with the contents of
+// local commits after it, so that pushing it only appends to the remote data
+// file and never moves what the remote metadata points at. When the remote
+// has entries the local history lacks or lays out differently, the data file
+// is rebuilt from the remote one, the contents of local commits being copied
+// after it with their pointers rewritten.
+fn import_file_history(source_history_dir: &str, history_dir: &str) -> io::Result<()> {
+    if !Path::new(history_dir).exists() {
+        fs::create_dir_all(history_dir)?;
+        for relative_path in [
+            HISTORY_PATH_RELATIVE_PATH,
+            DATA_RELATIVE_PATH,
+            COMMIT_METADATA_RELATIVE_PATH,
+        ] {
+            let source_file_path = source_history_dir.to_owned() + relative_path;
+            if Path::new(&source_file_path).exists() {
+                fs::copy(source_file_path, history_dir.to_owned() + relative_path)?;
+            }
+        }
+        return Ok(());
+    }
+
+    let source_metadata = commits_metadata(source_history_dir)?;
+    let metadata = commits_metadata(history_dir)?;
+    if source_metadata
+        .iter()
+        .all(|entry| metadata.iter().any(|local| same_entry(local, entry)))
+    {
+        return Ok(());
+    }
+
+    let source_data_path = source_history_dir.to_owned() + DATA_RELATIVE_PATH;
+    let data_path = history_dir.to_owned() + DATA_RELATIVE_PATH;
+    let rebuilt_data_path = data_path.clone() + IMPORT_SUFFIX;
+    let mut rebuilt_data_file = File::create(&rebuilt_data_path)?;
+    if Path::new(&source_data_path).exists() {
+        io::copy(&mut File::open(&source_data_path)?, &mut rebuilt_data_file)?;
+    }
+
+    // Contents the remote data file may lack, as only some of its entries may
+    // have been downloaded, are copied from the local one to where the remote
+    // keeps them. Entries sharing contents keep sharing them.
+    let mut new_pointers: HashMap<(u64, u64), u64> = HashMap::new();
+    for entry in &source_metadata {
+        let local_entry = metadata
+            .iter()
+            .find(|local| local.commit_id == entry.commit_id && local.chunks.is_empty());
+        if let (Some(local_entry), true) = (local_entry, entry.chunks.is_empty()) {
+            rebuilt_data_file.seek(SeekFrom::Start(entry.pointer_to_data))?;
+            copy_part_of_file(
+                &data_path,
+                local_entry.pointer_to_data,
+                local_entry.size,
+                &mut rebuilt_data_file,
+            )?;
+            new_pointers.insert(
+                (local_entry.pointer_to_data, local_entry.size),
+                entry.pointer_to_data,
+            );
+        }
+    }
+
+    // The metadata follows the layout of the data file, the last entry being
+    // the one new commits compare their contents with
+    let mut rebuilt_metadata = source_metadata.clone();
+    for mut local_entry in metadata {
+        if source_metadata
+            .iter()
+            .any(|entry| entry.commit_id == local_entry.commit_id)
+        {
+            continue;
+        }
+        if local_entry.chunks.is_empty() {
+            let key = (local_entry.pointer_to_data, local_entry.size);
+            local_entry.pointer_to_data = match new_pointers.get(&key) {
+                Some(pointer) => *pointer,
+                None => {
+                    let pointer = rebuilt_data_file.seek(SeekFrom::End(0))?;
+                    copy_part_of_file(&data_path, key.0, key.1, &mut rebuilt_data_file)?;
+                    new_pointers.insert(key, pointer);
+                    pointer
+                }
+            };
+        }
+        rebuilt_metadata.push(local_entry);
+    }
+
+    rebuilt_data_file.sync_all()?;
+    fs::rename(rebuilt_data_path, data_path)?;
+    write_to_commit_metadata_file(history_dir, rebuilt_metadata)
+}
+
+// Pushing replaces the remote data file of every file history with the local
+// one, which must keep every remote entry where the remote metadata says it
+// is. Histories laid out differently, such as ones imported before layouts
+// were kept or ones the remote has entries of an interrupted push in, are
+// rebuilt around the remote data file first.
+pub async fn align_with_remote(remote: &dyn RemoteBackend) -> io::Result<()> {
+    let files_prefix = FILES_HISTORY_DIR.to_owned() + "/";
+    for object in with_retries(|| remote.list(&files_prefix)).await? {
+        let history_dir = match object.key.strip_suffix(COMMIT_METADATA_RELATIVE_PATH) {
+            Some(history_dir) if Path::new(history_dir).exists() => history_dir,
+            _ => continue,
+        };
+        let metadata_path = history_dir.to_owned() + COMMIT_METADATA_RELATIVE_PATH;
+        if object.checksum.as_deref()
+            == Some(&remote.local_checksum(&object.key, Path::new(&metadata_path))?)
+        {
+            continue;
+        }
+        let remote_metadata: Vec<CommitMetadata> =
+            serde_json::from_slice(&with_retries(|| remote.get(&object.key)).await?)?;
+        let metadata = commits_metadata(history_dir)?;
+        if remote_metadata
+            .iter()
+            .all(|entry| metadata.iter().any(|local| same_entry(local, entry)))
+        {
+            continue;
+        }
+
+        let source_history_dir = source_path(FETCH_DIR, history_dir);
+        fs::create_dir_all(&source_history_dir)?;
+        write_to_commit_metadata_file(&source_history_dir, remote_metadata)?;
+        let data_key = history_dir.to_owned() + DATA_RELATIVE_PATH;
+        let source_data_path = source_history_dir.clone() + DATA_RELATIVE_PATH;
+        match with_retries(|| remote.download(&data_key, Path::new(&source_data_path))).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        import_file_history(&source_history_dir, history_dir)?;
+    }
+
+    match fs::remove_dir_all(FETCH_DIR) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+// Content addressed files never change, so only the missing ones are copied.
+fn copy_missing_files(source_dir: &str, destination_dir: &str) -> io::Result<()> {
+    for source_file_path in get_file_paths_recursively(Some(Path::new(source_dir)), None) {
+        let relative_path = source_file_path.strip_prefix(source_dir).unwrap();
+        let destination_path = Path::new(destination_dir).join(relative_path);
+        if destination_path.exists() {
+            continue;
+        }
+        fs::create_dir_all(destination_path.parent().unwrap())?;
+        fs::copy(&source_file_path, destination_path)?;
+    }
+
+    Ok(())
+}
+
+// Merges a downloaded copy of a history directory into the local history,
+// keeping every local commit even when both histories diverged. Refs are left
+// for the caller to update.
+pub fn import_history(source_dir: &str) -> io::Result<()> {
+    import_commits(source_dir)?;
+
+    let source_files_dir = source_path(source_dir, FILES_HISTORY_DIR);
+    if let Ok(entries) = fs::read_dir(&source_files_dir) {
+        for entry in entries.flatten() {
+            if !entry.path().is_dir() {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().to_string();
+            import_file_history(
+                &(source_files_dir.clone() + "/" + &name),
+                &(FILES_HISTORY_DIR.to_owned() + "/" + &name),
+            )?;
+        }
+    }
+
+    copy_missing_files(&source_path(source_dir, OBJECTS_DIR), OBJECTS_DIR)?;
+    copy_missing_files(&source_path(source_dir, LFS_DIR), LFS_DIR)
+}
@@ -0,0 +1,169 @@
+use glob::{MatchOptions, Pattern};
+use sha2::{Digest, Sha256};
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Component, Path, PathBuf};
+
+use super::constants::{
+    COMMIT_METADATA_RELATIVE_PATH, DATA_RELATIVE_PATH, FILES_HISTORY_DIR,
+    HISTORY_PATH_RELATIVE_PATH, LEGACY_COMMIT_METADATA_RELATIVE_PATH, VSM_DIR,
+};
+use super::fs_provider::get_file_paths_recursively;
+
+// Paths are stored relative to the repository root without the leading "./",
+// so "./src/main.rs" and "src/main.rs" share the same history.
+pub fn normalize_path(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| !matches!(component, Component::CurDir))
+        .collect()
+}
+
+// A pathspec names a file, every file under a directory ("." being the whole
+// tree) or, when it contains wildcards, the paths matching it as a glob. Like
+// LFS patterns, globs without a "/" match the file name anywhere in the tree.
+pub fn matches_pathspec(path: &Path, pathspec: &str) -> bool {
+    let normalized_path = normalize_path(path);
+    let normalized_pathspec = normalize_path(Path::new(pathspec));
+    if normalized_path.starts_with(&normalized_pathspec) {
+        return true;
+    }
+    if !pathspec.contains(['*', '?', '[']) {
+        return false;
+    }
+
+    let pattern = match Pattern::new(&normalized_pathspec.to_string_lossy()) {
+        Ok(pattern) => pattern,
+        Err(_) => return false,
+    };
+    let match_options = MatchOptions {
+        require_literal_separator: true,
+        ..MatchOptions::new()
+    };
+    if pattern.as_str().contains('/') {
+        pattern.matches_path_with(&normalized_path, match_options)
+    } else {
+        normalized_path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|file_name| pattern.matches_with(file_name, match_options))
+    }
+}
+
+// History directories are named by the hash of the raw path bytes, which keeps
+// names unambiguous and bounded in length no matter how deeply a file is nested.
+// The original path is kept next to the data so it can be restored byte for byte.
+pub fn encode_history_path(path: &Path) -> String {
+    let normalized_path = normalize_path(path);
+    hex::encode(Sha256::digest(normalized_path.as_os_str().as_bytes()))
+}
+
+pub fn history_dir_for_path(path: &Path) -> String {
+    FILES_HISTORY_DIR.to_owned() + "/" + &encode_history_path(path)
+}
+
+pub fn write_history_path(history_dir: &str, path: &Path) -> io::Result<()> {
+    File::create(history_dir.to_owned() + HISTORY_PATH_RELATIVE_PATH)?
+        .write_all(normalize_path(path).as_os_str().as_bytes())
+}
+
+pub fn read_history_path(history_dir: &str) -> io::Result<PathBuf> {
+    let path_bytes = fs::read(history_dir.to_owned() + HISTORY_PATH_RELATIVE_PATH)?;
+    Ok(PathBuf::from(OsStr::from_bytes(&path_bytes)))
+}
+
+pub fn list_history_dirs() -> io::Result<Vec<String>> {
+    let entries = match fs::read_dir(FILES_HISTORY_DIR) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut history_dirs = Vec::new();
+    for entry in entries {
+        let entry_path = entry?.path();
+        if entry_path.is_dir() {
+            history_dirs.push(entry_path.to_str().unwrap().to_owned());
+        }
+    }
+
+    Ok(history_dirs)
+}
+
+fn legacy_history_name(path: &Path) -> Option<String> {
+    path.to_str().map(|path| path.replace('/', "_"))
+}
+
+// The legacy layout kept every file in ".history/<path with / replaced by _>"
+// with its metadata in a sibling "<name>.ignore" file. Since that encoding is
+// ambiguous, the working tree is used to find the file a directory belongs to,
+// falling back to the naive decoding when no single file matches.
+pub fn migrate_history_layout() -> io::Result<()> {
+    let entries = match fs::read_dir(VSM_DIR) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    let mut legacy_dirs = Vec::new();
+    for entry in entries {
+        let entry_path = entry?.path();
+        let legacy_name = match entry_path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name.to_owned(),
+            None => continue,
+        };
+        let legacy_dir = VSM_DIR.to_owned() + "/" + &legacy_name;
+        if entry_path.is_dir()
+            && Path::new(&(legacy_dir.clone() + DATA_RELATIVE_PATH)).is_file()
+            && Path::new(&(legacy_dir.clone() + LEGACY_COMMIT_METADATA_RELATIVE_PATH)).is_file()
+        {
+            legacy_dirs.push((legacy_dir, legacy_name));
+        }
+    }
+
+    if legacy_dirs.is_empty() {
+        return Ok(());
+    }
+
+    println!("Migrating the history to the new path layout");
+    let working_tree_files = get_file_paths_recursively(None, None);
+    fs::create_dir_all(FILES_HISTORY_DIR)?;
+
+    for (legacy_dir, legacy_name) in legacy_dirs {
+        let candidates: Vec<&PathBuf> = working_tree_files
+            .iter()
+            .filter(|file_path| legacy_history_name(file_path).as_deref() == Some(&legacy_name))
+            .collect();
+        let file_path = match candidates.as_slice() {
+            [file_path] => (*file_path).clone(),
+            _ => PathBuf::from(legacy_name.replace('_', "/")),
+        };
+
+        let history_dir = history_dir_for_path(&file_path);
+        if Path::new(&history_dir).exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!(
+                    "Cannot migrate {}: history for {} already exists",
+                    legacy_dir,
+                    file_path.display()
+                ),
+            ));
+        }
+
+        fs::create_dir(&history_dir)?;
+        write_history_path(&history_dir, &file_path)?;
+        fs::rename(
+            legacy_dir.clone() + DATA_RELATIVE_PATH,
+            history_dir.clone() + DATA_RELATIVE_PATH,
+        )?;
+        fs::rename(
+            legacy_dir.clone() + LEGACY_COMMIT_METADATA_RELATIVE_PATH,
+            history_dir.clone() + COMMIT_METADATA_RELATIVE_PATH,
+        )?;
+        fs::remove_dir_all(&legacy_dir)?;
+    }
+
+    Ok(())
+}
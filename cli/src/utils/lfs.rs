@@ -0,0 +1,114 @@
+use glob::{MatchOptions, Pattern};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use super::constants::{LFS_DIR, LFS_PATTERNS_PATH, LFS_POINTER_VERSION, LFS_REMOTE_PREFIX};
+use super::generate_commit_id;
+use super::history_paths::normalize_path;
+use super::types::LfsPointer;
+
+pub fn list_lfs_patterns() -> Vec<Pattern> {
+    let lfs_patterns_string = match fs::read_to_string(LFS_PATTERNS_PATH) {
+        Ok(lfs_patterns_string) => lfs_patterns_string,
+        Err(_) => return Vec::new(),
+    };
+
+    lfs_patterns_string
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.starts_with('#') && !line.is_empty())
+        .filter_map(|line| Pattern::new(line).ok())
+        .collect()
+}
+
+// Patterns without a "/" match the file name anywhere in the tree, the others
+// match the path relative to the repository root.
+pub fn is_lfs_path(file_path: &Path, lfs_patterns: &[Pattern]) -> bool {
+    let normalized_path = normalize_path(file_path);
+    let file_name = match normalized_path.file_name().and_then(|name| name.to_str()) {
+        Some(file_name) => file_name.to_owned(),
+        None => return false,
+    };
+    let match_options = MatchOptions {
+        require_literal_separator: true,
+        ..MatchOptions::new()
+    };
+
+    lfs_patterns.iter().any(|pattern| {
+        if pattern.as_str().contains('/') {
+            pattern.matches_path_with(&normalized_path, match_options)
+        } else {
+            pattern.matches_with(&file_name, match_options)
+        }
+    })
+}
+
+pub fn lfs_blob_path(oid: &str) -> String {
+    LFS_DIR.to_owned() + "/" + &oid[..2] + "/" + &oid[2..]
+}
+
+pub fn lfs_blob_key(oid: &str) -> String {
+    LFS_REMOTE_PREFIX.to_owned() + &oid[..2] + "/" + &oid[2..]
+}
+
+pub fn format_lfs_pointer(pointer: &LfsPointer) -> String {
+    format!(
+        "{}\noid sha256:{}\nsize {}\n",
+        LFS_POINTER_VERSION, pointer.oid, pointer.size
+    )
+}
+
+pub fn parse_lfs_pointer(contents: &[u8]) -> Option<LfsPointer> {
+    let contents = std::str::from_utf8(contents).ok()?;
+    let mut lines = contents.lines();
+    if lines.next()? != LFS_POINTER_VERSION {
+        return None;
+    }
+    let oid = lines.next()?.strip_prefix("oid sha256:")?.to_owned();
+    let size = lines.next()?.strip_prefix("size ")?.parse().ok()?;
+    if oid.len() != 64 || !oid.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    Some(LfsPointer { oid, size })
+}
+
+// A working file that is still a pointer (its asset was never fetched) keeps
+// its pointer instead of being stored as a new asset.
+pub fn store_lfs_blob(file_path: &Path) -> io::Result<LfsPointer> {
+    let size = fs::metadata(file_path)?.len();
+    if size < 256 {
+        if let Some(pointer) = parse_lfs_pointer(&fs::read(file_path)?) {
+            return Ok(pointer);
+        }
+    }
+
+    fs::create_dir_all(LFS_DIR)?;
+    let temp_path = LFS_DIR.to_owned() + "/" + &generate_commit_id() + ".tmp";
+    let mut temp_file = File::create(&temp_path)?;
+    let mut file = File::open(file_path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        temp_file.write_all(&buffer[..read])?;
+    }
+
+    let oid = hex::encode(hasher.finalize());
+    let blob_path = lfs_blob_path(&oid);
+    if Path::new(&blob_path).exists() {
+        fs::remove_file(temp_path)?;
+    } else {
+        fs::create_dir_all(Path::new(&blob_path).parent().unwrap())?;
+        fs::rename(temp_path, blob_path)?;
+    }
+
+    Ok(LfsPointer { oid, size })
+}
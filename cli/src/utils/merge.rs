@@ -0,0 +1,539 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use super::types::{Tree, TreeEntry};
+use super::{read_stored_contents, restore_file};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiffLine {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+pub enum MergedFile {
+    Entry(TreeEntry),
+    Contents(Vec<u8>),
+    Deleted,
+}
+
+pub struct TreeMergeResult {
+    pub changes: Vec<(PathBuf, MergedFile)>,
+    pub conflicts: Vec<PathBuf>,
+}
+
+// Myers' O(ND) diff. Only the part of each round's frontier that can be
+// reached is kept for backtracking, so memory grows with the square of the
+// edit distance rather than with the size of the inputs.
+pub fn diff<T: PartialEq>(a: &[T], b: &[T]) -> Vec<DiffLine> {
+    let n = a.len() as isize;
+    let m = b.len() as isize;
+    let max = n + m;
+    let offset = max;
+    let mut v = vec![0isize; 2 * max as usize + 2];
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+
+    'rounds: for d in 0..=max {
+        trace.push(v[(offset - d) as usize..=(offset + d) as usize].to_vec());
+        let mut k = -d;
+        while k <= d {
+            let index = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[index - 1] < v[index + 1]) {
+                v[index + 1]
+            } else {
+                v[index - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[index] = x;
+            if x >= n && y >= m {
+                break 'rounds;
+            }
+            k += 2;
+        }
+    }
+
+    let mut edits = Vec::new();
+    let mut x = n;
+    let mut y = m;
+    for (d, frontier) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let at = |k: isize| frontier[(k + d) as usize];
+        let k = x - y;
+        let previous_k = if k == -d || (k != d && at(k - 1) < at(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let previous_x = if d == 0 { 0 } else { at(previous_k) };
+        let previous_y = previous_x - previous_k;
+
+        while x > previous_x && y > previous_y {
+            edits.push(DiffLine::Equal((x - 1) as usize, (y - 1) as usize));
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            if x == previous_x {
+                edits.push(DiffLine::Insert((y - 1) as usize));
+            } else {
+                edits.push(DiffLine::Delete((x - 1) as usize));
+            }
+        }
+        x = previous_x;
+        y = previous_y;
+    }
+
+    edits.reverse();
+    edits
+}
+
+pub fn split_lines(contents: &str) -> Vec<&str> {
+    contents.split_inclusive('\n').collect()
+}
+
+fn matching_blocks<T: PartialEq>(a: &[T], b: &[T]) -> Vec<(usize, usize, usize)> {
+    let mut blocks: Vec<(usize, usize, usize)> = Vec::new();
+    for edit in diff(a, b) {
+        if let DiffLine::Equal(a_index, b_index) = edit {
+            match blocks.last_mut() {
+                Some((a_start, b_start, length))
+                    if *a_start + *length == a_index && *b_start + *length == b_index =>
+                {
+                    *length += 1;
+                }
+                _ => blocks.push((a_index, b_index, 1)),
+            }
+        }
+    }
+
+    blocks
+}
+
+// Regions of the base that are unchanged in both versions, as
+// (base_start, base_end, a_start, a_end, b_start, b_end), ending with an empty
+// region at the end of every input.
+fn find_sync_regions<T: PartialEq>(
+    base: &[T],
+    a: &[T],
+    b: &[T],
+) -> Vec<(usize, usize, usize, usize, usize, usize)> {
+    let a_matches = matching_blocks(base, a);
+    let b_matches = matching_blocks(base, b);
+    let mut regions = Vec::new();
+    let mut a_index = 0;
+    let mut b_index = 0;
+
+    while a_index < a_matches.len() && b_index < b_matches.len() {
+        let (a_base, a_match, a_length) = a_matches[a_index];
+        let (b_base, b_match, b_length) = b_matches[b_index];
+
+        let start = a_base.max(b_base);
+        let end = (a_base + a_length).min(b_base + b_length);
+        if start < end {
+            let a_start = a_match + (start - a_base);
+            let b_start = b_match + (start - b_base);
+            regions.push((
+                start,
+                end,
+                a_start,
+                a_start + (end - start),
+                b_start,
+                b_start + (end - start),
+            ));
+        }
+
+        if a_base + a_length < b_base + b_length {
+            a_index += 1;
+        } else {
+            b_index += 1;
+        }
+    }
+
+    regions.push((base.len(), base.len(), a.len(), a.len(), b.len(), b.len()));
+    regions
+}
+
+fn push_conflict_marker(result: &mut String, marker: &str) {
+    if !result.is_empty() && !result.ends_with('\n') {
+        result.push('\n');
+    }
+    result.push_str(marker);
+    result.push('\n');
+}
+
+// Line based diff3 merge, returning the merged contents and whether any
+// conflict markers were written.
+pub fn merge_contents(
+    base: &str,
+    ours: &str,
+    theirs: &str,
+    ours_label: &str,
+    theirs_label: &str,
+) -> (String, bool) {
+    let base_lines = split_lines(base);
+    let ours_lines = split_lines(ours);
+    let theirs_lines = split_lines(theirs);
+    let mut result = String::new();
+    let mut conflicts = false;
+    let (mut base_index, mut ours_index, mut theirs_index) = (0, 0, 0);
+
+    for (base_start, base_end, ours_start, ours_end, theirs_start, theirs_end) in
+        find_sync_regions(&base_lines, &ours_lines, &theirs_lines)
+    {
+        let base_region = &base_lines[base_index..base_start];
+        let ours_region = &ours_lines[ours_index..ours_start];
+        let theirs_region = &theirs_lines[theirs_index..theirs_start];
+
+        if ours_region == theirs_region || theirs_region == base_region {
+            result.extend(ours_region.iter().copied());
+        } else if ours_region == base_region {
+            result.extend(theirs_region.iter().copied());
+        } else {
+            conflicts = true;
+            push_conflict_marker(&mut result, &format!("<<<<<<< {}", ours_label));
+            result.extend(ours_region.iter().copied());
+            push_conflict_marker(&mut result, "=======");
+            result.extend(theirs_region.iter().copied());
+            push_conflict_marker(&mut result, &format!(">>>>>>> {}", theirs_label));
+        }
+
+        result.extend(base_lines[base_start..base_end].iter().copied());
+        base_index = base_end;
+        ours_index = ours_end;
+        theirs_index = theirs_end;
+    }
+
+    (result, conflicts)
+}
+
+pub fn entries_equal(a: Option<&TreeEntry>, b: Option<&TreeEntry>) -> io::Result<bool> {
+    let (a, b) = match (a, b) {
+        (None, None) => return Ok(true),
+        (Some(a), Some(b)) => (a, b),
+        _ => return Ok(false),
+    };
+
+    if !a.metadata.chunks.is_empty() || !b.metadata.chunks.is_empty() {
+        return Ok(a.metadata.chunks == b.metadata.chunks);
+    }
+    if a.metadata.size != b.metadata.size {
+        return Ok(false);
+    }
+    if a.history_dir == b.history_dir && a.metadata.pointer_to_data == b.metadata.pointer_to_data {
+        return Ok(true);
+    }
+
+    Ok(read_stored_contents(a)? == read_stored_contents(b)?)
+}
+
+enum FileMerge {
+    Clean(Vec<u8>),
+    Conflicted(Vec<u8>),
+    Unmergeable,
+}
+
+fn merge_file(
+    base: Option<&TreeEntry>,
+    ours: &TreeEntry,
+    theirs: &TreeEntry,
+    ours_label: &str,
+    theirs_label: &str,
+) -> io::Result<FileMerge> {
+    if ours.metadata.lfs || theirs.metadata.lfs || base.is_some_and(|base| base.metadata.lfs) {
+        return Ok(FileMerge::Unmergeable);
+    }
+
+    let base_contents = match base {
+        Some(base) => read_stored_contents(base)?,
+        None => Vec::new(),
+    };
+    let ours_contents = read_stored_contents(ours)?;
+    let theirs_contents = read_stored_contents(theirs)?;
+
+    let (base, ours, theirs) = match (
+        std::str::from_utf8(&base_contents),
+        std::str::from_utf8(&ours_contents),
+        std::str::from_utf8(&theirs_contents),
+    ) {
+        (Ok(base), Ok(ours), Ok(theirs)) => (base, ours, theirs),
+        _ => return Ok(FileMerge::Unmergeable),
+    };
+
+    let (merged, conflicts) = merge_contents(base, ours, theirs, ours_label, theirs_label);
+    if conflicts {
+        Ok(FileMerge::Conflicted(merged.into_bytes()))
+    } else {
+        Ok(FileMerge::Clean(merged.into_bytes()))
+    }
+}
+
+// Computes the changes to apply on top of "ours" to bring in everything that
+// changed between "base" and "theirs". Files that cannot be merged line by line
+// (binary and LFS files, or files deleted on one side and modified on the
+// other) keep the version that still exists and are reported as conflicts.
+pub fn merge_trees(
+    base: &Tree,
+    ours: &Tree,
+    theirs: &Tree,
+    ours_label: &str,
+    theirs_label: &str,
+) -> io::Result<TreeMergeResult> {
+    let mut paths: Vec<&PathBuf> = base
+        .keys()
+        .chain(ours.keys())
+        .chain(theirs.keys())
+        .collect();
+    paths.sort();
+    paths.dedup();
+
+    let mut result = TreeMergeResult {
+        changes: Vec::new(),
+        conflicts: Vec::new(),
+    };
+
+    for path in paths {
+        let base_entry = base.get(path);
+        let ours_entry = ours.get(path);
+        let theirs_entry = theirs.get(path);
+
+        if entries_equal(ours_entry, theirs_entry)? || entries_equal(base_entry, theirs_entry)? {
+            continue;
+        }
+
+        if entries_equal(base_entry, ours_entry)? {
+            let merged_file = match theirs_entry {
+                Some(theirs_entry) => MergedFile::Entry(theirs_entry.clone()),
+                None => MergedFile::Deleted,
+            };
+            result.changes.push((path.clone(), merged_file));
+            continue;
+        }
+
+        match (ours_entry, theirs_entry) {
+            (Some(ours_entry), Some(theirs_entry)) => {
+                match merge_file(
+                    base_entry,
+                    ours_entry,
+                    theirs_entry,
+                    ours_label,
+                    theirs_label,
+                )? {
+                    FileMerge::Clean(contents) => {
+                        result
+                            .changes
+                            .push((path.clone(), MergedFile::Contents(contents)));
+                    }
+                    FileMerge::Conflicted(contents) => {
+                        result
+                            .changes
+                            .push((path.clone(), MergedFile::Contents(contents)));
+                        result.conflicts.push(path.clone());
+                    }
+                    FileMerge::Unmergeable => result.conflicts.push(path.clone()),
+                }
+            }
+            (None, Some(theirs_entry)) => {
+                result
+                    .changes
+                    .push((path.clone(), MergedFile::Entry(theirs_entry.clone())));
+                result.conflicts.push(path.clone());
+            }
+            _ => result.conflicts.push(path.clone()),
+        }
+    }
+
+    Ok(result)
+}
+
+pub fn apply_merge_changes(changes: &[(PathBuf, MergedFile)]) -> io::Result<()> {
+    for (path, merged_file) in changes {
+        match merged_file {
+            MergedFile::Entry(entry) => restore_file(&entry.history_dir, &entry.metadata, path)?,
+            MergedFile::Contents(contents) => {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                File::create(path)?.write_all(contents)?;
+            }
+            MergedFile::Deleted => match fs::remove_file(path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            },
+        }
+    }
+
+    Ok(())
+}
+
+pub fn has_conflict_markers(path: &Path) -> io::Result<bool> {
+    let contents = match fs::read(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+
+    Ok(String::from_utf8_lossy(&contents).lines().any(|line| {
+        line.starts_with("<<<<<<< ") || line == "=======" || line.starts_with(">>>>>>> ")
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Rebuilds b from a and the edits, checking that they are consistent.
+    fn apply_edits(a: &[char], b: &[char], edits: &[DiffLine]) -> Vec<char> {
+        let (mut a_index, mut b_index) = (0, 0);
+        let mut result = Vec::new();
+        for edit in edits {
+            match *edit {
+                DiffLine::Equal(a_line, b_line) => {
+                    assert_eq!((a_line, b_line), (a_index, b_index));
+                    assert_eq!(a[a_line], b[b_line]);
+                    result.push(a[a_line]);
+                    a_index += 1;
+                    b_index += 1;
+                }
+                DiffLine::Delete(a_line) => {
+                    assert_eq!(a_line, a_index);
+                    a_index += 1;
+                }
+                DiffLine::Insert(b_line) => {
+                    assert_eq!(b_line, b_index);
+                    result.push(b[b_line]);
+                    b_index += 1;
+                }
+            }
+        }
+        assert_eq!((a_index, b_index), (a.len(), b.len()));
+        result
+    }
+
+    fn edit_count(edits: &[DiffLine]) -> usize {
+        edits
+            .iter()
+            .filter(|edit| !matches!(edit, DiffLine::Equal(_, _)))
+            .count()
+    }
+
+    #[test]
+    fn diff_finds_a_shortest_edit_script() {
+        let a: Vec<char> = "ABCABBA".chars().collect();
+        let b: Vec<char> = "CBABAC".chars().collect();
+        let edits = diff(&a, &b);
+
+        assert_eq!(apply_edits(&a, &b, &edits), b);
+        assert_eq!(edit_count(&edits), 5);
+    }
+
+    #[test]
+    fn diff_handles_empty_and_equal_inputs() {
+        let a: Vec<char> = "abc".chars().collect();
+
+        assert!(diff::<char>(&[], &[]).is_empty());
+        assert_eq!(
+            diff(&a, &[]),
+            [
+                DiffLine::Delete(0),
+                DiffLine::Delete(1),
+                DiffLine::Delete(2)
+            ]
+        );
+        assert_eq!(
+            diff(&[], &a),
+            [
+                DiffLine::Insert(0),
+                DiffLine::Insert(1),
+                DiffLine::Insert(2)
+            ]
+        );
+        assert_eq!(
+            diff(&a, &a),
+            [
+                DiffLine::Equal(0, 0),
+                DiffLine::Equal(1, 1),
+                DiffLine::Equal(2, 2)
+            ]
+        );
+    }
+
+    #[test]
+    fn split_lines_keeps_line_endings() {
+        assert_eq!(split_lines("a\nb\nc"), ["a\n", "b\n", "c"]);
+        assert!(split_lines("").is_empty());
+    }
+
+    #[test]
+    fn merge_combines_changes_to_different_lines() {
+        let base = "one\ntwo\nthree\nfour\nfive\n";
+        let ours = "ONE\ntwo\nthree\nfour\nfive\n";
+        let theirs = "one\ntwo\nthree\nfour\nFIVE\nsix\n";
+
+        assert_eq!(
+            merge_contents(base, ours, theirs, "ours", "theirs"),
+            ("ONE\ntwo\nthree\nfour\nFIVE\nsix\n".to_owned(), false)
+        );
+    }
+
+    #[test]
+    fn merge_takes_the_only_changed_side() {
+        let base = "one\ntwo\n";
+        let changed = "one\n2\n";
+
+        assert_eq!(
+            merge_contents(base, changed, base, "ours", "theirs"),
+            (changed.to_owned(), false)
+        );
+        assert_eq!(
+            merge_contents(base, base, changed, "ours", "theirs"),
+            (changed.to_owned(), false)
+        );
+        assert_eq!(
+            merge_contents(base, changed, changed, "ours", "theirs"),
+            (changed.to_owned(), false)
+        );
+    }
+
+    #[test]
+    fn merge_marks_conflicting_changes() {
+        let base = "one\ntwo\nthree\n";
+        let ours = "one\nours\nthree\n";
+        let theirs = "one\ntheirs\nthree\n";
+
+        assert_eq!(
+            merge_contents(base, ours, theirs, "HEAD", "feature"),
+            (
+                "one\n<<<<<<< HEAD\nours\n=======\ntheirs\n>>>>>>> feature\nthree\n".to_owned(),
+                true
+            )
+        );
+    }
+
+    #[test]
+    fn conflict_markers_start_on_their_own_line() {
+        let (merged, conflicts) = merge_contents("base", "ours", "theirs", "HEAD", "feature");
+
+        assert!(conflicts);
+        assert_eq!(
+            merged,
+            "<<<<<<< HEAD\nours\n=======\ntheirs\n>>>>>>> feature\n"
+        );
+    }
+
+    #[test]
+    fn merge_of_deletion_and_unrelated_edit_is_clean() {
+        let base = "one\ntwo\nthree\nfour\n";
+        let ours = "one\nthree\nfour\n";
+        let theirs = "one\ntwo\nthree\n4\n";
+
+        assert_eq!(
+            merge_contents(base, ours, theirs, "ours", "theirs"),
+            ("one\nthree\n4\n".to_owned(), false)
+        );
+    }
+}
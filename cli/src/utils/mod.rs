@@ -1,20 +1,54 @@
+pub mod bisect;
+pub mod blame;
+pub mod chunks;
 pub mod constants;
 pub mod dates;
+pub mod encryption;
 pub mod fs_provider;
+pub mod fs_remote;
+pub mod grep;
+pub mod history_import;
+pub mod history_paths;
+pub mod lfs;
+pub mod merge;
+pub mod patch;
+pub mod progress;
+pub mod refs;
+pub mod remote;
+pub mod revisions;
 pub mod s3_provider;
+pub mod shallow;
 pub mod types;
 
+use self::chunks::{copy_chunks, object_path, store_file_chunks};
 use self::constants::{
-    COMMIT_METADATA_RELATIVE_PATH, IGNORE_FILES_PATH, MAIN_COMMITS_METADATA_FILE_PATH,
+    AUTHOR_ENV_VAR, BISECT_STATE_FILE_PATH, CLONE_STATE_FILE_PATH, COMMIT_EDITMSG_FILE_PATH,
+    COMMIT_METADATA_RELATIVE_PATH, DATA_RELATIVE_PATH, DEFAULT_REMOTE, EDITOR_ENV_VAR,
+    HEADS_REFS_PREFIX, IGNORE_FILES_PATH, LARGE_FILE_THRESHOLD, MAIN_COMMITS_METADATA_FILE_PATH,
+    MERGE_STATE_FILE_PATH, REMOTES_CONFIG_FILE_PATH, REMOTE_REPOSITORY_REFERENCE_FILE_PATH,
+    REVERT_STATE_FILE_PATH, SEQUENCER_STATE_FILE_PATH, TAGS_REFS_PREFIX, VSM_DIR,
+};
+use self::fs_provider::{
+    copy_part_of_file, get_file_paths_recursively, read_part_of_file, write_to_data_file,
+};
+use self::history_paths::{
+    history_dir_for_path, list_history_dirs, migrate_history_layout, normalize_path,
+    read_history_path,
+};
+use self::lfs::{format_lfs_pointer, lfs_blob_path, parse_lfs_pointer, store_lfs_blob};
+use self::refs::{list_ref_names, migrate_refs, read_ref, write_atomically};
+use self::shallow::read_shallow_commits;
+use self::types::{
+    BisectState, CloneState, Commit, CommitMetadata, MergeState, RemoteConfig, RemotesConfig,
+    RevertState, SequencerState, Tree, TreeEntry,
 };
-use self::fs_provider::{get_file_paths_recursively, read_part_of_file};
-use self::types::{Commit, CommitMetadata};
 use rand::distributions::Alphanumeric;
 use rand::Rng;
+use std::collections::HashSet;
 use std::fs::File;
 use std::fs::{self};
-use std::io::{self, Read, Write};
-use std::path::Path;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
 use std::thread;
 
 pub fn generate_commit_id() -> String {
@@ -25,6 +59,57 @@ pub fn generate_commit_id() -> String {
         .collect()
 }
 
+pub fn get_author() -> String {
+    std::env::var(AUTHOR_ENV_VAR)
+        .or_else(|_| std::env::var("USER"))
+        .unwrap_or_else(|_| "unknown".to_owned())
+}
+
+// The editor command may contain arguments (e.g. "code --wait"), so it is run
+// through the shell with the file path as its last argument.
+pub fn launch_editor(file_path: &str) -> std::io::Result<()> {
+    let editor = std::env::var(EDITOR_ENV_VAR)
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_owned());
+    let status = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(editor.clone() + " \"$@\"")
+        .arg(&editor)
+        .arg(file_path)
+        .status()?;
+    if !status.success() {
+        return Err(std::io::Error::other(format!(
+            "Editor {} exited with {}",
+            editor, status
+        )));
+    }
+
+    Ok(())
+}
+
+// Lines starting with "#" are instructions and are dropped from the message.
+pub fn edit_message(message: &str, instructions: &str) -> std::io::Result<String> {
+    File::create(COMMIT_EDITMSG_FILE_PATH)?
+        .write_all(format!("{}\n\n{}", message, instructions).as_bytes())?;
+    launch_editor(COMMIT_EDITMSG_FILE_PATH)?;
+
+    let edited_message = fs::read_to_string(COMMIT_EDITMSG_FILE_PATH)?
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .collect::<Vec<&str>>()
+        .join("\n")
+        .trim()
+        .to_owned();
+    if edited_message.is_empty() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Aborting because the commit message is empty",
+        ));
+    }
+
+    Ok(edited_message)
+}
+
 pub fn check_if_initialized() -> std::io::Result<()> {
     let history_path = ".history".to_owned();
     let git_path = ".git".to_owned();
@@ -38,6 +123,22 @@ pub fn check_if_initialized() -> std::io::Result<()> {
     Ok(())
 }
 
+pub fn open_repository() -> std::io::Result<()> {
+    if fs::read_dir(VSM_DIR).is_err() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "Not a repository, run init first",
+        ));
+    }
+
+    migrate_repository()
+}
+
+pub fn migrate_repository() -> std::io::Result<()> {
+    migrate_history_layout()?;
+    migrate_refs()
+}
+
 pub fn commits_metadata(path: &str) -> std::io::Result<Vec<CommitMetadata>> {
     let file_metadata_string_result =
         fs::read_to_string(path.to_owned() + COMMIT_METADATA_RELATIVE_PATH)?;
@@ -59,72 +160,573 @@ pub fn list_commits(path: &str) -> std::io::Result<Vec<Commit>> {
     })
 }
 
-pub fn add_root_commit_metadata(commit_metadata: Commit) -> std::io::Result<()> {
-    let mut commits_metadata = list_commits(MAIN_COMMITS_METADATA_FILE_PATH)?;
-    commits_metadata.push(commit_metadata);
-
-    let commits_metadata_string = serde_json::to_string(&commits_metadata).map_err(|e| {
+pub fn write_commits(commits: &[Commit]) -> std::io::Result<()> {
+    let commits_metadata_string = serde_json::to_string(commits).map_err(|e| {
         io::Error::new(
             io::ErrorKind::InvalidData,
             format!("Failed to parse metadata: {}", e),
         )
     })?;
 
-    File::create(MAIN_COMMITS_METADATA_FILE_PATH)?.write_all(commits_metadata_string.as_bytes())?;
+    // Rewritten through a rename so that an interrupted write never leaves a
+    // truncated commits list behind
+    write_atomically(
+        MAIN_COMMITS_METADATA_FILE_PATH,
+        commits_metadata_string.as_bytes(),
+    )
+}
+
+pub fn add_root_commit_metadata(commit_metadata: Commit) -> std::io::Result<()> {
+    let mut commits_metadata = list_commits(MAIN_COMMITS_METADATA_FILE_PATH)?;
+    commits_metadata.push(commit_metadata);
+
+    write_commits(&commits_metadata)
+}
+
+// Follows the first parents from the given commit, oldest commit first.
+pub fn commit_history(commits: &[Commit], commit_id: &str) -> Vec<Commit> {
+    let mut history = Vec::new();
+    let mut next_commit_id = Some(commit_id.to_owned());
+    while let Some(commit_id) = next_commit_id {
+        let commit = match find_commit_by_commit_id(commits, &commit_id) {
+            Some(commit) => commit,
+            None => break,
+        };
+        next_commit_id = commit.parents.first().cloned();
+        history.push(commit);
+    }
+
+    history.reverse();
+    history
+}
+
+pub fn is_ancestor(commits: &[Commit], ancestor_id: &str, commit_id: &str) -> bool {
+    let mut pending = vec![commit_id.to_owned()];
+    let mut visited = Vec::new();
+    while let Some(commit_id) = pending.pop() {
+        if commit_id == ancestor_id {
+            return true;
+        }
+        if visited.contains(&commit_id) {
+            continue;
+        }
+        if let Some(commit) = find_commit_by_commit_id(commits, &commit_id) {
+            pending.extend(commit.parents);
+        }
+        visited.push(commit_id);
+    }
+
+    false
+}
+
+fn new_commit_metadata(commit: &Commit, size: u64) -> CommitMetadata {
+    CommitMetadata {
+        date: commit.date.clone(),
+        description: commit.description.clone(),
+        commit_id: commit.commit_id.clone(),
+        pointer_to_data: 0,
+        size,
+        chunks: Vec::new(),
+        lfs: false,
+    }
+}
+
+pub fn store_file_contents(
+    file_path: &Path,
+    history_dir: &str,
+    last_committed_metadata: Option<&CommitMetadata>,
+    commit: &Commit,
+) -> std::io::Result<CommitMetadata> {
+    let mut commit_metadata = new_commit_metadata(commit, fs::metadata(file_path)?.len());
+
+    if commit_metadata.size >= LARGE_FILE_THRESHOLD {
+        commit_metadata.chunks = store_file_chunks(file_path)?;
+        return Ok(commit_metadata);
+    }
+
+    let file_contents = fs::read(file_path)?;
+    store_data_contents(
+        history_dir,
+        &file_contents,
+        last_committed_metadata,
+        commit_metadata,
+    )
+}
+
+pub fn store_lfs_file_contents(
+    file_path: &Path,
+    history_dir: &str,
+    last_committed_metadata: Option<&CommitMetadata>,
+    commit: &Commit,
+) -> std::io::Result<CommitMetadata> {
+    let pointer = store_lfs_blob(file_path)?;
+    let pointer_contents = format_lfs_pointer(&pointer).into_bytes();
+    let mut commit_metadata = new_commit_metadata(commit, pointer_contents.len() as u64);
+    commit_metadata.lfs = true;
+
+    store_data_contents(
+        history_dir,
+        &pointer_contents,
+        last_committed_metadata,
+        commit_metadata,
+    )
+}
+
+fn store_data_contents(
+    history_dir: &str,
+    file_contents: &[u8],
+    last_committed_metadata: Option<&CommitMetadata>,
+    mut commit_metadata: CommitMetadata,
+) -> std::io::Result<CommitMetadata> {
+    let data_file_path = history_dir.to_owned() + DATA_RELATIVE_PATH;
+
+    if let Some(last_committed_metadata) = last_committed_metadata
+        .filter(|metadata| metadata.chunks.is_empty() && metadata.size == commit_metadata.size)
+    {
+        let last_committed_file_contents = read_part_of_file(
+            &data_file_path,
+            last_committed_metadata.pointer_to_data,
+            last_committed_metadata.size as usize,
+        )?;
+        if last_committed_file_contents == file_contents {
+            commit_metadata.pointer_to_data = last_committed_metadata.pointer_to_data;
+            return Ok(commit_metadata);
+        }
+    }
+
+    let data_file_exists = Path::new(&data_file_path).exists();
+    if data_file_exists {
+        commit_metadata.pointer_to_data = fs::metadata(&data_file_path)?.len();
+    }
+    write_to_data_file(history_dir, file_contents, data_file_exists)?;
+
+    Ok(commit_metadata)
+}
+
+// LFS files are written from their blob when it has been fetched, otherwise
+// the pointer record itself is written in their place.
+pub fn write_file_contents(
+    history_dir: &str,
+    commit_metadata: &CommitMetadata,
+    destination: &mut impl Write,
+) -> std::io::Result<u64> {
+    if !commit_metadata.chunks.is_empty() {
+        return copy_chunks(&commit_metadata.chunks, destination);
+    }
+
+    let data_file_path = history_dir.to_owned() + DATA_RELATIVE_PATH;
+    if commit_metadata.lfs {
+        let pointer_contents = read_part_of_file(
+            &data_file_path,
+            commit_metadata.pointer_to_data,
+            commit_metadata.size as usize,
+        )?;
+        if let Some(pointer) = parse_lfs_pointer(&pointer_contents) {
+            let blob_path = lfs_blob_path(&pointer.oid);
+            if Path::new(&blob_path).exists() {
+                return io::copy(&mut File::open(blob_path)?, destination);
+            }
+        }
+        destination.write_all(&pointer_contents)?;
+        return Ok(pointer_contents.len() as u64);
+    }
+
+    copy_part_of_file(
+        &data_file_path,
+        commit_metadata.pointer_to_data,
+        commit_metadata.size,
+        destination,
+    )
+}
+
+pub fn restore_file(
+    history_dir: &str,
+    commit_metadata: &CommitMetadata,
+    file_path: &Path,
+) -> std::io::Result<()> {
+    if let Some(parent) = file_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = BufWriter::new(File::create(file_path)?);
+    write_file_contents(history_dir, commit_metadata, &mut file)?;
+    file.flush()
+}
+
+// Reads what is stored for the entry, which is the pointer record for LFS files
+pub fn read_stored_contents(entry: &TreeEntry) -> std::io::Result<Vec<u8>> {
+    if !entry.metadata.chunks.is_empty() {
+        let mut contents = Vec::new();
+        copy_chunks(&entry.metadata.chunks, &mut contents)?;
+        return Ok(contents);
+    }
+
+    read_part_of_file(
+        &(entry.history_dir.clone() + DATA_RELATIVE_PATH),
+        entry.metadata.pointer_to_data,
+        entry.metadata.size as usize,
+    )
+}
+
+// Looks a single file up in a commit without loading the whole tree.
+pub fn load_tree_entry(commit_id: &str, file_path: &Path) -> std::io::Result<Option<TreeEntry>> {
+    let history_dir = history_dir_for_path(file_path);
+    let commits_metadata = match commits_metadata(&history_dir) {
+        Ok(commits_metadata) => commits_metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    Ok(
+        find_metadata_by_commit_id(&commits_metadata, commit_id).map(|metadata| TreeEntry {
+            history_dir,
+            metadata,
+        }),
+    )
+}
+
+pub fn load_commit_tree(commit_id: &str) -> std::io::Result<Tree> {
+    let mut tree = Tree::new();
+    for history_dir in list_history_dirs()? {
+        let commits_metadata = commits_metadata(&history_dir)?;
+        if let Some(metadata) = find_metadata_by_commit_id(&commits_metadata, commit_id) {
+            let file_path = read_history_path(&history_dir)?;
+            tree.insert(
+                file_path,
+                TreeEntry {
+                    history_dir,
+                    metadata,
+                },
+            );
+        }
+    }
+
+    Ok(tree)
+}
+
+struct ContentsComparator<R: Read> {
+    reader: R,
+    matches: bool,
+}
+
+impl<R: Read> Write for ContentsComparator<R> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.matches {
+            let mut expected = vec![0; buf.len()];
+            self.matches = self.reader.read_exact(&mut expected).is_ok() && expected == buf;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub fn file_matches_entry(file_path: &Path, entry: &TreeEntry) -> std::io::Result<bool> {
+    let file = match File::open(file_path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    if !entry.metadata.lfs && file.metadata()?.len() != entry.metadata.size {
+        return Ok(false);
+    }
+
+    let mut comparator = ContentsComparator {
+        reader: io::BufReader::new(file),
+        matches: true,
+    };
+    write_file_contents(&entry.history_dir, &entry.metadata, &mut comparator)?;
+
+    Ok(comparator.matches && comparator.reader.read(&mut [0])? == 0)
+}
+
+// Paths whose working tree contents differ from the tree, including files
+// missing from the working tree and files the tree does not have.
+pub fn working_tree_changes(tree: &Tree) -> std::io::Result<Vec<PathBuf>> {
+    let files_to_ignore = list_files_ignore();
+    let file_paths: Vec<PathBuf> = get_file_paths_recursively(None, Some(&files_to_ignore))
+        .iter()
+        .map(|file_path| normalize_path(file_path))
+        .collect();
+
+    let mut changes = Vec::new();
+    for file_path in &file_paths {
+        match tree.get(file_path) {
+            Some(entry) if file_matches_entry(file_path, entry)? => {}
+            _ => changes.push(file_path.clone()),
+        }
+    }
+    for path in tree.keys() {
+        if !file_paths.contains(path) {
+            changes.push(path.clone());
+        }
+    }
+
+    Ok(changes)
+}
+
+pub fn read_revert_state() -> std::io::Result<RevertState> {
+    let revert_state_string = fs::read_to_string(REVERT_STATE_FILE_PATH)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::NotFound, "No revert in progress"))?;
+    serde_json::from_str(&revert_state_string).map_err(|e| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Failed to parse revert state: {}", e),
+        )
+    })
+}
+
+pub fn write_revert_state(revert_state: &RevertState) -> std::io::Result<()> {
+    let revert_state_string = serde_json::to_string(revert_state)?;
+    File::create(REVERT_STATE_FILE_PATH)?.write_all(revert_state_string.as_bytes())
+}
+
+pub fn read_merge_state() -> std::io::Result<MergeState> {
+    let merge_state_string = fs::read_to_string(MERGE_STATE_FILE_PATH)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::NotFound, "No merge in progress"))?;
+    serde_json::from_str(&merge_state_string).map_err(|e| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Failed to parse merge state: {}", e),
+        )
+    })
+}
+
+pub fn write_merge_state(merge_state: &MergeState) -> std::io::Result<()> {
+    let merge_state_string = serde_json::to_string(merge_state)?;
+    File::create(MERGE_STATE_FILE_PATH)?.write_all(merge_state_string.as_bytes())
+}
+
+pub fn read_clone_state() -> std::io::Result<CloneState> {
+    let clone_state_string = fs::read_to_string(CLONE_STATE_FILE_PATH)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::NotFound, "No clone in progress"))?;
+    // Clones started before their settings were recorded left an empty file
+    if clone_state_string.trim().is_empty() {
+        return Ok(CloneState::default());
+    }
+    serde_json::from_str(&clone_state_string).map_err(|e| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Failed to parse clone state: {}", e),
+        )
+    })
+}
+
+pub fn write_clone_state(clone_state: &CloneState) -> std::io::Result<()> {
+    let clone_state_string = serde_json::to_string(clone_state)?;
+    File::create(CLONE_STATE_FILE_PATH)?.write_all(clone_state_string.as_bytes())
+}
+
+pub fn read_sequencer_state() -> std::io::Result<Option<SequencerState>> {
+    let sequencer_state_string = match fs::read_to_string(SEQUENCER_STATE_FILE_PATH) {
+        Ok(sequencer_state_string) => sequencer_state_string,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    serde_json::from_str(&sequencer_state_string)
+        .map(Some)
+        .map_err(|e| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Failed to parse sequencer state: {}", e),
+            )
+        })
+}
+
+pub fn write_sequencer_state(sequencer_state: &SequencerState) -> std::io::Result<()> {
+    let sequencer_state_string = serde_json::to_string(sequencer_state)?;
+    File::create(SEQUENCER_STATE_FILE_PATH)?.write_all(sequencer_state_string.as_bytes())
+}
+
+pub fn remove_sequencer_state() -> std::io::Result<()> {
+    match fs::remove_file(SEQUENCER_STATE_FILE_PATH) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+// Repositories with a single remote kept it in .history/remote, first as a
+// bucket name and then as JSON settings, and it becomes the default remote.
+pub fn read_remotes_config() -> std::io::Result<RemotesConfig> {
+    match fs::read_to_string(REMOTES_CONFIG_FILE_PATH) {
+        Ok(remotes_config_string) => {
+            return serde_json::from_str(&remotes_config_string).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Failed to parse remotes: {}", e),
+                )
+            })
+        }
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+
+    let mut remotes_config = RemotesConfig::default();
+    let legacy_remote_string = match fs::read_to_string(REMOTE_REPOSITORY_REFERENCE_FILE_PATH) {
+        Ok(legacy_remote_string) => legacy_remote_string,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(remotes_config),
+        Err(e) => return Err(e),
+    };
+    let remote_config = serde_json::from_str(&legacy_remote_string).unwrap_or(RemoteConfig {
+        url: legacy_remote_string.trim().to_owned(),
+        ..RemoteConfig::default()
+    });
+    remotes_config
+        .remotes
+        .insert(DEFAULT_REMOTE.to_owned(), remote_config);
+
+    Ok(remotes_config)
+}
+
+pub fn write_remotes_config(remotes_config: &RemotesConfig) -> std::io::Result<()> {
+    let remotes_config_string = serde_json::to_string_pretty(remotes_config)?;
+    write_atomically(REMOTES_CONFIG_FILE_PATH, remotes_config_string.as_bytes())?;
+    match fs::remove_file(REMOTE_REPOSITORY_REFERENCE_FILE_PATH) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+pub fn read_bisect_state() -> std::io::Result<Option<BisectState>> {
+    let bisect_state_string = match fs::read_to_string(BISECT_STATE_FILE_PATH) {
+        Ok(bisect_state_string) => bisect_state_string,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    serde_json::from_str(&bisect_state_string)
+        .map(Some)
+        .map_err(|e| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Failed to parse bisect state: {}", e),
+            )
+        })
+}
+
+pub fn write_bisect_state(bisect_state: &BisectState) -> std::io::Result<()> {
+    let bisect_state_string = serde_json::to_string(bisect_state)?;
+    File::create(BISECT_STATE_FILE_PATH)?.write_all(bisect_state_string.as_bytes())
+}
+
+pub fn remove_bisect_state() -> std::io::Result<()> {
+    match fs::remove_file(BISECT_STATE_FILE_PATH) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+fn incomplete_history(reason: String) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("Incomplete history: {}", reason),
+    )
+}
+
+// Checks that every ref points to a known commit, every commit's parents are
+// known and the data of every stored file version is present, so that a
+// truncated download is caught before anything is checked out. Shallow
+// clones leave out the parents of their oldest commits and the data of the
+// commits they do not have.
+pub fn verify_history() -> std::io::Result<()> {
+    let commits = list_commits(MAIN_COMMITS_METADATA_FILE_PATH)?;
+    let commit_ids: HashSet<&str> = commits
+        .iter()
+        .map(|commit| commit.commit_id.as_str())
+        .collect();
+    let shallow_commits = read_shallow_commits()?;
+
+    for commit in &commits {
+        if shallow_commits.contains(&commit.commit_id) {
+            continue;
+        }
+        for parent in &commit.parents {
+            if !commit_ids.contains(parent.as_str()) {
+                return Err(incomplete_history(format!(
+                    "parent {} of commit {} is missing",
+                    parent, commit.commit_id
+                )));
+            }
+        }
+    }
+
+    let mut ref_names: Vec<String> = list_ref_names(HEADS_REFS_PREFIX)?
+        .iter()
+        .map(|branch| HEADS_REFS_PREFIX.to_owned() + branch)
+        .collect();
+    ref_names.extend(
+        list_ref_names(TAGS_REFS_PREFIX)?
+            .iter()
+            .map(|tag| TAGS_REFS_PREFIX.to_owned() + tag),
+    );
+    for ref_name in ref_names {
+        if let Some(commit_id) = read_ref(&ref_name)? {
+            if !commit_ids.contains(commit_id.as_str()) {
+                return Err(incomplete_history(format!(
+                    "{} points to missing commit {}",
+                    ref_name, commit_id
+                )));
+            }
+        }
+    }
+
+    for history_dir in list_history_dirs()? {
+        let file_path = read_history_path(&history_dir)?;
+        let data_size = match fs::metadata(history_dir.clone() + DATA_RELATIVE_PATH) {
+            Ok(metadata) => metadata.len(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e),
+        };
+        for metadata in commits_metadata(&history_dir)? {
+            if !commit_ids.contains(metadata.commit_id.as_str()) {
+                continue;
+            }
+            let is_complete = if metadata.chunks.is_empty() {
+                metadata.pointer_to_data + metadata.size <= data_size
+            } else {
+                metadata
+                    .chunks
+                    .iter()
+                    .all(|object_id| Path::new(&object_path(object_id)).exists())
+            };
+            if !is_complete {
+                return Err(incomplete_history(format!(
+                    "data of {} in commit {} is missing",
+                    file_path.display(),
+                    metadata.commit_id
+                )));
+            }
+        }
+    }
 
     Ok(())
 }
 
 pub fn load_commit(branch_id: &str) -> std::io::Result<()> {
-    let history_path = ".history".to_owned();
+    let mut join_handles = Vec::new();
+    for last_committed_file_path in list_history_dirs()? {
+        let branch_id = branch_id.to_owned();
+        let handle = thread::spawn(move || -> std::io::Result<()> {
+            let commits_metadata = commits_metadata(&last_committed_file_path)?;
+            let target_commit_metadata =
+                match find_metadata_by_commit_id(&commits_metadata, &branch_id) {
+                    Some(target_commit_metadata) => target_commit_metadata,
+                    None => return Ok(()),
+                };
 
-    if let Ok(entries) = fs::read_dir(history_path.clone()) {
-        let mut join_handles = Vec::new();
-        for entry in entries {
-            let branch_id = branch_id.to_owned();
-            let history_path = history_path.clone();
-            let handle = thread::spawn(move || {
-                if let Ok(entry) = entry {
-                    let entry_path = entry.path();
-                    if entry_path.is_file() {
-                        return;
-                    }
-                    let file_name = entry_path.file_name().unwrap().to_str().unwrap().to_owned();
-                    let file_name_for_history = file_name.replace("_", "/");
-                    let last_committed_file_path: String = history_path.clone() + &"/" + &file_name;
-
-                    let commits_metadata = commits_metadata(&last_committed_file_path).unwrap();
-                    let target_commit_metadata_result =
-                        find_metadata_by_commit_id(&commits_metadata, &branch_id);
-                    let target_commit_metadata = match target_commit_metadata_result {
-                        None => commits_metadata.last().unwrap().clone(),
-                        _ => target_commit_metadata_result.unwrap(),
-                    };
-
-                    let last_committed_file_pointer = target_commit_metadata.pointer_to_data;
-                    let last_committed_file_size = target_commit_metadata.size;
-
-                    let last_committed_file_contents = read_part_of_file(
-                        &(last_committed_file_path.clone() + "/data.bin"),
-                        last_committed_file_pointer as u64,
-                        last_committed_file_size as usize,
-                    )
-                    .unwrap();
-
-                    fs::create_dir_all(Path::new(&file_name_for_history).parent().unwrap())
-                        .unwrap();
-                    let mut file = File::create(file_name_for_history).unwrap();
-                    file.write_all(last_committed_file_contents.as_bytes())
-                        .unwrap();
-                }
-            });
-
-            join_handles.push(handle);
-        }
-
-        for handle in join_handles {
-            handle.join().unwrap();
-        }
+            let file_path = read_history_path(&last_committed_file_path)?;
+            restore_file(
+                &last_committed_file_path,
+                &target_commit_metadata,
+                &file_path,
+            )
+        });
+
+        join_handles.push(handle);
+    }
+
+    for handle in join_handles {
+        handle.join().unwrap()?;
     }
 
     Ok(())
@@ -143,7 +745,7 @@ pub fn find_metadata_by_commit_id(
     None
 }
 
-pub fn find_commit_by_commit_id(metadata: &Vec<Commit>, commit_id: &str) -> Option<Commit> {
+pub fn find_commit_by_commit_id(metadata: &[Commit], commit_id: &str) -> Option<Commit> {
     for commit_metadata in metadata {
         if commit_metadata.commit_id == commit_id {
             return Some(commit_metadata.clone());
@@ -153,6 +755,15 @@ pub fn find_commit_by_commit_id(metadata: &Vec<Commit>, commit_id: &str) -> Opti
     None
 }
 
+pub fn find_commit(commits: &[Commit], commit_id: &str) -> std::io::Result<Commit> {
+    find_commit_by_commit_id(commits, commit_id).ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("Commit {} not found", commit_id),
+        )
+    })
+}
+
 pub fn list_files_ignore() -> Vec<String> {
     let mut ignore_file = match File::open(IGNORE_FILES_PATH) {
         Ok(file) => file,
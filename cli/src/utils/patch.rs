@@ -0,0 +1,160 @@
+use std::io::{self, Write};
+use std::path::Path;
+
+use super::merge::{diff, entries_equal, split_lines, DiffLine};
+use super::read_stored_contents;
+use super::types::{Tree, TreeEntry};
+
+const CONTEXT_LINES: usize = 3;
+
+pub fn is_binary(contents: &[u8]) -> bool {
+    contents.contains(&0) || std::str::from_utf8(contents).is_err()
+}
+
+fn write_line(out: &mut impl Write, prefix: char, line: &str) -> io::Result<()> {
+    write!(out, "{}{}", prefix, line)?;
+    if !line.ends_with('\n') {
+        writeln!(out)?;
+        writeln!(out, "\\ No newline at end of file")?;
+    }
+
+    Ok(())
+}
+
+// Writes the hunks of a unified diff between two texts, each change being
+// surrounded by up to three unchanged lines.
+pub fn write_hunks(old: &str, new: &str, out: &mut impl Write) -> io::Result<()> {
+    let old_lines = split_lines(old);
+    let new_lines = split_lines(new);
+    let edits = diff(&old_lines, &new_lines);
+
+    // Position in both texts before each edit
+    let mut positions = Vec::with_capacity(edits.len() + 1);
+    let (mut old_position, mut new_position) = (0, 0);
+    for edit in &edits {
+        positions.push((old_position, new_position));
+        match edit {
+            DiffLine::Equal(_, _) => {
+                old_position += 1;
+                new_position += 1;
+            }
+            DiffLine::Delete(_) => old_position += 1,
+            DiffLine::Insert(_) => new_position += 1,
+        }
+    }
+    positions.push((old_position, new_position));
+
+    let changes: Vec<usize> = edits
+        .iter()
+        .enumerate()
+        .filter(|(_, edit)| !matches!(edit, DiffLine::Equal(_, _)))
+        .map(|(index, _)| index)
+        .collect();
+
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for index in changes {
+        let start = index.saturating_sub(CONTEXT_LINES);
+        let end = (index + 1 + CONTEXT_LINES).min(edits.len());
+        match hunks.last_mut() {
+            Some((_, hunk_end)) if start <= *hunk_end => *hunk_end = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    for (start, end) in hunks {
+        let (old_start, new_start) = positions[start];
+        let (old_end, new_end) = positions[end];
+        let old_count = old_end - old_start;
+        let new_count = new_end - new_start;
+        writeln!(
+            out,
+            "@@ -{},{} +{},{} @@",
+            if old_count == 0 {
+                old_start
+            } else {
+                old_start + 1
+            },
+            old_count,
+            if new_count == 0 {
+                new_start
+            } else {
+                new_start + 1
+            },
+            new_count
+        )?;
+
+        for edit in &edits[start..end] {
+            match *edit {
+                DiffLine::Equal(old_index, _) => write_line(out, ' ', old_lines[old_index])?,
+                DiffLine::Delete(old_index) => write_line(out, '-', old_lines[old_index])?,
+                DiffLine::Insert(new_index) => write_line(out, '+', new_lines[new_index])?,
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// LFS files are compared by their pointers, the assets themselves may not
+// have been fetched.
+pub fn write_file_diff(
+    path: &Path,
+    old: Option<&TreeEntry>,
+    new: Option<&TreeEntry>,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let old_contents = old.map(read_stored_contents).transpose()?;
+    let new_contents = new.map(read_stored_contents).transpose()?;
+    write_contents_diff(path, old_contents.as_deref(), new_contents.as_deref(), out)
+}
+
+// A missing side is a file that does not exist on it.
+pub fn write_contents_diff(
+    path: &Path,
+    old_contents: Option<&[u8]>,
+    new_contents: Option<&[u8]>,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let old_label = match old_contents {
+        Some(_) => format!("a/{}", path.display()),
+        None => "/dev/null".to_owned(),
+    };
+    let new_label = match new_contents {
+        Some(_) => format!("b/{}", path.display()),
+        None => "/dev/null".to_owned(),
+    };
+    let old_contents = old_contents.unwrap_or_default();
+    let new_contents = new_contents.unwrap_or_default();
+
+    if is_binary(old_contents) || is_binary(new_contents) {
+        return writeln!(out, "Binary files {} and {} differ", old_label, new_label);
+    }
+
+    writeln!(out, "--- {}", old_label)?;
+    writeln!(out, "+++ {}", new_label)?;
+    write_hunks(
+        &String::from_utf8_lossy(old_contents),
+        &String::from_utf8_lossy(new_contents),
+        out,
+    )
+}
+
+pub fn write_tree_diff(old_tree: &Tree, new_tree: &Tree, out: &mut impl Write) -> io::Result<()> {
+    let mut paths: Vec<&Path> = old_tree
+        .keys()
+        .chain(new_tree.keys())
+        .map(|path| path.as_path())
+        .collect();
+    paths.sort();
+    paths.dedup();
+
+    for path in paths {
+        let old = old_tree.get(path);
+        let new = new_tree.get(path);
+        if !entries_equal(old, new)? {
+            write_file_diff(path, old, new, out)?;
+        }
+    }
+
+    Ok(())
+}
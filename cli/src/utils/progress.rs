@@ -0,0 +1,77 @@
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const BAR_WIDTH: u64 = 30;
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+fn format_bytes(bytes: u64) -> String {
+    format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+// Progress of a batch of transfers, shown on stderr. The bar is only redrawn
+// on terminals, other outputs just get the final line.
+pub struct Progress {
+    action: String,
+    total_objects: u64,
+    total_bytes: u64,
+    objects: AtomicU64,
+    bytes: AtomicU64,
+    last_draw: Mutex<Option<Instant>>,
+    is_terminal: bool,
+}
+
+impl Progress {
+    pub fn new(action: &str, total_objects: usize, total_bytes: u64) -> Self {
+        Progress {
+            action: action.to_owned(),
+            total_objects: total_objects as u64,
+            total_bytes,
+            objects: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            last_draw: Mutex::new(None),
+            is_terminal: io::stderr().is_terminal(),
+        }
+    }
+
+    fn line(&self) -> String {
+        let bytes = self.bytes.load(Ordering::Relaxed);
+        let filled = match self.total_bytes {
+            0 => BAR_WIDTH,
+            total_bytes => bytes.min(total_bytes) * BAR_WIDTH / total_bytes,
+        };
+        format!(
+            "{} [{}{}] {}/{} objects, {} / {}",
+            self.action,
+            "#".repeat(filled as usize),
+            " ".repeat((BAR_WIDTH - filled) as usize),
+            self.objects.load(Ordering::Relaxed),
+            self.total_objects,
+            format_bytes(bytes),
+            format_bytes(self.total_bytes)
+        )
+    }
+
+    pub fn advance(&self, bytes: u64) {
+        self.objects.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        if !self.is_terminal {
+            return;
+        }
+
+        let mut last_draw = self.last_draw.lock().unwrap();
+        if last_draw.is_some_and(|last_draw| last_draw.elapsed() < REDRAW_INTERVAL) {
+            return;
+        }
+        *last_draw = Some(Instant::now());
+        eprint!("\r{}", self.line());
+        let _ = io::stderr().flush();
+    }
+
+    pub fn finish(&self) {
+        if self.total_objects > 0 {
+            eprintln!("\r{}", self.line());
+        }
+    }
+}
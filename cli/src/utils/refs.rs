@@ -0,0 +1,296 @@
+use chrono::NaiveDateTime;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+use super::constants::{
+    DEFAULT_BRANCH, HEADS_REFS_PREFIX, HEAD_FILE_PATH, LOGS_DIR, MAIN_COMMITS_METADATA_FILE_PATH,
+    REFS_DIR, REMOTES_REFS_PREFIX, SYMBOLIC_REF_PREFIX, TAGS_REFS_PREFIX, VSM_DIR,
+};
+use super::dates::{get_current_formatted_date, parse_formatted_date};
+use super::types::ReflogEntry;
+use super::{list_commits, write_commits};
+
+fn ref_file_path(ref_name: &str) -> String {
+    VSM_DIR.to_owned() + "/" + ref_name
+}
+
+fn reflog_file_path(ref_name: &str) -> String {
+    LOGS_DIR.to_owned() + "/" + ref_name
+}
+
+pub fn write_atomically(path: &str, contents: &[u8]) -> io::Result<()> {
+    fs::create_dir_all(Path::new(path).parent().unwrap())?;
+    let temp_path = path.to_owned() + ".lock";
+    File::create(&temp_path)?.write_all(contents)?;
+    fs::rename(temp_path, path)
+}
+
+pub fn branch_ref_name(branch: &str) -> String {
+    HEADS_REFS_PREFIX.to_owned() + branch
+}
+
+pub fn remote_ref_name(remote: &str, branch: &str) -> String {
+    REMOTES_REFS_PREFIX.to_owned() + remote + "/" + branch
+}
+
+// Moves the remote-tracking refs of a remote and their reflogs to another
+// remote name, or deletes them when no new name is given.
+pub fn rename_remote_refs(remote: &str, new_remote: Option<&str>) -> io::Result<()> {
+    let old_prefix = REMOTES_REFS_PREFIX.to_owned() + remote;
+    for path_of in [ref_file_path, reflog_file_path] {
+        let old_path = path_of(&old_prefix);
+        if !Path::new(&old_path).exists() {
+            continue;
+        }
+        match new_remote {
+            Some(new_remote) => {
+                let new_path = path_of(&(REMOTES_REFS_PREFIX.to_owned() + new_remote));
+                fs::create_dir_all(Path::new(&new_path).parent().unwrap())?;
+                fs::rename(old_path, new_path)?;
+            }
+            None => fs::remove_dir_all(old_path)?,
+        }
+    }
+
+    Ok(())
+}
+
+pub fn read_ref(ref_name: &str) -> io::Result<Option<String>> {
+    match fs::read_to_string(ref_file_path(ref_name)) {
+        Ok(commit_id) => Ok(Some(commit_id.trim().to_owned())),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+pub fn write_ref(ref_name: &str, commit_id: &str) -> io::Result<()> {
+    write_atomically(&ref_file_path(ref_name), commit_id.as_bytes())
+}
+
+// HEAD either points to a branch ("ref: refs/heads/main") or directly to a
+// commit id when a commit is checked out outside of any branch.
+pub fn current_branch() -> io::Result<Option<String>> {
+    let head = fs::read_to_string(HEAD_FILE_PATH)?;
+    Ok(head
+        .trim()
+        .strip_prefix(SYMBOLIC_REF_PREFIX)
+        .and_then(|ref_name| ref_name.strip_prefix(HEADS_REFS_PREFIX))
+        .map(|branch| branch.to_owned()))
+}
+
+pub fn head_commit_id() -> io::Result<Option<String>> {
+    let head = fs::read_to_string(HEAD_FILE_PATH)?;
+    match head.trim().strip_prefix(SYMBOLIC_REF_PREFIX) {
+        Some(ref_name) => read_ref(ref_name),
+        None => Ok(Some(head.trim().to_owned())),
+    }
+}
+
+pub fn set_head_to_branch(branch: &str) -> io::Result<()> {
+    let head = SYMBOLIC_REF_PREFIX.to_owned() + &branch_ref_name(branch);
+    write_atomically(HEAD_FILE_PATH, head.as_bytes())
+}
+
+pub fn detach_head(commit_id: &str) -> io::Result<()> {
+    write_atomically(HEAD_FILE_PATH, commit_id.as_bytes())
+}
+
+fn collect_ref_names(dir: &Path, prefix: &str, ref_names: &mut Vec<String>) -> io::Result<()> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.ends_with(".lock") {
+            continue;
+        }
+        if entry.path().is_dir() {
+            collect_ref_names(&entry.path(), &(prefix.to_owned() + &name + "/"), ref_names)?;
+        } else {
+            ref_names.push(prefix.to_owned() + &name);
+        }
+    }
+
+    Ok(())
+}
+
+// Names of the refs under the given prefix, without it. Branch names may
+// contain "/" (e.g. release/1.0), so the directory is walked recursively.
+pub fn list_ref_names(prefix: &str) -> io::Result<Vec<String>> {
+    let mut ref_names = Vec::new();
+    collect_ref_names(&Path::new(VSM_DIR).join(prefix), "", &mut ref_names)?;
+    ref_names.sort();
+
+    Ok(ref_names)
+}
+
+pub fn list_branches() -> io::Result<Vec<String>> {
+    list_ref_names(HEADS_REFS_PREFIX)
+}
+
+// Moves the current branch, or HEAD itself when detached, and records the
+// move in the reflogs.
+pub fn update_head(commit_id: &str, reason: &str) -> io::Result<()> {
+    let old_commit_id = head_commit_id()?;
+    match current_branch()? {
+        Some(branch) => write_ref(&branch_ref_name(&branch), commit_id)?,
+        None => detach_head(commit_id)?,
+    }
+
+    append_head_reflog(old_commit_id.as_deref(), commit_id, reason)
+}
+
+pub fn update_ref(ref_name: &str, commit_id: &str, reason: &str) -> io::Result<()> {
+    let old_commit_id = read_ref(ref_name)?;
+    write_ref(ref_name, commit_id)?;
+    if old_commit_id.as_deref() == Some(commit_id) {
+        return Ok(());
+    }
+
+    append_reflog(ref_name, old_commit_id.as_deref(), commit_id, reason)
+}
+
+// Expands a short ref name, e.g. "main" to "refs/heads/main" or "origin/main"
+// to "refs/remotes/origin/main".
+pub fn full_ref_name(name: &str) -> String {
+    if name == "HEAD" || name.starts_with("refs/") {
+        return name.to_owned();
+    }
+
+    let branch_ref = branch_ref_name(name);
+    if Path::new(&ref_file_path(&branch_ref)).exists()
+        || Path::new(&reflog_file_path(&branch_ref)).exists()
+    {
+        return branch_ref;
+    }
+    for prefix in [TAGS_REFS_PREFIX, REMOTES_REFS_PREFIX] {
+        let ref_name = prefix.to_owned() + name;
+        if Path::new(&ref_file_path(&ref_name)).exists() {
+            return ref_name;
+        }
+    }
+
+    branch_ref
+}
+
+pub fn tag_ref_name(tag: &str) -> String {
+    TAGS_REFS_PREFIX.to_owned() + tag
+}
+
+// "ref@{date}" is where the ref was at that date, i.e. the last move made
+// before it.
+pub fn resolve_reflog_date(ref_name: &str, date: &NaiveDateTime) -> io::Result<String> {
+    read_reflog(ref_name)?
+        .iter()
+        .rev()
+        .find(|entry| {
+            parse_formatted_date(&entry.date).is_some_and(|entry_date| entry_date <= *date)
+        })
+        .map(|entry| entry.new_commit_id.clone())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "The reflog of {} does not go back to {}",
+                    ref_name,
+                    date.format("%Y-%m-%d %H:%M:%S")
+                ),
+            )
+        })
+}
+
+// "ref@{n}" is where the ref was n moves ago, "ref@{0}" being where it is now.
+pub fn resolve_reflog_entry(ref_name: &str, index: usize) -> io::Result<String> {
+    let reflog = read_reflog(ref_name)?;
+    if index >= reflog.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} only has {} reflog entries", ref_name, reflog.len()),
+        ));
+    }
+
+    Ok(reflog[reflog.len() - 1 - index].new_commit_id.clone())
+}
+
+pub fn read_reflog(ref_name: &str) -> io::Result<Vec<ReflogEntry>> {
+    let reflog_string = match fs::read_to_string(reflog_file_path(ref_name)) {
+        Ok(reflog_string) => reflog_string,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    serde_json::from_str(&reflog_string).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Failed to parse reflog: {}", e),
+        )
+    })
+}
+
+pub fn append_reflog(
+    ref_name: &str,
+    old_commit_id: Option<&str>,
+    new_commit_id: &str,
+    reason: &str,
+) -> io::Result<()> {
+    let mut reflog = read_reflog(ref_name)?;
+    reflog.push(ReflogEntry {
+        old_commit_id: old_commit_id.map(|commit_id| commit_id.to_owned()),
+        new_commit_id: new_commit_id.to_owned(),
+        date: get_current_formatted_date(),
+        reason: reason.to_owned(),
+    });
+
+    let reflog_string = serde_json::to_string(&reflog)?;
+    write_atomically(&reflog_file_path(ref_name), reflog_string.as_bytes())
+}
+
+pub fn append_head_reflog(
+    old_commit_id: Option<&str>,
+    new_commit_id: &str,
+    reason: &str,
+) -> io::Result<()> {
+    append_reflog("HEAD", old_commit_id, new_commit_id, reason)?;
+    if let Some(branch) = current_branch()? {
+        append_reflog(
+            &branch_ref_name(&branch),
+            old_commit_id,
+            new_commit_id,
+            reason,
+        )?;
+    }
+
+    Ok(())
+}
+
+pub fn init_refs() -> io::Result<()> {
+    fs::create_dir_all(REFS_DIR.to_owned() + "/heads")?;
+    set_head_to_branch(DEFAULT_BRANCH)
+}
+
+// Repositories created before refs existed kept a single linear history in
+// commits.json, with the last commit being the current one.
+pub fn migrate_refs() -> io::Result<()> {
+    if Path::new(HEAD_FILE_PATH).exists() {
+        return Ok(());
+    }
+
+    let mut commits = list_commits(MAIN_COMMITS_METADATA_FILE_PATH)?;
+    for index in 1..commits.len() {
+        if commits[index].parents.is_empty() {
+            let parent = commits[index - 1].commit_id.clone();
+            commits[index].parents.push(parent);
+        }
+    }
+    write_commits(&commits)?;
+
+    init_refs()?;
+    if let Some(last_commit) = commits.last() {
+        write_ref(&branch_ref_name(DEFAULT_BRANCH), &last_commit.commit_id)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,475 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use futures_util::{stream, StreamExt, TryStreamExt};
+use md5::{Digest, Md5};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::future::Future;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::constants::{
+    BISECT_STATE_FILE_PATH, CLONE_STATE_FILE_PATH, COMMIT_EDITMSG_FILE_PATH, DATA_RELATIVE_PATH,
+    FETCH_DIR, FILES_HISTORY_DIR, HEAD_FILE_PATH, LFS_DIR, LFS_REMOTE_PREFIX, LOGS_DIR,
+    MERGE_STATE_FILE_PATH, MULTIPART_PART_SIZE, MULTIPART_THRESHOLD, OBJECTS_DIR,
+    PUSH_LOCK_EXPIRY_SECS, PUSH_LOCK_KEY, REBASE_TODO_FILE_PATH, REFS_DIR,
+    REMOTES_CONFIG_FILE_PATH, REMOTES_REFS_PREFIX, REMOTE_REPOSITORY_REFERENCE_FILE_PATH,
+    REVERT_STATE_FILE_PATH, SEQUENCER_STATE_FILE_PATH, SHALLOW_FILE_PATH, TRANSFER_CONCURRENCY,
+    TRANSFER_MAX_ATTEMPTS, TRANSFER_RETRY_DELAY_MS, VSM_DIR,
+};
+
+// Suffix of files being downloaded, renamed once complete.
+const PARTIAL_DOWNLOAD_SUFFIX: &str = ".part";
+use super::dates::{get_current_formatted_date, parse_formatted_date};
+use super::encryption::{check_not_encrypted, EncryptedBackend};
+use super::fs_remote::DirectoryBackend;
+use super::get_file_paths_recursively;
+use super::progress::Progress;
+use super::s3_provider::S3Backend;
+use super::types::RemoteConfig;
+
+pub struct RemoteObject {
+    pub key: String,
+    pub size: u64,
+    // Checksum of the contents in the format of file_checksum when the backend
+    // keeps one, as S3 does in ETags
+    pub checksum: Option<String>,
+}
+
+// Storage of a remote repository, addressed by keys laid out like the local
+// ".history" directory with LFS blobs under "lfs/". Missing keys are reported
+// with io::ErrorKind::NotFound.
+#[async_trait]
+pub trait RemoteBackend: Send + Sync {
+    async fn list(&self, prefix: &str) -> io::Result<Vec<RemoteObject>>;
+    async fn get(&self, key: &str) -> io::Result<Vec<u8>>;
+    // Reads length bytes of an object from start, fewer when it ends before.
+    async fn get_range(&self, key: &str, start: u64, length: u64) -> io::Result<Vec<u8>>;
+    async fn download(&self, key: &str, destination: &Path) -> io::Result<()>;
+    async fn put(&self, key: &str, source: &Path) -> io::Result<()>;
+    async fn delete(&self, key: &str) -> io::Result<()>;
+    // Replaces the contents of a ref only if they still are the expected ones,
+    // None meaning that the ref must not exist yet.
+    async fn update_ref(
+        &self,
+        key: &str,
+        expected_contents: Option<&[u8]>,
+        contents: &[u8],
+    ) -> io::Result<()>;
+
+    // Checksum the object would have once the file is uploaded under the key.
+    fn local_checksum(&self, _key: &str, file: &Path) -> io::Result<String> {
+        file_checksum(file)
+    }
+}
+
+// "file://" URLs and paths name a directory, anything else an S3 bucket.
+// Encrypted remotes wrap the storage so that it only ever sees ciphertext.
+pub async fn open_remote(remote_config: &RemoteConfig) -> io::Result<Box<dyn RemoteBackend>> {
+    let url = remote_config.url.as_str();
+    let storage: Box<dyn RemoteBackend> = if let Some(path) = url.strip_prefix("file://") {
+        Box::new(DirectoryBackend::new(Path::new(path)))
+    } else if url.starts_with('/') || url.starts_with("./") || url.starts_with("../") {
+        Box::new(DirectoryBackend::new(Path::new(url)))
+    } else {
+        Box::new(S3Backend::new(remote_config).await)
+    };
+
+    if remote_config.encrypted {
+        return Ok(Box::new(
+            EncryptedBackend::open(storage, remote_config).await?,
+        ));
+    }
+    check_not_encrypted(&*storage).await?;
+    Ok(storage)
+}
+
+pub fn ref_mismatch(key: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        format!(
+            "{} was updated by someone else, pull the changes first",
+            key
+        ),
+    )
+}
+
+fn lock_in_progress(holder: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::WouldBlock,
+        format!(
+            "Another push is in progress ({}), try again later",
+            holder.trim().replace('\n', " by ")
+        ),
+    )
+}
+
+// A lock records when it was taken, on its first line.
+fn is_lock_expired(holder: &str) -> bool {
+    holder
+        .lines()
+        .next()
+        .and_then(parse_formatted_date)
+        .is_some_and(|date| {
+            Utc::now().naive_utc() - date > chrono::Duration::seconds(PUSH_LOCK_EXPIRY_SECS)
+        })
+}
+
+// Pushes rewrite shared files such as commits.json, so a push holds a lock
+// object on the remote until its ref is updated. The lock is taken with the
+// same conditional write as refs, which only succeeds if it does not exist.
+// A lock older than PUSH_LOCK_EXPIRY_SECS was left by an interrupted push and
+// is taken over, with a conditional write too so that only one push does.
+// Returns the contents of the lock, which unlock_remote expects.
+pub async fn lock_remote(remote: &dyn RemoteBackend, owner: &str) -> io::Result<String> {
+    let lock = format!("{}\n{}", get_current_formatted_date(), owner);
+    match remote
+        .update_ref(PUSH_LOCK_KEY, None, lock.as_bytes())
+        .await
+    {
+        Err(e)
+            if e.kind() == io::ErrorKind::PermissionDenied
+                || e.kind() == io::ErrorKind::AlreadyExists =>
+        {
+            let holder = read_remote_string(remote, PUSH_LOCK_KEY)
+                .await
+                .unwrap_or_default();
+            if !is_lock_expired(&holder) {
+                return Err(lock_in_progress(&holder));
+            }
+            remote
+                .update_ref(PUSH_LOCK_KEY, Some(holder.as_bytes()), lock.as_bytes())
+                .await
+                .map_err(|_| lock_in_progress(&holder))?;
+            Ok(lock)
+        }
+        Err(e) => Err(e),
+        Ok(()) => Ok(lock),
+    }
+}
+
+// The lock is only deleted while it is still the one taken, as it may have
+// expired and been taken over meanwhile.
+pub async fn unlock_remote(remote: &dyn RemoteBackend, lock: &str) -> io::Result<()> {
+    match read_remote_string(remote, PUSH_LOCK_KEY).await {
+        Ok(holder) if holder == lock => remote.delete(PUSH_LOCK_KEY).await,
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+// Reflogs, remote-tracking refs, remote settings and the state of operations in
+// progress only make sense in the clone they were written in, so they are
+// never synced.
+pub fn is_local_only_path(path: &Path) -> bool {
+    let remotes_refs_dir = VSM_DIR.to_owned() + "/" + REMOTES_REFS_PREFIX;
+    [
+        LOGS_DIR,
+        &remotes_refs_dir,
+        FETCH_DIR,
+        REMOTE_REPOSITORY_REFERENCE_FILE_PATH,
+        REMOTES_CONFIG_FILE_PATH,
+        SEQUENCER_STATE_FILE_PATH,
+        REVERT_STATE_FILE_PATH,
+        MERGE_STATE_FILE_PATH,
+        BISECT_STATE_FILE_PATH,
+        CLONE_STATE_FILE_PATH,
+        SHALLOW_FILE_PATH,
+        REBASE_TODO_FILE_PATH,
+        COMMIT_EDITMSG_FILE_PATH,
+    ]
+    .iter()
+    .any(|local_only_path| path.starts_with(local_only_path))
+}
+
+fn remote_key(file: &Path) -> String {
+    match file.strip_prefix(LFS_DIR) {
+        Ok(blob_path) => LFS_REMOTE_PREFIX.to_owned() + blob_path.to_str().unwrap(),
+        Err(_) => file.to_str().unwrap().to_owned(),
+    }
+}
+
+// Uploads are ordered so that the remote never references what it does not
+// have yet: content addressed objects first, then file data before the
+// metadata pointing into it, then the commits and the refs last.
+fn upload_rank(file: &Path) -> u8 {
+    if file.starts_with(OBJECTS_DIR) || file.starts_with(LFS_DIR) {
+        0
+    } else if file.starts_with(FILES_HISTORY_DIR) {
+        if file.ends_with(DATA_RELATIVE_PATH.trim_start_matches('/')) {
+            1
+        } else {
+            2
+        }
+    } else if file.starts_with(REFS_DIR) || file == Path::new(HEAD_FILE_PATH) {
+        4
+    } else {
+        3
+    }
+}
+
+fn key_rank(key: &str) -> u8 {
+    if key.starts_with(LFS_REMOTE_PREFIX) {
+        0
+    } else {
+        upload_rank(Path::new(key))
+    }
+}
+
+fn is_ref_key(key: &str) -> bool {
+    key_rank(key) == 4
+}
+
+fn is_content_addressed(file: &Path) -> bool {
+    file.starts_with(OBJECTS_DIR) || file.starts_with(LFS_DIR)
+}
+
+// MD5 of the contents, or for contents big enough to be uploaded in parts,
+// MD5 of the MD5s of the parts followed by their number, as S3 computes ETags.
+pub fn checksum(mut reader: impl Read, size: u64) -> io::Result<String> {
+    if size <= MULTIPART_THRESHOLD {
+        let mut hasher = Md5::new();
+        io::copy(&mut reader, &mut hasher)?;
+        return Ok(hex::encode(hasher.finalize()));
+    }
+
+    let mut parts_hasher = Md5::new();
+    let parts_count = size.div_ceil(MULTIPART_PART_SIZE);
+    for _ in 0..parts_count {
+        let mut part_hasher = Md5::new();
+        io::copy(
+            &mut (&mut reader).take(MULTIPART_PART_SIZE),
+            &mut part_hasher,
+        )?;
+        parts_hasher.update(part_hasher.finalize());
+    }
+    Ok(format!(
+        "{}-{}",
+        hex::encode(parts_hasher.finalize()),
+        parts_count
+    ))
+}
+
+fn file_checksum(file: &Path) -> io::Result<String> {
+    checksum(File::open(file)?, fs::metadata(file)?.len())
+}
+
+pub fn contents_checksum(contents: &[u8]) -> String {
+    checksum(contents, contents.len() as u64).unwrap()
+}
+
+fn is_transient(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::TimedOut
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::UnexpectedEof
+    )
+}
+
+// Retries an operation failing with a transient error, doubling the delay
+// between attempts.
+pub async fn with_retries<T, F, Fut>(mut operation: F) -> io::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = io::Result<T>>,
+{
+    let mut delay = Duration::from_millis(TRANSFER_RETRY_DELAY_MS);
+    let mut attempt = 1;
+    loop {
+        match operation().await {
+            Err(e) if attempt < TRANSFER_MAX_ATTEMPTS && is_transient(&e) => {
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+// Uploads only the files the remote does not have or has different contents
+// for, except the given keys. Nothing is ever deleted, so a push failing midway
+// leaves the remote with its previous refs and every object they reference.
+// Files of the same rank are uploaded in parallel.
+pub async fn upload_history(
+    remote: &dyn RemoteBackend,
+    local_path: &str,
+    skipped_keys: &[String],
+) -> io::Result<()> {
+    let remote_checksums: HashMap<String, Option<String>> = with_retries(|| remote.list(""))
+        .await?
+        .into_iter()
+        .map(|object| (object.key, object.checksum))
+        .collect();
+
+    let mut files: Vec<_> = get_file_paths_recursively(Some(Path::new(local_path)), None)
+        .into_iter()
+        .filter(|file| !is_local_only_path(file))
+        .collect();
+    files.sort_by_key(|file| (upload_rank(file), file.clone()));
+
+    let mut uploads = Vec::new();
+    for file in files {
+        let key = remote_key(&file);
+        if skipped_keys.contains(&key) {
+            continue;
+        }
+        let is_uploaded = match remote_checksums.get(&key) {
+            Some(_) if is_content_addressed(&file) => true,
+            Some(Some(checksum)) => *checksum == remote.local_checksum(&key, &file)?,
+            Some(None) => with_retries(|| remote.get(&key)).await? == fs::read(&file)?,
+            None => false,
+        };
+        if !is_uploaded {
+            let size = fs::metadata(&file)?.len();
+            uploads.push((file, key, size));
+        }
+    }
+
+    let progress = Progress::new(
+        "Uploading",
+        uploads.len(),
+        uploads.iter().map(|(_, _, size)| size).sum(),
+    );
+    for rank_uploads in uploads.chunk_by(|a, b| upload_rank(&a.0) == upload_rank(&b.0)) {
+        stream::iter(rank_uploads)
+            .map(|(file, key, size)| {
+                let progress = &progress;
+                async move {
+                    with_retries(|| remote.put(key, file)).await?;
+                    progress.advance(*size);
+                    Ok::<_, io::Error>(())
+                }
+            })
+            .buffer_unordered(TRANSFER_CONCURRENCY)
+            .try_collect::<Vec<_>>()
+            .await?;
+    }
+    progress.finish();
+
+    Ok(())
+}
+
+// Downloads the remote history into destination_dir, which is laid out like
+// ".history", LFS blobs included unless fetch_lfs is false.
+pub async fn download_history(
+    remote: &dyn RemoteBackend,
+    destination_dir: &str,
+    fetch_lfs: bool,
+) -> io::Result<()> {
+    let objects = download_refs(remote, destination_dir, |_| true).await?;
+    download_matching(remote, destination_dir, &objects, |key| {
+        !is_ref_key(key) && (fetch_lfs || !key.starts_with(LFS_REMOTE_PREFIX))
+    })
+    .await
+}
+
+// Downloads the wanted refs, then lists the remote again. Pushes upload
+// everything a ref references before the ref, so the new listing has all of
+// it even if a push ran meanwhile.
+pub async fn download_refs(
+    remote: &dyn RemoteBackend,
+    destination_dir: &str,
+    wanted: impl Fn(&str) -> bool,
+) -> io::Result<Vec<RemoteObject>> {
+    let objects = with_retries(|| remote.list("")).await?;
+    download_matching(remote, destination_dir, &objects, |key| {
+        is_ref_key(key) && wanted(key)
+    })
+    .await?;
+    with_retries(|| remote.list("")).await
+}
+
+// Downloads the listed objects whose key is wanted into destination_dir, in
+// the reverse order of uploads: a push running meanwhile only appends to file
+// data and never changes what the metadata downloaded before points at.
+pub async fn download_matching(
+    remote: &dyn RemoteBackend,
+    destination_dir: &str,
+    objects: &[RemoteObject],
+    wanted: impl Fn(&str) -> bool,
+) -> io::Result<()> {
+    let mut downloads = Vec::new();
+    for object in objects {
+        let key = &object.key;
+        if !wanted(key) {
+            continue;
+        }
+        let destination = if let Some(blob_path) = key.strip_prefix(LFS_REMOTE_PREFIX) {
+            destination_dir.to_owned() + LFS_DIR.strip_prefix(VSM_DIR).unwrap() + "/" + blob_path
+        } else if let Some(path) = key
+            .strip_prefix(VSM_DIR)
+            .filter(|path| path.starts_with('/'))
+        {
+            if is_local_only_path(Path::new(key)) {
+                continue;
+            }
+            destination_dir.to_owned() + path
+        } else {
+            continue;
+        };
+
+        // Files left by an interrupted download are complete, as they are only
+        // renamed into place once fully written, so they are kept if unchanged
+        let destination = PathBuf::from(destination);
+        if destination.exists() {
+            let is_content_addressed =
+                key.starts_with(LFS_REMOTE_PREFIX) || Path::new(key).starts_with(OBJECTS_DIR);
+            let is_downloaded = match &object.checksum {
+                _ if is_content_addressed => true,
+                Some(checksum) => *checksum == remote.local_checksum(key, &destination)?,
+                None => false,
+            };
+            if is_downloaded {
+                continue;
+            }
+        }
+        downloads.push((object, destination));
+    }
+
+    downloads.sort_by_key(|(object, _)| std::cmp::Reverse(key_rank(&object.key)));
+
+    let progress = Progress::new(
+        "Downloading",
+        downloads.len(),
+        downloads.iter().map(|(object, _)| object.size).sum(),
+    );
+    for rank_downloads in downloads.chunk_by(|a, b| key_rank(&a.0.key) == key_rank(&b.0.key)) {
+        stream::iter(rank_downloads)
+            .map(|(object, destination)| {
+                let progress = &progress;
+                async move {
+                    let mut partial_path = destination.clone().into_os_string();
+                    partial_path.push(PARTIAL_DOWNLOAD_SUFFIX);
+                    with_retries(|| remote.download(&object.key, Path::new(&partial_path))).await?;
+                    fs::rename(&partial_path, destination)?;
+                    progress.advance(object.size);
+                    Ok::<_, io::Error>(())
+                }
+            })
+            .buffer_unordered(TRANSFER_CONCURRENCY)
+            .try_collect::<Vec<_>>()
+            .await?;
+    }
+    progress.finish();
+
+    Ok(())
+}
+
+pub async fn read_remote_string(remote: &dyn RemoteBackend, key: &str) -> io::Result<String> {
+    String::from_utf8(remote.get(key).await?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+// Reads the commit a remote ref points at, None when the ref does not exist.
+pub async fn read_remote_ref(remote: &dyn RemoteBackend, key: &str) -> io::Result<Option<String>> {
+    match read_remote_string(remote, key).await {
+        Ok(commit_id) => Ok(Some(commit_id.trim().to_owned())),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
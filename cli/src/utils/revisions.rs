@@ -0,0 +1,424 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::dates::parse_approximate_date;
+use super::history_paths::normalize_path;
+use super::refs::{
+    branch_ref_name, full_ref_name, head_commit_id, list_branches, read_ref, resolve_reflog_date,
+    resolve_reflog_entry,
+};
+use super::types::Commit;
+
+fn invalid_revision(revision: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("Invalid revision {}", revision),
+    )
+}
+
+fn index_commits(commits: &[Commit]) -> HashMap<&str, &Commit> {
+    commits
+        .iter()
+        .map(|commit| (commit.commit_id.as_str(), commit))
+        .collect()
+}
+
+// Every commit reachable from the given ones through any parent.
+pub fn reachable_commits(commits: &[Commit], commit_ids: &[String]) -> HashSet<String> {
+    let commits_by_id = index_commits(commits);
+    let mut reachable = HashSet::new();
+    let mut pending = commit_ids.to_vec();
+
+    while let Some(commit_id) = pending.pop() {
+        if !reachable.insert(commit_id.clone()) {
+            continue;
+        }
+        if let Some(commit) = commits_by_id.get(commit_id.as_str()) {
+            pending.extend(commit.parents.iter().cloned());
+        }
+    }
+
+    reachable
+}
+
+// The common ancestor of two commits that no other common ancestor descends
+// from, the oldest one when criss-cross merges leave several.
+pub fn merge_base(commits: &[Commit], commit_id: &str, other_commit_id: &str) -> Option<String> {
+    let reachable = reachable_commits(commits, &[commit_id.to_owned()]);
+    let other_reachable = reachable_commits(commits, &[other_commit_id.to_owned()]);
+    let common: Vec<&Commit> = commits
+        .iter()
+        .filter(|commit| {
+            reachable.contains(&commit.commit_id) && other_reachable.contains(&commit.commit_id)
+        })
+        .collect();
+    let common_parents: Vec<String> = common
+        .iter()
+        .flat_map(|commit| commit.parents.iter().cloned())
+        .collect();
+    let older_ancestors = reachable_commits(commits, &common_parents);
+
+    common
+        .into_iter()
+        .find(|commit| !older_ancestors.contains(&commit.commit_id))
+        .map(|commit| commit.commit_id.clone())
+}
+
+// Commit ids may be abbreviated as long as the prefix is unambiguous.
+fn resolve_commit_id_prefix(commits: &[Commit], prefix: &str) -> io::Result<String> {
+    if commits.iter().any(|commit| commit.commit_id == prefix) {
+        return Ok(prefix.to_owned());
+    }
+
+    let matches: Vec<&Commit> = commits
+        .iter()
+        .filter(|commit| prefix.len() >= 4 && commit.commit_id.starts_with(prefix))
+        .collect();
+    match matches.as_slice() {
+        [commit] => Ok(commit.commit_id.clone()),
+        [] => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Revision {} not found", prefix),
+        )),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Revision {} is ambiguous", prefix),
+        )),
+    }
+}
+
+fn resolve_name(commits: &[Commit], name: &str) -> io::Result<String> {
+    if name == "HEAD" || name == "@" {
+        return head_commit_id()?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Nothing committed yet"));
+    }
+    if let Some(commit_id) = read_ref(&full_ref_name(name))? {
+        return Ok(commit_id);
+    }
+
+    resolve_commit_id_prefix(commits, name)
+}
+
+// ":/text" is the youngest commit reachable from HEAD or a branch whose message
+// contains the text.
+fn search_commit_message(commits: &[Commit], text: &str) -> io::Result<String> {
+    let mut tips: Vec<String> = head_commit_id()?.into_iter().collect();
+    for branch in list_branches()? {
+        tips.extend(read_ref(&branch_ref_name(&branch))?);
+    }
+    let reachable = reachable_commits(commits, &tips);
+
+    commits
+        .iter()
+        .rev()
+        .find(|commit| reachable.contains(&commit.commit_id) && commit.description.contains(text))
+        .map(|commit| commit.commit_id.clone())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No commit message contains {}", text),
+            )
+        })
+}
+
+// "name@{n}" and "name@{date}" look the position of the ref up in its reflog,
+// a bare "@{...}" meaning HEAD.
+fn resolve_reflog_selector(revision: &str, name: &str, selector: &str) -> io::Result<String> {
+    let ref_name = match name {
+        "" | "@" => "HEAD".to_owned(),
+        name => full_ref_name(name),
+    };
+
+    match selector.parse::<usize>() {
+        Ok(index) => resolve_reflog_entry(&ref_name, index),
+        Err(_) => {
+            let date =
+                parse_approximate_date(selector).ok_or_else(|| invalid_revision(revision))?;
+            resolve_reflog_date(&ref_name, &date)
+        }
+    }
+}
+
+// Resolves a revision to a commit id. A revision is a name (HEAD, a branch, a
+// tag, a remote branch or a possibly abbreviated commit id), optionally with a
+// reflog selector ("@{2}", "@{yesterday}"), followed by any number of "~n"
+// (n-th first-parent ancestor), "^n" (n-th parent) and "^{commit}" suffixes.
+// ":/text" searches the commit messages instead.
+pub fn resolve_revision(commits: &[Commit], revision: &str) -> io::Result<String> {
+    if let Some(text) = revision.strip_prefix(":/") {
+        return search_commit_message(commits, text);
+    }
+
+    let suffixes_start = revision.find(['~', '^']).unwrap_or(revision.len());
+    let (name, mut suffixes) = revision.split_at(suffixes_start);
+    let mut commit_id = match name.find("@{") {
+        Some(index) if name.ends_with('}') => {
+            resolve_reflog_selector(revision, &name[..index], &name[index + 2..name.len() - 1])?
+        }
+        _ => resolve_name(commits, name)?,
+    };
+
+    let commits_by_id = index_commits(commits);
+    while !suffixes.is_empty() {
+        let mut chars = suffixes.chars();
+        let operator = chars.next().ok_or_else(|| invalid_revision(revision))?;
+        let rest = chars.as_str();
+        if operator != '~' && operator != '^' {
+            return Err(invalid_revision(revision));
+        }
+
+        // Only commits can be named, so peeling them is a no-op
+        if operator == '^' && rest.starts_with('{') {
+            let end = rest.find('}').ok_or_else(|| invalid_revision(revision))?;
+            if !matches!(&rest[1..end], "" | "commit") {
+                return Err(invalid_revision(revision));
+            }
+            suffixes = &rest[end + 1..];
+            continue;
+        }
+
+        let digits_end = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let count: usize = match &rest[..digits_end] {
+            "" => 1,
+            digits => digits.parse().map_err(|_| invalid_revision(revision))?,
+        };
+        suffixes = &rest[digits_end..];
+
+        let parent_index = if operator == '~' { 1 } else { count };
+        let steps = if operator == '~' { count } else { 1 };
+        if parent_index == 0 {
+            continue;
+        }
+        for _ in 0..steps {
+            let commit = commits_by_id
+                .get(commit_id.as_str())
+                .ok_or_else(|| invalid_revision(revision))?;
+            commit_id = commit
+                .parents
+                .get(parent_index - 1)
+                .cloned()
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("Commit {} has no parent {}", commit_id, parent_index),
+                    )
+                })?;
+        }
+    }
+
+    Ok(commit_id)
+}
+
+// An empty side of a range stands for HEAD.
+pub fn resolve_range_side(commits: &[Commit], revision: &str) -> io::Result<String> {
+    if revision.is_empty() {
+        resolve_revision(commits, "HEAD")
+    } else {
+        resolve_revision(commits, revision)
+    }
+}
+
+// "A..B" lists the commits reachable from B but not from A, "A...B" the ones
+// reachable from either but not both and a single revision its whole history.
+// Commits are returned oldest first.
+pub fn resolve_range(commits: &[Commit], range: &str) -> io::Result<Vec<Commit>> {
+    let (included, excluded) = if let Some((a, b)) = range.split_once("...") {
+        let from_a = reachable_commits(commits, &[resolve_range_side(commits, a)?]);
+        let from_b = reachable_commits(commits, &[resolve_range_side(commits, b)?]);
+        let included = from_a.symmetric_difference(&from_b).cloned().collect();
+        (included, HashSet::new())
+    } else if let Some((a, b)) = range.split_once("..") {
+        (
+            reachable_commits(commits, &[resolve_range_side(commits, b)?]),
+            reachable_commits(commits, &[resolve_range_side(commits, a)?]),
+        )
+    } else {
+        (
+            reachable_commits(commits, &[resolve_revision(commits, range)?]),
+            HashSet::new(),
+        )
+    };
+
+    Ok(commits
+        .iter()
+        .filter(|commit| {
+            included.contains(&commit.commit_id) && !excluded.contains(&commit.commit_id)
+        })
+        .cloned()
+        .collect())
+}
+
+// Index of the ":" separating a revision from a path, skipping the ones inside
+// reflog selectors such as "@{2024-01-31 12:00:00}".
+pub fn path_separator_index(revision: &str) -> Option<usize> {
+    if revision.starts_with(":/") {
+        return None;
+    }
+
+    let mut depth = 0;
+    for (index, c) in revision.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ':' if depth == 0 => return Some(index),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+// "rev:path" names a file as it was in a commit, ":path" meaning HEAD.
+pub fn resolve_file_revision(commits: &[Commit], revision: &str) -> io::Result<(String, PathBuf)> {
+    let separator = path_separator_index(revision).ok_or_else(|| invalid_revision(revision))?;
+    let commit_id = resolve_range_side(commits, &revision[..separator])?;
+    let path = normalize_path(Path::new(&revision[separator + 1..]));
+
+    Ok((commit_id, path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 1aaaaaa <- 2bbbbbb <- 3cccccc <- 5eeeeee, the merge of 4dddddd, itself
+    // a child of 2bbbbbb, and 5eeefff, a child of 3cccccc.
+    fn commits() -> Vec<Commit> {
+        [
+            ("1aaaaaa", vec![]),
+            ("2bbbbbb", vec!["1aaaaaa"]),
+            ("3cccccc", vec!["2bbbbbb"]),
+            ("4dddddd", vec!["2bbbbbb"]),
+            ("5eeeeee", vec!["3cccccc", "4dddddd"]),
+            ("5eeefff", vec!["3cccccc"]),
+        ]
+        .into_iter()
+        .map(|(commit_id, parents)| Commit {
+            date: String::new(),
+            description: format!("commit {}", commit_id),
+            commit_id: commit_id.to_owned(),
+            parents: parents
+                .into_iter()
+                .map(|parent| parent.to_owned())
+                .collect(),
+            author: String::new(),
+        })
+        .collect()
+    }
+
+    fn resolve(revision: &str) -> io::Result<String> {
+        resolve_revision(&commits(), revision)
+    }
+
+    fn commit_ids(commits: Vec<Commit>) -> Vec<String> {
+        commits.into_iter().map(|commit| commit.commit_id).collect()
+    }
+
+    #[test]
+    fn commit_ids_may_be_abbreviated() {
+        assert_eq!(resolve("3cccccc").unwrap(), "3cccccc");
+        assert_eq!(resolve("3ccc").unwrap(), "3cccccc");
+        assert_eq!(
+            resolve("5eee").unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        assert_eq!(resolve("3cc").unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(resolve("9999").unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn tilde_follows_first_parents() {
+        assert_eq!(resolve("5eeeeee~").unwrap(), "3cccccc");
+        assert_eq!(resolve("5eeeeee~2").unwrap(), "2bbbbbb");
+        assert_eq!(resolve("5eeeeee~1~1").unwrap(), "2bbbbbb");
+        assert_eq!(resolve("5eeeeee~0").unwrap(), "5eeeeee");
+    }
+
+    #[test]
+    fn caret_selects_a_parent() {
+        assert_eq!(resolve("5eeeeee^").unwrap(), "3cccccc");
+        assert_eq!(resolve("5eeeeee^2").unwrap(), "4dddddd");
+        assert_eq!(resolve("5eeeeee^2~1").unwrap(), "2bbbbbb");
+        assert_eq!(resolve("5eeeeee^0").unwrap(), "5eeeeee");
+        assert_eq!(resolve("5eeeeee^^").unwrap(), "2bbbbbb");
+        assert_eq!(
+            resolve("5eeeeee^3").unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+        assert_eq!(
+            resolve("1aaaaaa~").unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn commits_peel_to_themselves() {
+        assert_eq!(resolve("5eeeeee^{commit}").unwrap(), "5eeeeee");
+        assert_eq!(resolve("5eeeeee^{}~1").unwrap(), "3cccccc");
+        assert!(resolve("5eeeeee^{tree}").is_err());
+        assert!(resolve("5eeeeee^{commit").is_err());
+    }
+
+    #[test]
+    fn invalid_suffixes_are_rejected() {
+        for revision in [
+            "5eeeeee~1x",
+            "5eeeeee~1é",
+            "5eeeeee^é",
+            "5eeeeee~99999999999999999999",
+        ] {
+            assert_eq!(
+                resolve(revision).unwrap_err().kind(),
+                io::ErrorKind::InvalidInput,
+                "{}",
+                revision
+            );
+        }
+    }
+
+    #[test]
+    fn ranges_list_commits_oldest_first() {
+        let commits = commits();
+
+        assert_eq!(
+            commit_ids(resolve_range(&commits, "3cccccc..5eeeeee").unwrap()),
+            ["4dddddd", "5eeeeee"]
+        );
+        assert_eq!(
+            commit_ids(resolve_range(&commits, "5eeeeee...5eeefff").unwrap()),
+            ["4dddddd", "5eeeeee", "5eeefff"]
+        );
+        assert_eq!(
+            commit_ids(resolve_range(&commits, "3cccccc").unwrap()),
+            ["1aaaaaa", "2bbbbbb", "3cccccc"]
+        );
+    }
+
+    #[test]
+    fn merge_base_is_the_nearest_common_ancestor() {
+        let commits = commits();
+
+        assert_eq!(
+            merge_base(&commits, "4dddddd", "5eeefff").as_deref(),
+            Some("2bbbbbb")
+        );
+        assert_eq!(
+            merge_base(&commits, "5eeeeee", "5eeefff").as_deref(),
+            Some("3cccccc")
+        );
+    }
+
+    #[test]
+    fn path_separator_skips_reflog_selectors() {
+        assert_eq!(path_separator_index("main:src/a.rs"), Some(4));
+        assert_eq!(
+            path_separator_index("main@{2024-01-31 12:00:00}:a"),
+            Some(26)
+        );
+        assert_eq!(path_separator_index(":/message"), None);
+        assert_eq!(path_separator_index("main"), None);
+    }
+}
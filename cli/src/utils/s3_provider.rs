@@ -1,108 +1,350 @@
+use async_trait::async_trait;
 use std::fs::{self, File};
 use std::io::{self, Write};
 use std::path::Path;
 
 use aws_sdk_s3 as s3;
+use aws_sdk_s3::error::{DisplayErrorContext, SdkError};
 use aws_sdk_s3::primitives::ByteStream;
-use aws_sdk_s3::types::{Delete, ObjectIdentifier};
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart, Object};
+use s3::config::Region;
 use s3::operation::get_object::GetObjectOutput;
 use s3::Client;
 
-use super::get_file_paths_recursively;
+use super::constants::{MULTIPART_PART_SIZE, MULTIPART_THRESHOLD};
+use super::fs_provider::read_part_of_file;
+use super::remote::{ref_mismatch, RemoteBackend, RemoteObject};
+use super::types::RemoteConfig;
 
-pub async fn clear_bucket(client: &Client, bucket_name: &str) -> io::Result<()> {
-    let objects = client
-        .list_objects_v2()
-        .bucket(bucket_name)
-        .send()
-        .await
-        .unwrap();
-
-    let mut delete_objects: Vec<ObjectIdentifier> = vec![];
-    for obj in objects.contents() {
-        let obj_id = ObjectIdentifier::builder()
-            .set_key(Some(obj.key().unwrap().to_string()))
-            .build()
-            .unwrap();
-        delete_objects.push(obj_id);
+// Settings missing from the remote config fall back to the usual AWS
+// environment variables and shared config files.
+async fn create_client(remote_config: &RemoteConfig) -> Client {
+    let mut config_loader = aws_config::from_env();
+    if let Some(profile) = &remote_config.profile {
+        config_loader = config_loader.profile_name(profile);
     }
+    if let Some(region) = &remote_config.region {
+        config_loader = config_loader.region(Region::new(region.clone()));
+    }
+    if let Some(endpoint_url) = &remote_config.endpoint_url {
+        config_loader = config_loader.endpoint_url(endpoint_url);
+    }
+    let config = config_loader.load().await;
 
-    client
-        .delete_objects()
-        .bucket(bucket_name)
-        .delete(
-            Delete::builder()
-                .set_objects(Some(delete_objects))
-                .build()
-                .unwrap(),
-        )
-        .send()
-        .await
-        .unwrap();
+    // MinIO and LocalStack serve buckets under the path rather than as
+    // subdomains
+    let s3_config = s3::config::Builder::from(&config)
+        .force_path_style(remote_config.path_style)
+        .build();
+    Client::from_conf(s3_config)
+}
+
+// Timeouts, connection failures, throttling and server errors are reported as
+// transient error kinds so that transfers retry them.
+fn s3_error<E: std::error::Error + Send + Sync + 'static>(
+    e: SdkError<E>,
+    message: String,
+) -> io::Error {
+    let status = e.raw_response().map(|response| response.status().as_u16());
+    let kind = match &e {
+        SdkError::TimeoutError(_) => io::ErrorKind::TimedOut,
+        SdkError::DispatchFailure(_) => io::ErrorKind::ConnectionAborted,
+        SdkError::ResponseError(_) => io::ErrorKind::Interrupted,
+        _ if status.is_some_and(|status| status == 429 || status >= 500) => {
+            io::ErrorKind::Interrupted
+        }
+        _ => io::ErrorKind::Other,
+    };
+    io::Error::new(kind, format!("{}: {}", message, DisplayErrorContext(e)))
+}
+
+// A single listing returns at most 1000 objects, so the continuation tokens
+// are followed until the whole prefix has been listed.
+async fn list_objects(client: &Client, bucket_name: &str, prefix: &str) -> io::Result<Vec<Object>> {
+    let mut objects = Vec::new();
+    let mut continuation_token = None;
+    loop {
+        let output = client
+            .list_objects_v2()
+            .bucket(bucket_name)
+            .prefix(prefix)
+            .set_continuation_token(continuation_token)
+            .send()
+            .await
+            .map_err(|e| s3_error(e, "Failed to list objects".to_owned()))?;
+        objects.extend(output.contents().iter().cloned());
 
-    Ok(())
+        continuation_token = output
+            .next_continuation_token()
+            .map(|token| token.to_owned());
+        if continuation_token.is_none() {
+            return Ok(objects);
+        }
+    }
 }
 
-pub async fn sync_local_history_with_s3(
+async fn get_object(
     client: &Client,
     bucket_name: &str,
-    local_path: &str,
-) -> io::Result<()> {
-    clear_bucket(client, bucket_name).await.unwrap();
+    key: &str,
+    range: Option<String>,
+) -> Result<GetObjectOutput, io::Error> {
+    client
+        .get_object()
+        .bucket(bucket_name)
+        .key(key)
+        .set_range(range)
+        .send()
+        .await
+        .map_err(|e| match &e {
+            SdkError::ServiceError(service_error) if service_error.err().is_no_such_key() => {
+                io::Error::new(io::ErrorKind::NotFound, format!("No object {}", key))
+            }
+            _ => s3_error(e, format!("Failed to get {}", key)),
+        })
+}
 
-    let files = get_file_paths_recursively(Some(Path::new(local_path)), None);
+pub struct S3Backend {
+    client: Client,
+    bucket_name: String,
+}
 
-    for file in files {
-        let body = ByteStream::from_path(&file).await.unwrap();
-        let path = file.strip_prefix(".history/").unwrap();
+impl S3Backend {
+    pub async fn new(remote_config: &RemoteConfig) -> Self {
+        S3Backend {
+            client: create_client(remote_config).await,
+            bucket_name: remote_config.url.clone(),
+        }
+    }
 
-        client
+    async fn put_body(&self, key: &str, body: ByteStream) -> io::Result<()> {
+        self.client
             .put_object()
-            .bucket(bucket_name)
-            .key(".history/".to_owned() + path.to_str().unwrap())
+            .bucket(&self.bucket_name)
+            .key(key)
             .body(body)
             .send()
             .await
-            .unwrap();
+            .map_err(|e| s3_error(e, format!("Failed to upload {}", key)))?;
+
+        Ok(())
     }
 
-    Ok(())
+    // Large files are sent in parts of a fixed size, which S3 assembles once
+    // all of them are uploaded. The upload is aborted on failure so that the
+    // parts do not linger in the bucket.
+    async fn put_multipart(&self, key: &str, source: &Path, size: u64) -> io::Result<()> {
+        let upload_id = self
+            .client
+            .create_multipart_upload()
+            .bucket(&self.bucket_name)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| s3_error(e, format!("Failed to upload {}", key)))?
+            .upload_id()
+            .unwrap_or_default()
+            .to_owned();
+
+        let result = self.put_parts(key, source, size, &upload_id).await;
+        if result.is_err() {
+            let _ = self
+                .client
+                .abort_multipart_upload()
+                .bucket(&self.bucket_name)
+                .key(key)
+                .upload_id(&upload_id)
+                .send()
+                .await;
+        }
+
+        result
+    }
+
+    async fn put_parts(
+        &self,
+        key: &str,
+        source: &Path,
+        size: u64,
+        upload_id: &str,
+    ) -> io::Result<()> {
+        let source_path = source.to_string_lossy();
+        let mut completed_parts = Vec::new();
+        for (index, offset) in (0..size).step_by(MULTIPART_PART_SIZE as usize).enumerate() {
+            let part_number = index as i32 + 1;
+            let length = MULTIPART_PART_SIZE.min(size - offset);
+            let part = read_part_of_file(&source_path, offset, length as usize)?;
+            let output = self
+                .client
+                .upload_part()
+                .bucket(&self.bucket_name)
+                .key(key)
+                .upload_id(upload_id)
+                .part_number(part_number)
+                .body(ByteStream::from(part))
+                .send()
+                .await
+                .map_err(|e| s3_error(e, format!("Failed to upload part of {}", key)))?;
+            completed_parts.push(
+                CompletedPart::builder()
+                    .part_number(part_number)
+                    .set_e_tag(output.e_tag().map(|e_tag| e_tag.to_owned()))
+                    .build(),
+            );
+        }
+
+        self.client
+            .complete_multipart_upload()
+            .bucket(&self.bucket_name)
+            .key(key)
+            .upload_id(upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(completed_parts))
+                    .build(),
+            )
+            .send()
+            .await
+            .map_err(|e| s3_error(e, format!("Failed to upload {}", key)))?;
+
+        Ok(())
+    }
 }
 
-pub async fn create_file_from_s3object(
-    client: &Client,
-    destination: &str,
-    bucket_name: &str,
-    key: &str,
-) -> io::Result<()> {
-    fs::create_dir_all(Path::new(destination).parent().unwrap())?;
+#[async_trait]
+impl RemoteBackend for S3Backend {
+    async fn list(&self, prefix: &str) -> io::Result<Vec<RemoteObject>> {
+        Ok(list_objects(&self.client, &self.bucket_name, prefix)
+            .await?
+            .iter()
+            .filter_map(|obj| {
+                Some(RemoteObject {
+                    key: obj.key()?.to_owned(),
+                    size: obj.size() as u64,
+                    checksum: obj.e_tag().map(|etag| etag.trim_matches('"').to_owned()),
+                })
+            })
+            .collect())
+    }
 
-    let mut file = File::create(destination)?;
+    async fn get(&self, key: &str) -> io::Result<Vec<u8>> {
+        let object = get_object(&self.client, &self.bucket_name, key, None).await?;
+        let bytes = object.body.collect().await.map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Failed to read object: {}", e),
+            )
+        })?;
+
+        Ok(bytes.into_bytes().to_vec())
+    }
 
-    let mut object = get_object(client, bucket_name, key).await?;
+    async fn get_range(&self, key: &str, start: u64, length: u64) -> io::Result<Vec<u8>> {
+        if length == 0 {
+            return Ok(Vec::new());
+        }
+        let range = format!("bytes={}-{}", start, start + length - 1);
+        let object = get_object(&self.client, &self.bucket_name, key, Some(range)).await?;
+        let bytes = object.body.collect().await.map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Failed to read object: {}", e),
+            )
+        })?;
 
-    while let Some(bytes) = object.body.try_next().await? {
-        file.write_all(&bytes)?;
+        Ok(bytes.into_bytes().to_vec())
     }
 
-    Ok(())
-}
+    async fn download(&self, key: &str, destination: &Path) -> io::Result<()> {
+        fs::create_dir_all(destination.parent().unwrap())?;
 
-pub async fn get_object(
-    client: &Client,
-    bucket_name: &str,
-    key: &str,
-) -> Result<GetObjectOutput, io::Error> {
-    client
-        .get_object()
-        .bucket(bucket_name)
-        .key(key)
-        .send()
-        .await
-        .map_err(|e| {
+        let mut file = File::create(destination)?;
+
+        let mut object = get_object(&self.client, &self.bucket_name, key, None).await?;
+
+        while let Some(bytes) = object.body.try_next().await? {
+            file.write_all(&bytes)?;
+        }
+
+        Ok(())
+    }
+
+    async fn put(&self, key: &str, source: &Path) -> io::Result<()> {
+        let size = fs::metadata(source)?.len();
+        if size > MULTIPART_THRESHOLD {
+            return self.put_multipart(key, source, size).await;
+        }
+
+        let body = ByteStream::from_path(source).await.map_err(|e| {
             io::Error::new(
                 io::ErrorKind::InvalidData,
-                format!("Failed to get object: {}", e),
+                format!("Failed to read {}: {}", source.display(), e),
             )
-        })
+        })?;
+        self.put_body(key, body).await
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        self.client
+            .delete_object()
+            .bucket(&self.bucket_name)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| s3_error(e, format!("Failed to delete {}", key)))?;
+
+        Ok(())
+    }
+
+    // The write is conditional on the ETag of the ref that was read, or on the
+    // ref not existing, so S3 rejects it if another push got in between.
+    async fn update_ref(
+        &self,
+        key: &str,
+        expected_contents: Option<&[u8]>,
+        contents: &[u8],
+    ) -> io::Result<()> {
+        let condition = match expected_contents {
+            Some(expected_contents) => {
+                let object = match get_object(&self.client, &self.bucket_name, key, None).await {
+                    Ok(object) => object,
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(ref_mismatch(key)),
+                    Err(e) => return Err(e),
+                };
+                let e_tag = object.e_tag().unwrap_or_default().to_owned();
+                let bytes = object.body.collect().await.map_err(|e| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Failed to read object: {}", e),
+                    )
+                })?;
+                if bytes.into_bytes() != expected_contents {
+                    return Err(ref_mismatch(key));
+                }
+                ("if-match", e_tag)
+            }
+            None => ("if-none-match", "*".to_owned()),
+        };
+
+        self.client
+            .put_object()
+            .bucket(&self.bucket_name)
+            .key(key)
+            .body(ByteStream::from(contents.to_vec()))
+            .customize()
+            .mutate_request(move |request| {
+                request
+                    .headers_mut()
+                    .insert(condition.0, condition.1.clone());
+            })
+            .send()
+            .await
+            .map_err(|e| match e.raw_response().map(|r| r.status().as_u16()) {
+                // 409 is returned when a concurrent conditional write wins
+                Some(409) | Some(412) => ref_mismatch(key),
+                _ => s3_error(e, format!("Failed to update {}", key)),
+            })?;
+
+        Ok(())
+    }
 }
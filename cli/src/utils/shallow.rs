@@ -0,0 +1,437 @@
+use futures_util::{stream, StreamExt, TryStreamExt};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use super::chunks::object_path;
+use super::constants::{
+    COMMIT_METADATA_RELATIVE_PATH, DATA_RELATIVE_PATH, FILES_HISTORY_DIR, HEADS_REFS_PREFIX,
+    HEAD_FILE_PATH, HISTORY_PATH_RELATIVE_PATH, LFS_REMOTE_PREFIX, MAIN_COMMITS_METADATA_FILE_PATH,
+    OBJECTS_DIR, REFS_DIR, SHALLOW_FILE_PATH, SYMBOLIC_REF_PREFIX, TAGS_REFS_PREFIX,
+    TRANSFER_CONCURRENCY, VSM_DIR,
+};
+use super::fs_provider::{get_file_paths_recursively, read_part_of_file};
+use super::lfs::{lfs_blob_key, lfs_blob_path, parse_lfs_pointer};
+use super::progress::Progress;
+use super::refs::write_atomically;
+use super::remote::{
+    download_matching, download_refs, read_remote_string, with_retries, RemoteBackend,
+};
+use super::types::{Commit, CommitMetadata};
+use super::{commits_metadata, list_commits, write_to_commit_metadata_file};
+
+// Commits whose parents were left out of a shallow clone, one per line.
+pub fn read_shallow_commits() -> io::Result<HashSet<String>> {
+    match fs::read_to_string(SHALLOW_FILE_PATH) {
+        Ok(shallow_string) => Ok(shallow_string.lines().map(|line| line.to_owned()).collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(HashSet::new()),
+        Err(e) => Err(e),
+    }
+}
+
+pub fn is_shallow() -> bool {
+    Path::new(SHALLOW_FILE_PATH).exists()
+}
+
+// Records the commits of the local history whose parents are missing, the
+// repository being complete again once there are none.
+pub fn update_shallow_commits() -> io::Result<()> {
+    let commits = list_commits(MAIN_COMMITS_METADATA_FILE_PATH)?;
+    let commit_ids: HashSet<&str> = commits
+        .iter()
+        .map(|commit| commit.commit_id.as_str())
+        .collect();
+    let shallow_commits: Vec<&str> = commits
+        .iter()
+        .filter(|commit| {
+            commit
+                .parents
+                .iter()
+                .any(|parent| !commit_ids.contains(parent.as_str()))
+        })
+        .map(|commit| commit.commit_id.as_str())
+        .collect();
+
+    if shallow_commits.is_empty() {
+        return match fs::remove_file(SHALLOW_FILE_PATH) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        };
+    }
+    write_atomically(SHALLOW_FILE_PATH, shallow_commits.join("\n").as_bytes())
+}
+
+// Commits reachable from the tips within depth commits, the tips being at
+// depth 1. The walk stops at the known commits, which are not selected.
+pub fn select_commits(
+    commits: &[Commit],
+    tips: &[String],
+    depth: Option<usize>,
+    known: &HashSet<String>,
+) -> HashSet<String> {
+    let commits_by_id: HashMap<&str, &Commit> = commits
+        .iter()
+        .map(|commit| (commit.commit_id.as_str(), commit))
+        .collect();
+    let mut selected = HashSet::new();
+    // Walked breadth first, so that every commit is reached first from its
+    // nearest tip
+    let mut pending: VecDeque<(String, usize)> = tips.iter().map(|tip| (tip.clone(), 1)).collect();
+    while let Some((commit_id, commit_depth)) = pending.pop_front() {
+        if known.contains(&commit_id) || depth.is_some_and(|depth| commit_depth > depth) {
+            continue;
+        }
+        let commit = match commits_by_id.get(commit_id.as_str()) {
+            Some(commit) => commit,
+            None => continue,
+        };
+        if !selected.insert(commit_id) {
+            continue;
+        }
+        pending.extend(
+            commit
+                .parents
+                .iter()
+                .map(|parent| (parent.clone(), commit_depth + 1)),
+        );
+    }
+
+    selected
+}
+
+// Path inside destination_dir of a key of the remote ".history".
+fn destination_path(destination_dir: &str, key: &str) -> String {
+    destination_dir.to_owned() + key.strip_prefix(VSM_DIR).unwrap()
+}
+
+// Sorted ranges, overlapping and adjacent ones being merged.
+fn merge_ranges(mut ranges: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    ranges.sort();
+    let mut merged_ranges: Vec<(u64, u64)> = Vec::new();
+    for (start, end) in ranges {
+        match merged_ranges.last_mut() {
+            Some((_, last_end)) if start <= *last_end => *last_end = (*last_end).max(end),
+            _ => merged_ranges.push((start, end)),
+        }
+    }
+
+    merged_ranges
+}
+
+// Writes the given ranges of a remote data file at the same offsets of
+// destination, which gets the length of the remote file, the rest of it being
+// left empty.
+async fn download_data_ranges(
+    remote: &dyn RemoteBackend,
+    key: &str,
+    destination: &str,
+    ranges: &[(u64, u64)],
+    data_len: u64,
+    progress: &Progress,
+) -> io::Result<()> {
+    let mut file = File::create(destination)?;
+    for &(start, end) in ranges {
+        let contents = with_retries(|| remote.get_range(key, start, end - start)).await?;
+        if contents.len() as u64 != end - start {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("{} is shorter than its metadata says", key),
+            ));
+        }
+        file.seek(SeekFrom::Start(start))?;
+        file.write_all(&contents)?;
+        progress.advance(end - start);
+    }
+
+    file.set_len(data_len)
+}
+
+// Downloads into destination_dir the part of the remote history reachable
+// from the given branches, or from all of them when none are given: the
+// commits within depth of their heads, stopping at the known ones, and only
+// the file history entries, chunks and LFS blobs those commits use. Unshallow
+// walks past the known commits, down to the parents a shallow history lacks. Chunks and LFS
+// blobs already in the local history are not downloaded again. The commits
+// list is cut down to the downloaded commits.
+pub async fn download_partial_history(
+    remote: &dyn RemoteBackend,
+    destination_dir: &str,
+    branches: &[String],
+    depth: Option<usize>,
+    known: &HashSet<String>,
+    unshallow: bool,
+    fetch_lfs: bool,
+) -> io::Result<()> {
+    let heads_dir = VSM_DIR.to_owned() + "/" + HEADS_REFS_PREFIX;
+
+    // The refs and the commits list come first, as they tell which commits
+    // are needed
+    let objects = download_refs(remote, destination_dir, |key| {
+        match key.strip_prefix(&heads_dir) {
+            Some(branch) => branches.is_empty() || branches.iter().any(|b| b == branch),
+            None => true,
+        }
+    })
+    .await?;
+    download_matching(remote, destination_dir, &objects, |key| {
+        !key.starts_with(LFS_REMOTE_PREFIX)
+            && !Path::new(key).starts_with(FILES_HISTORY_DIR)
+            && !Path::new(key).starts_with(OBJECTS_DIR)
+            && !Path::new(key).starts_with(REFS_DIR)
+            && key != HEAD_FILE_PATH
+    })
+    .await?;
+    let commits_path = destination_path(destination_dir, MAIN_COMMITS_METADATA_FILE_PATH);
+    if !Path::new(&commits_path).exists() {
+        return Ok(());
+    }
+    let commits = list_commits(&commits_path)?;
+
+    let fetched_heads_dir = destination_dir.to_owned() + "/" + HEADS_REFS_PREFIX;
+    let mut tips = Vec::new();
+    for ref_path in get_file_paths_recursively(Some(Path::new(&fetched_heads_dir)), None) {
+        tips.push(fs::read_to_string(ref_path)?.trim().to_owned());
+    }
+    let selected = if unshallow {
+        select_commits(&commits, &tips, depth, &HashSet::new())
+            .into_iter()
+            .filter(|commit_id| !known.contains(commit_id))
+            .collect()
+    } else {
+        select_commits(&commits, &tips, depth, known)
+    };
+
+    // Tags are kept only when their commit is part of the history
+    let fetched_tags_dir = destination_dir.to_owned() + "/" + TAGS_REFS_PREFIX;
+    for ref_path in get_file_paths_recursively(Some(Path::new(&fetched_tags_dir)), None) {
+        let commit_id = fs::read_to_string(&ref_path)?.trim().to_owned();
+        if !selected.contains(&commit_id) && !known.contains(&commit_id) {
+            fs::remove_file(ref_path)?;
+        }
+    }
+
+    // The metadata of every file tells which of them exist in the selected
+    // commits, and which chunks and LFS blobs they need
+    let metadata_suffix = COMMIT_METADATA_RELATIVE_PATH;
+    download_matching(remote, destination_dir, &objects, |key| {
+        Path::new(key).starts_with(FILES_HISTORY_DIR) && key.ends_with(metadata_suffix)
+    })
+    .await?;
+    let mut history_dirs = HashSet::new();
+    let mut data_downloads = Vec::new();
+    let mut chunk_keys = HashSet::new();
+    let mut lfs_entries = Vec::new();
+    for object in &objects {
+        let history_dir = match object.key.strip_suffix(metadata_suffix) {
+            Some(history_dir) if Path::new(history_dir).starts_with(FILES_HISTORY_DIR) => {
+                history_dir
+            }
+            _ => continue,
+        };
+        let destination_history_dir = destination_path(destination_dir, history_dir);
+        let metadata = commits_metadata(&destination_history_dir)?;
+        if !metadata
+            .iter()
+            .any(|entry| selected.contains(&entry.commit_id))
+        {
+            fs::remove_dir_all(destination_history_dir)?;
+            continue;
+        }
+
+        // Only the contents of the selected entries are downloaded, where the
+        // remote data file keeps them. The known entries are kept in the
+        // metadata, their contents being filled in from the local history
+        // when imported.
+        let data_len = metadata
+            .iter()
+            .filter(|entry| entry.chunks.is_empty())
+            .map(|entry| entry.pointer_to_data + entry.size)
+            .max()
+            .unwrap_or(0);
+        let metadata: Vec<CommitMetadata> = metadata
+            .into_iter()
+            .filter(|entry| selected.contains(&entry.commit_id) || known.contains(&entry.commit_id))
+            .collect();
+        let mut ranges = Vec::new();
+        for entry in metadata
+            .iter()
+            .filter(|entry| selected.contains(&entry.commit_id))
+        {
+            if entry.chunks.is_empty() && entry.size > 0 {
+                ranges.push((entry.pointer_to_data, entry.pointer_to_data + entry.size));
+            }
+            chunk_keys.extend(
+                entry
+                    .chunks
+                    .iter()
+                    .map(|object_id| object_path(object_id))
+                    .filter(|key| !Path::new(key).exists()),
+            );
+            if entry.lfs {
+                lfs_entries.push((destination_history_dir.clone(), entry.clone()));
+            }
+        }
+        write_to_commit_metadata_file(&destination_history_dir, metadata)?;
+
+        history_dirs.insert(history_dir);
+        data_downloads.push((
+            history_dir.to_owned() + DATA_RELATIVE_PATH,
+            destination_history_dir + DATA_RELATIVE_PATH,
+            merge_ranges(ranges),
+            data_len,
+        ));
+    }
+
+    let progress = Progress::new(
+        "Downloading",
+        data_downloads
+            .iter()
+            .map(|(_, _, ranges, _)| ranges.len())
+            .sum(),
+        data_downloads
+            .iter()
+            .flat_map(|(_, _, ranges, _)| ranges)
+            .map(|(start, end)| end - start)
+            .sum(),
+    );
+    stream::iter(&data_downloads)
+        .map(|(key, destination, ranges, data_len)| {
+            download_data_ranges(remote, key, destination, ranges, *data_len, &progress)
+        })
+        .buffer_unordered(TRANSFER_CONCURRENCY)
+        .try_collect::<Vec<_>>()
+        .await?;
+    progress.finish();
+
+    download_matching(remote, destination_dir, &objects, |key| {
+        chunk_keys.contains(key)
+            || key
+                .strip_suffix(HISTORY_PATH_RELATIVE_PATH)
+                .is_some_and(|history_dir| history_dirs.contains(history_dir))
+    })
+    .await?;
+
+    if fetch_lfs {
+        let mut blob_keys = HashSet::new();
+        for (history_dir, entry) in lfs_entries {
+            let pointer_contents = read_part_of_file(
+                &(history_dir + DATA_RELATIVE_PATH),
+                entry.pointer_to_data,
+                entry.size as usize,
+            )?;
+            if let Some(pointer) = parse_lfs_pointer(&pointer_contents) {
+                if !Path::new(&lfs_blob_path(&pointer.oid)).exists() {
+                    blob_keys.insert(lfs_blob_key(&pointer.oid));
+                }
+            }
+        }
+        download_matching(remote, destination_dir, &objects, |key| {
+            blob_keys.contains(key)
+        })
+        .await?;
+    }
+
+    let commits: Vec<&Commit> = commits
+        .iter()
+        .filter(|commit| selected.contains(&commit.commit_id))
+        .collect();
+    write_atomically(&commits_path, serde_json::to_string(&commits)?.as_bytes())
+}
+
+// The branch the remote HEAD points at, which single-branch clones follow
+// when no branch is given.
+pub async fn remote_head_branch(remote: &dyn RemoteBackend) -> io::Result<Option<String>> {
+    let head = match with_retries(|| read_remote_string(remote, HEAD_FILE_PATH)).await {
+        Ok(head) => head,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    Ok(head
+        .trim()
+        .strip_prefix(SYMBOLIC_REF_PREFIX)
+        .and_then(|ref_name| ref_name.strip_prefix(HEADS_REFS_PREFIX))
+        .map(|branch| branch.to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a <- b <- c <- e, the merge of d, itself a child of a.
+    fn commits() -> Vec<Commit> {
+        [
+            ("a", vec![]),
+            ("b", vec!["a"]),
+            ("c", vec!["b"]),
+            ("d", vec!["a"]),
+            ("e", vec!["c", "d"]),
+        ]
+        .into_iter()
+        .map(|(commit_id, parents)| Commit {
+            date: String::new(),
+            description: String::new(),
+            commit_id: commit_id.to_owned(),
+            parents: parents
+                .into_iter()
+                .map(|parent| parent.to_owned())
+                .collect(),
+            author: String::new(),
+        })
+        .collect()
+    }
+
+    fn select(tips: &[&str], depth: Option<usize>, known: &[&str]) -> Vec<String> {
+        let tips: Vec<String> = tips.iter().map(|tip| tip.to_string()).collect();
+        let known = known
+            .iter()
+            .map(|commit_id| commit_id.to_string())
+            .collect();
+        let mut selected: Vec<String> = select_commits(&commits(), &tips, depth, &known)
+            .into_iter()
+            .collect();
+        selected.sort();
+        selected
+    }
+
+    #[test]
+    fn without_depth_every_ancestor_is_selected() {
+        assert_eq!(select(&["e"], None, &[]), ["a", "b", "c", "d", "e"]);
+        assert_eq!(select(&["c"], None, &[]), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn depth_counts_commits_from_the_tips() {
+        assert_eq!(select(&["e"], Some(1), &[]), ["e"]);
+        assert_eq!(select(&["e"], Some(2), &[]), ["c", "d", "e"]);
+        assert_eq!(select(&["c", "d"], Some(1), &[]), ["c", "d"]);
+    }
+
+    #[test]
+    fn depth_counts_from_the_nearest_tip() {
+        assert_eq!(select(&["e", "c"], Some(2), &[]), ["b", "c", "d", "e"]);
+        assert_eq!(select(&["c", "e"], Some(2), &[]), ["b", "c", "d", "e"]);
+    }
+
+    #[test]
+    fn known_commits_stop_the_walk() {
+        assert_eq!(select(&["e"], None, &["c"]), ["a", "d", "e"]);
+        assert_eq!(select(&["e"], None, &["c", "a"]), ["d", "e"]);
+        assert!(select(&["e"], None, &["e"]).is_empty());
+    }
+
+    #[test]
+    fn missing_commits_are_skipped() {
+        assert_eq!(select(&["x", "b"], None, &[]), ["a", "b"]);
+    }
+
+    #[test]
+    fn ranges_are_sorted_and_merged() {
+        assert_eq!(
+            merge_ranges(vec![(10, 20), (0, 5), (5, 8), (15, 30), (40, 50)]),
+            [(0, 8), (10, 30), (40, 50)]
+        );
+        assert!(merge_ranges(Vec::new()).is_empty());
+    }
+}
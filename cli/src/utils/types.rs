@@ -1,12 +1,18 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct CommitMetadata {
     pub date: String,
     pub description: String,
     pub commit_id: String,
-    pub pointer_to_data: i32,
-    pub size: i32,
+    pub pointer_to_data: u64,
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub chunks: Vec<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub lfs: bool,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -14,4 +20,168 @@ pub struct Commit {
     pub date: String,
     pub description: String,
     pub commit_id: String,
+    #[serde(default)]
+    pub parents: Vec<String>,
+    #[serde(default)]
+    pub author: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct RemoteConfig {
+    // S3 bucket name, "file://" URL or path of a directory
+    #[serde(alias = "bucket")]
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endpoint_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub path_style: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub encrypted: bool,
+    // Without a key file, the key is derived from the passphrase
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_file: Option<String>,
+    // Branches fetched from the remote, all of them when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub branches: Vec<String>,
+    // Cloned with --no-lfs, LFS blobs then only come from lfs fetch
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub no_lfs: bool,
+}
+
+// Stored in the clear at the root of an encrypted remote: how the key is
+// derived, and a value encrypted with it to tell whether it is the right one.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct EncryptionParams {
+    pub algorithm: String,
+    pub kdf: String,
+    pub salt: String,
+    pub iterations: u32,
+    pub check: String,
+}
+
+// Remotes by name, along with the remote each branch pushes to and pulls
+// from by default.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct RemotesConfig {
+    #[serde(default)]
+    pub remotes: BTreeMap<String, RemoteConfig>,
+    #[serde(default)]
+    pub branch_remotes: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ReflogEntry {
+    pub old_commit_id: Option<String>,
+    pub new_commit_id: String,
+    pub date: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LfsPointer {
+    pub oid: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResetMode {
+    Soft,
+    Mixed,
+    Hard,
+}
+
+#[derive(Debug, Clone)]
+pub struct TreeEntry {
+    pub history_dir: String,
+    pub metadata: CommitMetadata,
+}
+
+pub type Tree = BTreeMap<PathBuf, TreeEntry>;
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RevertState {
+    pub commit_id: String,
+    pub message: String,
+    pub conflicts: Vec<PathBuf>,
+}
+
+// Settings of a clone in progress, kept so that it can be resumed. Shallow
+// clones only download the last depth commits of the branches.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct CloneState {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub depth: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    #[serde(default)]
+    pub single_branch: bool,
+}
+
+// A merge stopped on conflicts, commit_id being the merged commit.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct MergeState {
+    pub commit_id: String,
+    pub message: String,
+    pub conflicts: Vec<PathBuf>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SequencerOperation {
+    CherryPick,
+    Rebase,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TodoAction {
+    Pick,
+    Reword,
+    Edit,
+    Squash,
+    Fixup,
+    Drop,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct TodoItem {
+    pub action: TodoAction,
+    pub commit_id: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct SequencerState {
+    pub operation: SequencerOperation,
+    pub original_head: String,
+    #[serde(default)]
+    pub current: Option<TodoItem>,
+    pub todo: Vec<TodoItem>,
+    pub record_origin: bool,
+    pub conflicts: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BisectMark {
+    Good,
+    Bad,
+    Skip,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct BisectState {
+    pub original_head: String,
+    pub original_branch: Option<String>,
+    pub bad: Option<String>,
+    pub good: Vec<String>,
+    pub skipped: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BlameLine {
+    pub commit_id: String,
+    pub contents: String,
 }
@@ -0,0 +1,63 @@
+// Finds the first bad commit with bisect run, while the test command leaves
+// build outputs behind.
+
+mod common;
+
+use common::{commit, init_repository, read, rgit, rgit_output, TempDir};
+use std::fs;
+
+#[test]
+fn bisect_run_keeps_untracked_build_outputs() {
+    let temp_dir = TempDir::new();
+    let repository = init_repository(&temp_dir, "repository");
+    for value in 1..=6 {
+        commit(&repository, "value", &format!("{}\n", value), "next value");
+        if value == 2 {
+            fs::create_dir_all(repository.join("src")).unwrap();
+            commit(&repository, "src/lib.txt", "lib\n", "add lib");
+        }
+    }
+    let commit_ids: Vec<String> = rgit(&repository, &["commits"])
+        .lines()
+        .map(|line| line.split(' ').nth(2).unwrap().to_owned())
+        .collect();
+    fs::create_dir_all(repository.join("target")).unwrap();
+    fs::write(repository.join("target/out"), "stale\n").unwrap();
+
+    rgit(&repository, &["bisect", "start", "HEAD", &commit_ids[0]]);
+    let output = rgit(
+        &repository,
+        &[
+            "bisect",
+            "run",
+            "sh",
+            "-c",
+            "echo x > build.log; mkdir -p target; echo x > target/out; test $(cat value) -lt 4",
+        ],
+    );
+    // value 4 is the fifth commit, after the one adding src/lib.txt
+    assert!(output.contains(&format!("{} is the first bad commit", commit_ids[4])));
+    assert_eq!(read(&repository, "build.log"), "x\n");
+    assert_eq!(read(&repository, "target/out"), "x\n");
+
+    rgit(&repository, &["bisect", "reset"]);
+    assert_eq!(read(&repository, "value"), "6\n");
+    assert_eq!(read(&repository, "src/lib.txt"), "lib\n");
+    assert_eq!(read(&repository, "build.log"), "x\n");
+}
+
+#[test]
+fn bisect_refuses_changes_to_tracked_files() {
+    let temp_dir = TempDir::new();
+    let repository = init_repository(&temp_dir, "repository");
+    for value in 1..=5 {
+        commit(&repository, "value", &format!("{}\n", value), "next value");
+    }
+
+    rgit(&repository, &["bisect", "start", "HEAD", "HEAD~4"]);
+    fs::write(repository.join("value"), "changed\n").unwrap();
+    let output = rgit_output(&repository, &["bisect", "good"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Commit or reset the local changes"));
+    assert_eq!(read(&repository, "value"), "changed\n");
+}
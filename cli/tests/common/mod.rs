@@ -0,0 +1,73 @@
+// Helpers driving the binary in temporary repositories, shared by the
+// integration tests.
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+// A fresh directory, removed once the test is done.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "rgit-test-{}-{}",
+            std::process::id(),
+            NEXT_DIR.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+pub fn rgit_output(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_vms-cli"))
+        .args(args)
+        .current_dir(dir)
+        .env("RUST_BACKTRACE", "0")
+        .output()
+        .unwrap()
+}
+
+pub fn rgit(dir: &Path, args: &[&str]) -> String {
+    let output = rgit_output(dir, args);
+    assert!(
+        output.status.success(),
+        "rgit {} failed: {}",
+        args.join(" "),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+pub fn commit(dir: &Path, file: &str, contents: &str, message: &str) {
+    fs::write(dir.join(file), contents).unwrap();
+    rgit(dir, &["commit", "-d", message]);
+}
+
+pub fn read(dir: &Path, file: &str) -> String {
+    fs::read_to_string(dir.join(file)).unwrap()
+}
+
+// A new repository in a directory of temp_dir.
+pub fn init_repository(temp_dir: &TempDir, name: &str) -> PathBuf {
+    let repository = temp_dir.path().join(name);
+    fs::create_dir_all(&repository).unwrap();
+    rgit(&repository, &["init"]);
+    repository
+}
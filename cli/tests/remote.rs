@@ -0,0 +1,196 @@
+// Clones, pushes and pulls through a directory remote, driving the binary
+// the way a user would.
+
+mod common;
+
+use common::{commit, init_repository, read, rgit, rgit_output, TempDir};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// A repository with one commit, pushed to a new directory remote.
+fn pushed_repository(temp_dir: &TempDir) -> (PathBuf, String) {
+    let repository = init_repository(temp_dir, "a");
+    let remote = temp_dir.path().join("remote");
+    fs::create_dir_all(&remote).unwrap();
+    let remote = remote.to_str().unwrap().to_owned();
+
+    commit(&repository, "f.txt", "one\n", "first");
+    rgit(&repository, &["remote", "add", "origin", &remote]);
+    rgit(&repository, &["push", "-u", "origin"]);
+
+    (repository, remote)
+}
+
+fn clone(temp_dir: &TempDir, name: &str, remote: &str) -> PathBuf {
+    let repository = temp_dir.path().join(name);
+    fs::create_dir_all(&repository).unwrap();
+    rgit(&repository, &["clone", "-n", remote]);
+    repository
+}
+
+fn copy_dir(source: &Path, destination: &Path) {
+    fs::create_dir_all(destination).unwrap();
+    for entry in fs::read_dir(source).unwrap() {
+        let entry = entry.unwrap();
+        let destination = destination.join(entry.file_name());
+        if entry.path().is_dir() {
+            copy_dir(&entry.path(), &destination);
+        } else {
+            fs::copy(entry.path(), destination).unwrap();
+        }
+    }
+}
+
+#[test]
+fn clone_push_fetch_and_pull_round_trip() {
+    let temp_dir = TempDir::new();
+    let (a, remote) = pushed_repository(&temp_dir);
+
+    let b = clone(&temp_dir, "b", &remote);
+    assert_eq!(read(&b, "f.txt"), "one\n");
+
+    commit(&a, "f.txt", "one\ntwo\n", "second");
+    rgit(&a, &["push"]);
+    rgit(&b, &["fetch"]);
+    assert_eq!(read(&b, "f.txt"), "one\n");
+    rgit(&b, &["pull"]);
+    assert_eq!(read(&b, "f.txt"), "one\ntwo\n");
+
+    commit(&b, "g.txt", "from b\n", "third");
+    rgit(&b, &["push"]);
+    rgit(&a, &["pull"]);
+    assert_eq!(read(&a, "g.txt"), "from b\n");
+    assert_eq!(read(&a, "f.txt"), "one\ntwo\n");
+
+    let c = clone(&temp_dir, "c", &remote);
+    assert_eq!(read(&c, "f.txt"), "one\ntwo\n");
+    assert_eq!(read(&c, "g.txt"), "from b\n");
+    assert_eq!(rgit(&c, &["commits"]).lines().count(), 3);
+}
+
+#[test]
+fn push_behind_the_remote_is_rejected() {
+    let temp_dir = TempDir::new();
+    let (a, remote) = pushed_repository(&temp_dir);
+    let b = clone(&temp_dir, "b", &remote);
+
+    commit(&a, "f.txt", "from a\n", "from a");
+    rgit(&a, &["push"]);
+    commit(&b, "g.txt", "from b\n", "from b");
+    let output = rgit_output(&b, &["push"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("pull the changes first"));
+
+    // The rejected push left the remote as a pushed it
+    let c = clone(&temp_dir, "c", &remote);
+    assert_eq!(read(&c, "f.txt"), "from a\n");
+    assert!(!c.join("g.txt").exists());
+
+    rgit(&b, &["pull"]);
+    rgit(&b, &["push"]);
+    let d = clone(&temp_dir, "d", &remote);
+    assert_eq!(read(&d, "f.txt"), "from a\n");
+    assert_eq!(read(&d, "g.txt"), "from b\n");
+}
+
+#[test]
+fn interrupted_push_leaves_a_consistent_remote() {
+    let temp_dir = TempDir::new();
+    let (a, remote) = pushed_repository(&temp_dir);
+    let snapshot = temp_dir.path().join("snapshot");
+    copy_dir(Path::new(&remote), &snapshot);
+
+    // The push stops once the data files are uploaded: everything else is
+    // put back as it was
+    commit(&a, "f.txt", "one\ntwo\n", "second");
+    rgit(&a, &["push"]);
+    let remote_history = Path::new(&remote).join(".history");
+    fs::remove_dir_all(&remote_history).unwrap();
+    copy_dir(&snapshot.join(".history"), &remote_history);
+    for entry in fs::read_dir(remote_history.join("files")).unwrap() {
+        let history_dir = entry.unwrap().path();
+        let pushed_data = a
+            .join(".history/files")
+            .join(history_dir.file_name().unwrap())
+            .join("data.bin");
+        fs::copy(pushed_data, history_dir.join("data.bin")).unwrap();
+    }
+
+    let b = clone(&temp_dir, "b", &remote);
+    assert_eq!(read(&b, "f.txt"), "one\n");
+    assert_eq!(rgit(&b, &["commits"]).lines().count(), 1);
+
+    // Someone else pushes on top of the interrupted push
+    commit(&b, "g.txt", "from b\n", "from b");
+    rgit(&b, &["push"]);
+    let c = clone(&temp_dir, "c", &remote);
+    assert_eq!(read(&c, "f.txt"), "one\n");
+    assert_eq!(read(&c, "g.txt"), "from b\n");
+
+    // The interrupted commit can still be pushed once merged
+    rgit(&a, &["pull"]);
+    rgit(&a, &["push"]);
+    let d = clone(&temp_dir, "d", &remote);
+    assert_eq!(read(&d, "f.txt"), "one\ntwo\n");
+    assert_eq!(read(&d, "g.txt"), "from b\n");
+    assert_eq!(rgit(&d, &["commits"]).lines().count(), 4);
+    assert_eq!(rgit(&d, &["cat-file", "HEAD^1:f.txt"]), "one\ntwo\n");
+    assert_eq!(rgit(&d, &["cat-file", "HEAD^2:f.txt"]), "one\n");
+}
+
+#[test]
+fn shallow_clone_deepens_with_unshallow() {
+    let temp_dir = TempDir::new();
+    let (a, remote) = pushed_repository(&temp_dir);
+    commit(&a, "f.txt", "one\ntwo\n", "second");
+    commit(&a, "f.txt", "one\ntwo\nthree\n", "third");
+    rgit(&a, &["push"]);
+
+    let b = temp_dir.path().join("b");
+    fs::create_dir_all(&b).unwrap();
+    rgit(&b, &["clone", "-n", &remote, "--depth", "1"]);
+    assert_eq!(read(&b, "f.txt"), "one\ntwo\nthree\n");
+    assert_eq!(rgit(&b, &["commits"]).lines().count(), 1);
+    assert!(!rgit_output(&b, &["push"]).status.success());
+
+    rgit(&b, &["fetch", "--unshallow"]);
+    assert_eq!(rgit(&b, &["commits"]).lines().count(), 3);
+    assert_eq!(rgit(&b, &["cat-file", "HEAD~2:f.txt"]), "one\n");
+    commit(&b, "g.txt", "from b\n", "fourth");
+    rgit(&b, &["push"]);
+    rgit(&a, &["pull"]);
+    assert_eq!(read(&a, "g.txt"), "from b\n");
+}
+
+#[test]
+fn single_branch_clone_pushes_without_the_other_branches() {
+    let temp_dir = TempDir::new();
+    let (a, remote) = pushed_repository(&temp_dir);
+    rgit(&a, &["branch", "other"]);
+    rgit(&a, &["checkout", "other"]);
+    commit(&a, "other.txt", "other\n", "on other");
+    rgit(&a, &["push", "-u", "origin"]);
+    rgit(&a, &["checkout", "main"]);
+
+    let b = temp_dir.path().join("b");
+    fs::create_dir_all(&b).unwrap();
+    rgit(
+        &b,
+        &["clone", "-n", &remote, "-b", "main", "--single-branch"],
+    );
+    assert_eq!(rgit(&b, &["commits"]).lines().count(), 1);
+    commit(&b, "f.txt", "one\nfrom b\n", "from b");
+    rgit(&b, &["push"]);
+    rgit(&b, &["fetch"]);
+    commit(&b, "f.txt", "one\nfrom b\nagain\n", "again");
+    rgit(&b, &["push"]);
+
+    // The branch b does not fetch is still complete on the remote
+    let c = clone(&temp_dir, "c", &remote);
+    assert_eq!(read(&c, "f.txt"), "one\nfrom b\nagain\n");
+    rgit(&c, &["checkout", "other"]);
+    assert_eq!(read(&c, "other.txt"), "other\n");
+    assert_eq!(read(&c, "f.txt"), "one\n");
+    rgit(&a, &["pull"]);
+    assert_eq!(read(&a, "f.txt"), "one\nfrom b\nagain\n");
+}
@@ -0,0 +1,71 @@
+// Moves the current branch with the three reset modes.
+
+mod common;
+
+use common::{commit, init_repository, read, rgit, rgit_output, TempDir};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Two commits of f.txt, with an uncommitted change on top.
+fn repository_with_changes(temp_dir: &TempDir) -> PathBuf {
+    let repository = init_repository(temp_dir, "repository");
+    commit(&repository, "f.txt", "one\n", "first");
+    commit(&repository, "f.txt", "two\n", "second");
+    fs::write(repository.join("f.txt"), "changed\n").unwrap();
+    repository
+}
+
+fn head_contents(repository: &Path) -> String {
+    rgit(repository, &["cat-file", "HEAD:f.txt"])
+}
+
+#[test]
+fn soft_reset_only_moves_head() {
+    let temp_dir = TempDir::new();
+    let repository = repository_with_changes(&temp_dir);
+
+    rgit(&repository, &["reset", "HEAD~1", "--soft"]);
+    assert_eq!(head_contents(&repository), "one\n");
+    assert_eq!(read(&repository, "f.txt"), "changed\n");
+}
+
+#[test]
+fn mixed_reset_is_the_default_and_behaves_like_soft() {
+    let temp_dir = TempDir::new();
+    let repository = repository_with_changes(&temp_dir);
+
+    rgit(&repository, &["reset", "HEAD~1", "--mixed"]);
+    assert_eq!(head_contents(&repository), "one\n");
+    assert_eq!(read(&repository, "f.txt"), "changed\n");
+
+    rgit(&repository, &["reset", "HEAD@{1}"]);
+    assert_eq!(head_contents(&repository), "two\n");
+    assert_eq!(read(&repository, "f.txt"), "changed\n");
+}
+
+#[test]
+fn hard_reset_overwrites_the_working_tree() {
+    let temp_dir = TempDir::new();
+    let repository = repository_with_changes(&temp_dir);
+
+    rgit(&repository, &["reset", "HEAD~1", "--hard"]);
+    assert_eq!(head_contents(&repository), "one\n");
+    assert_eq!(read(&repository, "f.txt"), "one\n");
+}
+
+#[test]
+fn reset_modes_are_exclusive() {
+    let temp_dir = TempDir::new();
+    let repository = repository_with_changes(&temp_dir);
+
+    for modes in [
+        ["--soft", "--mixed"],
+        ["--soft", "--hard"],
+        ["--mixed", "--hard"],
+    ] {
+        let output = rgit_output(&repository, &["reset", "HEAD~1", modes[0], modes[1]]);
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("cannot be used with"));
+    }
+    assert_eq!(head_contents(&repository), "two\n");
+}
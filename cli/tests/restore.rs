@@ -0,0 +1,36 @@
+// Brings files of the working tree back from a commit.
+
+mod common;
+
+use common::{commit, init_repository, read, rgit, rgit_output, TempDir};
+use std::fs;
+
+#[test]
+fn restore_brings_back_only_the_given_paths() {
+    let temp_dir = TempDir::new();
+    let repository = init_repository(&temp_dir, "repository");
+    fs::write(repository.join("g.txt"), "g one\n").unwrap();
+    commit(&repository, "f.txt", "f one\n", "first");
+    fs::write(repository.join("g.txt"), "g two\n").unwrap();
+    commit(&repository, "f.txt", "f two\n", "second");
+    fs::write(repository.join("f.txt"), "changed\n").unwrap();
+
+    rgit(&repository, &["restore", "f.txt"]);
+    assert_eq!(read(&repository, "f.txt"), "f two\n");
+    rgit(&repository, &["restore", "-s", "HEAD~1", "g.txt"]);
+    assert_eq!(read(&repository, "g.txt"), "g one\n");
+    assert_eq!(read(&repository, "f.txt"), "f two\n");
+}
+
+#[test]
+fn restore_staged_reports_that_there_is_no_index() {
+    let temp_dir = TempDir::new();
+    let repository = init_repository(&temp_dir, "repository");
+    commit(&repository, "f.txt", "one\n", "first");
+    fs::write(repository.join("f.txt"), "changed\n").unwrap();
+
+    let output = rgit_output(&repository, &["restore", "--staged", "f.txt"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("There is no staging index"));
+    assert_eq!(read(&repository, "f.txt"), "changed\n");
+}
@@ -0,0 +1,105 @@
+// Reverts commits, resolving or abandoning conflicting reverts.
+
+mod common;
+
+use common::{commit, init_repository, read, rgit, rgit_output, TempDir};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// f.txt changed twice: the second change conflicts with reverting the first.
+fn repository_with_history(temp_dir: &TempDir) -> PathBuf {
+    let repository = init_repository(temp_dir, "repository");
+    commit(&repository, "f.txt", "one\ntwo\nthree\n", "first");
+    commit(&repository, "f.txt", "one\nTWO\nthree\n", "second");
+    commit(&repository, "f.txt", "one\nTWO!\nthree\n", "third");
+    repository
+}
+
+// Revert messages span several lines, only the first one starts with the date.
+fn commit_count(repository: &Path) -> usize {
+    rgit(repository, &["commits"])
+        .lines()
+        .filter(|line| line.starts_with(|c: char| c.is_ascii_digit()))
+        .count()
+}
+
+#[test]
+fn revert_commits_the_inverse_changes() {
+    let temp_dir = TempDir::new();
+    let repository = init_repository(&temp_dir, "repository");
+    commit(&repository, "f.txt", "one\ntwo\nthree\n", "first");
+    commit(&repository, "f.txt", "one\ntwo\nTHREE\n", "second");
+    commit(&repository, "g.txt", "added\n", "add g");
+    commit(&repository, "f.txt", "ONE\ntwo\nTHREE\n", "fourth");
+
+    rgit(&repository, &["revert", "HEAD~2"]);
+    assert_eq!(read(&repository, "f.txt"), "ONE\ntwo\nthree\n");
+    assert_eq!(
+        rgit(&repository, &["cat-file", "HEAD:f.txt"]),
+        "ONE\ntwo\nthree\n"
+    );
+    assert!(rgit(&repository, &["show", "HEAD"]).contains("Revert \"second\""));
+    assert_eq!(commit_count(&repository), 5);
+
+    // Reverting the commit adding a file removes it
+    rgit(&repository, &["revert", "HEAD~2"]);
+    assert!(!repository.join("g.txt").exists());
+    assert!(!rgit_output(&repository, &["cat-file", "HEAD:g.txt"])
+        .status
+        .success());
+    assert_eq!(commit_count(&repository), 6);
+}
+
+#[test]
+fn conflicting_revert_continues_once_resolved() {
+    let temp_dir = TempDir::new();
+    let repository = repository_with_history(&temp_dir);
+
+    let output = rgit(&repository, &["revert", "HEAD~1"]);
+    assert!(output.contains("CONFLICT: f.txt"));
+    assert!(read(&repository, "f.txt").contains("<<<<<<<"));
+    assert_eq!(commit_count(&repository), 3);
+
+    // Other commands wait for the revert to finish
+    assert!(!rgit_output(&repository, &["revert", "HEAD"])
+        .status
+        .success());
+
+    let output = rgit_output(&repository, &["revert", "--continue"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("still has conflict markers"));
+    assert_eq!(commit_count(&repository), 3);
+
+    fs::write(repository.join("f.txt"), "one\ntwo!\nthree\n").unwrap();
+    rgit(&repository, &["revert", "--continue"]);
+    assert_eq!(commit_count(&repository), 4);
+    assert_eq!(
+        rgit(&repository, &["cat-file", "HEAD:f.txt"]),
+        "one\ntwo!\nthree\n"
+    );
+    assert!(rgit(&repository, &["show", "HEAD"]).contains("Revert \"second\""));
+    assert!(!rgit_output(&repository, &["revert", "--continue"])
+        .status
+        .success());
+}
+
+#[test]
+fn conflicting_revert_aborts_back_to_head() {
+    let temp_dir = TempDir::new();
+    let repository = repository_with_history(&temp_dir);
+
+    rgit(&repository, &["revert", "HEAD~1"]);
+    assert!(read(&repository, "f.txt").contains("<<<<<<<"));
+
+    rgit(&repository, &["revert", "--abort"]);
+    assert_eq!(read(&repository, "f.txt"), "one\nTWO!\nthree\n");
+    assert_eq!(commit_count(&repository), 3);
+    assert!(!rgit_output(&repository, &["revert", "--abort"])
+        .status
+        .success());
+
+    // Nothing is left in progress
+    rgit(&repository, &["revert", "HEAD"]);
+    assert_eq!(read(&repository, "f.txt"), "one\nTWO\nthree\n");
+    assert_eq!(commit_count(&repository), 4);
+}